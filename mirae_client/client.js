@@ -1,6 +1,6 @@
 // all the potential types of Packets we can expect.
-const PacketTypes = Object.freeze({ "Text": 0, "Display": 1, "Init": 2, "Err": 3, "Img": 4, "StaticDisplay": 5 })
-const MAX_PACKET_TYPE_LEN = "StaticDisplay".length
+const PacketTypes = Object.freeze({ "Text": 0, "Display": 1, "Init": 2, "Err": 3, "Img": 4, "StaticDisplay": 5, "Chat": 6, "DisplayDelta": 7, "StaticDisplayDelta": 8 })
+const MAX_PACKET_TYPE_LEN = "StaticDisplayDelta".length
 const MAX_PACKET_SIZE_LEN = 5
 const MAX_TEXTAREA_SIZE = 25000
 const mob_imgs = 'mob_images_to_load'
@@ -9,6 +9,11 @@ const id_to_block = 'block_img_id_to_img'
 const path_to_blocks = 'resources/blocks/'
 const path_to_mob_map = 'resources/mobs/map_img/'
 const path_to_mob_full = 'resources/mobs/full_img/'
+const reconnect_token = 'reconnect_token'
+// asked for in the handshake, servers that don't know it fall back to the packet format
+const JSON_PROTOCOL = 'mirae.json.v1'
+const RECONNECT_DELAY = 2000
+const MAX_RECONNECT_TRIES = 15
 
 let socket = null
 let canvas = null
@@ -20,6 +25,13 @@ let textbox = null
 let initData = null
 let imgData = null
 let buffer = null
+let wsUrl = null
+let reconnectToken = null
+let reconnectTries = 0
+let lastSent = []
+// the last full images, deltas from the server are applied to these
+let lastDisplay = null
+let lastStaticDisplay = null
 
 class Player {
     constructor(ID, x, y) {
@@ -71,6 +83,18 @@ class Uint8Iter {
         this.index++
         return val
     }
+
+    pop16() {
+        let val = this.data.getUint16(this.index, false)
+        this.index += 2
+        return val
+    }
+
+    pop32() {
+        let val = this.data.getUint32(this.index, false)
+        this.index += 4
+        return val
+    }
 }
 
 /** custom error thrown when packet is incomplete (needs more bytes) */
@@ -164,23 +188,68 @@ function getPacket(msg) {
 }
 
 /**
- * does the action required by the packet
+ * turns a packet from the legacy format into the same event the JSON format would send
  * @param {Packet} pkt - packet of information recieved from server
+ * @returns {object} - event, with a "type" like the JSON format's
  */
-function handlePacket(pkt) {
+function packetToEvent(pkt) {
     let dec = new TextDecoder()
     if (pkt.packetType === PacketTypes.Text) {
-        let decoded = dec.decode(pkt.content)
-        displayString(decoded)
+        return { type: 'text', text: dec.decode(pkt.content) }
+    } else if (pkt.packetType === PacketTypes.Chat) {
+        return { type: 'chat', text: dec.decode(pkt.content) }
+    } else if (pkt.packetType === PacketTypes.Err) {
+        return { type: 'error', message: dec.decode(pkt.content) }
+    } else if (pkt.packetType === PacketTypes.Img) {
+        return { type: 'image', name: dec.decode(pkt.content) }
+    } else if (pkt.packetType === PacketTypes.Init) {
+        return { type: 'init', data: JSON.parse(dec.decode(pkt.content)) }
     } else if (pkt.packetType === PacketTypes.Display) {
+        return { type: 'display', image: readImg(new Uint8Iter(new DataView(pkt.content.buffer))) }
+    } else if (pkt.packetType === PacketTypes.StaticDisplay) {
+        return { type: 'static_display', image: readImg(new Uint8Iter(new DataView(pkt.content.buffer))) }
+    } else if (pkt.packetType === PacketTypes.DisplayDelta) {
+        return { type: 'display_delta', delta: readDelta(new Uint8Iter(new DataView(pkt.content.buffer))) }
+    } else if (pkt.packetType === PacketTypes.StaticDisplayDelta) {
+        return { type: 'static_display_delta', delta: readDelta(new Uint8Iter(new DataView(pkt.content.buffer))) }
+    }
+    throw new PacketBroken()
+}
+
+/**
+ * does the action required by the event
+ * @param {object} event - event recieved from server
+ */
+function handleEvent(event) {
+    if (event.type === 'text' || event.type === 'chat') {
+        displayString(event.text)
+    } else if (event.type === 'display') {
         if (initData === null) {
             throw new InitDataNotInitialized()
         }
+        lastDisplay = event.image
         ct.clearRect(0, 0, canvas.width, canvas.height)
-        displayImg(new Uint8Iter(new DataView(pkt.content.buffer)), canvas, ct)
-    } else if (pkt.packetType === PacketTypes.Init) {
-        initData = JSON.parse(dec.decode(pkt.content))
+        displayImg(lastDisplay, canvas, ct)
+    } else if (event.type === 'display_delta') {
+        if (initData === null) {
+            throw new InitDataNotInitialized()
+        }
+        if (!applyDelta(lastDisplay, event.delta)) {
+            sendCommand(["disp", "full"])
+            return
+        }
+        ct.clearRect(0, 0, canvas.width, canvas.height)
+        displayImg(lastDisplay, canvas, ct)
+    } else if (event.type === 'init') {
+        initData = event.data
+        // image ids might mean something else now
+        lastDisplay = null
+        lastStaticDisplay = null
         console.log(initData)
+        if (initData[reconnect_token] !== undefined) {
+            reconnectToken = initData[reconnect_token]
+            reconnectTries = 0
+        }
         imgData = {}
         for (var entity in initData[id_to_mob]) {
             let name = initData[id_to_mob][entity]
@@ -204,12 +273,12 @@ function handlePacket(pkt) {
             imgData[name] = new Image()
             imgData[name].src = path_to_blocks + name
         }
-    } else if (pkt.packetType === PacketTypes.Err) {
-        let decoded = dec.decode(pkt.content)
-        let err = "ERROR: " + decoded + '\n'
-        displayString(err)
-    } else if (pkt.packetType === PacketTypes.Img) {
-        let imgname = dec.decode(pkt.content)
+        // the server keeps the map up to date from here on
+        sendCommand(["disp", "full"])
+    } else if (event.type === 'error') {
+        displayString("ERROR: " + event.message + '\n')
+    } else if (event.type === 'image') {
+        let imgname = event.name
         l_ct.clearRect(0, 0, l_canvas.width, l_canvas.height)
         if (imgname == "none") {
             return;
@@ -221,12 +290,23 @@ function handlePacket(pkt) {
             let width = imgData[imgname].width * l_canvas.height / imgData[imgname].height;
             l_ct.drawImage(imgData[imgname], (l_canvas.width - width)/2, 0, width, l_canvas.height);
         }        
-    } else if (pkt.packetType === PacketTypes.StaticDisplay) {
+    } else if (event.type === 'static_display') {
+        if (initData === null) {
+            throw new InitDataNotInitialized()
+        }
+        lastStaticDisplay = event.image
+        l_ct.clearRect(0, 0, l_canvas.width, l_canvas.height)
+        displayImg(lastStaticDisplay, l_canvas, l_ct)
+    } else if (event.type === 'static_display_delta') {
         if (initData === null) {
             throw new InitDataNotInitialized()
         }
+        if (!applyDelta(lastStaticDisplay, event.delta)) {
+            sendCommand(["map", "full"])
+            return
+        }
         l_ct.clearRect(0, 0, l_canvas.width, l_canvas.height)
-        displayImg(new Uint8Iter(new DataView(pkt.content.buffer)), l_canvas, l_ct)
+        displayImg(lastStaticDisplay, l_canvas, l_ct)
     }
 }
 
@@ -243,19 +323,20 @@ function displayString(string) {
 }
 
 /**
- * display image on the webpage
+ * reads an image in the legacy packet format
  * @param {Uint8Iter} data
+ * @returns {object} - image, in the shape displayImg takes
  */
-function displayImg(data, canvas, ct) {
+function readImg(data) {
     let width = data.pop()
     let height = data.pop()
-    let numPlayers = data.pop()
+    let numPlayers = data.pop16()
     let resolution_is_1 = data.pop()
     let numElems = width * height
 
     let players = []
     for (let i = 0; i < numPlayers; i++) {
-        let player = new Player(data.pop(), data.pop(), data.pop())
+        let player = new Player(data.pop32(), data.pop(), data.pop())
         players.push(player)
     }
 
@@ -274,6 +355,130 @@ function displayImg(data, canvas, ct) {
             entities.push(data.pop())
         }
     }
+    return { width: width, height: height, players: players, blocks: blocks, block_img_ids: block_img_ids, entities: entities }
+}
+
+/**
+ * reads what changed in an image, in the legacy packet format
+ * @param {Uint8Iter} data
+ * @returns {object} - delta, in the shape applyDelta takes
+ */
+function readDelta(data) {
+    let width = data.pop()
+    let height = data.pop()
+    let numPlayers = data.pop16()
+    let resolution_is_1 = data.pop()
+
+    let players = []
+    for (let i = 0; i < numPlayers; i++) {
+        players.push(new Player(data.pop32(), data.pop(), data.pop()))
+    }
+
+    let runs = []
+    let numRuns = data.pop16()
+    for (let i = 0; i < numRuns; i++) {
+        let start = data.pop16()
+        let len = data.pop16()
+        let run = { start: start, blocks: [], block_img_ids: [], entities: [] }
+        for (let j = 0; j < len; j++) {
+            run.blocks.push([data.pop(), data.pop(), data.pop()])
+        }
+        if (resolution_is_1 == 1) {
+            for (let j = 0; j < len; j++) {
+                run.block_img_ids.push(data.pop())
+            }
+            for (let j = 0; j < len; j++) {
+                run.entities.push(data.pop())
+            }
+        }
+        runs.push(run)
+    }
+    return { width: width, height: height, players: players, runs: runs }
+}
+
+/**
+ * reads what changed in an image from a JSON event
+ * @param {object} delta - delta as the server sends it
+ * @returns {object} - delta, in the shape applyDelta takes
+ */
+function jsonDelta(delta) {
+    return {
+        width: delta.width,
+        height: delta.height,
+        players: delta.players.map(p => new Player(p.number, p.x, p.y)),
+        runs: delta.runs.map(run => ({
+            start: run.start,
+            blocks: colorTriples(run.colors),
+            block_img_ids: run.textures === null ? [] : run.textures,
+            entities: run.entities === null ? [] : run.entities
+        }))
+    }
+}
+
+/**
+ * applies what changed to the last full image
+ * @param {object} img - image from readImg or jsonImg, changed in place
+ * @param {object} delta - delta from readDelta or jsonDelta
+ * @returns {boolean} - false if there's no image it fits, and a full one is needed
+ */
+function applyDelta(img, delta) {
+    if (img === null || img.width !== delta.width || img.height !== delta.height) {
+        return false
+    }
+    img.players = delta.players
+    for (let run of delta.runs) {
+        for (let i = 0; i < run.blocks.length; i++) {
+            img.blocks[run.start + i] = run.blocks[i]
+        }
+        for (let i = 0; i < run.block_img_ids.length; i++) {
+            img.block_img_ids[run.start + i] = run.block_img_ids[i]
+        }
+        for (let i = 0; i < run.entities.length; i++) {
+            img.entities[run.start + i] = run.entities[i]
+        }
+    }
+    return true
+}
+
+/**
+ * @param {Array} colors - r, g, b, r, g, b...
+ * @returns {Array} - [r, g, b] for each square
+ */
+function colorTriples(colors) {
+    let blocks = []
+    for (let i = 0; i + 2 < colors.length; i += 3) {
+        blocks.push([colors[i], colors[i + 1], colors[i + 2]])
+    }
+    return blocks
+}
+
+/**
+ * reads an image from a JSON event
+ * @param {object} image - image as the server sends it
+ * @returns {object} - image, in the shape displayImg takes
+ */
+function jsonImg(image) {
+    return {
+        width: image.width,
+        height: image.height,
+        players: image.players.map(p => new Player(p.number, p.x, p.y)),
+        blocks: colorTriples(image.colors),
+        block_img_ids: image.textures === null ? [] : image.textures,
+        entities: image.entities === null ? [] : image.entities
+    }
+}
+
+/**
+ * display image on the webpage
+ * @param {object} img - image from readImg or jsonImg
+ */
+function displayImg(img, canvas, ct) {
+    let width = img.width
+    let height = img.height
+    let players = img.players
+    let blocks = img.blocks
+    let block_img_ids = img.block_img_ids
+    let entities = img.entities
 
     let blockWidth = Math.round(canvas.width / width)
     let blockHeight = Math.round(canvas.height / height)
@@ -352,26 +557,39 @@ function quoted(str) {
 }
 
 function onTextboxEnter() {
-    let content = textbox.value.trim().split(/[ ,]+/)
-    let send = ""
-    for (i = 0; i < content.length; i++) {
-        if (!isNaN(content[i]) || content[i] == 'true' || content[i] == 'false') {
-            send += content[i]
-        } else {
-            send += "\"" + content[i] + "\""
+    let content = textbox.value.trim()
+    if (content != "") {
+        let args = []
+        for (let word of content.split(/[ ,]+/)) {
+            if (!isNaN(word)) {
+                args.push(Number(word))
+            } else if (word == 'true' || word == 'false') {
+                args.push(word == 'true')
+            } else {
+                args.push(word)
+            }
         }
-        send += " "
+        lastSent = args
     }
-    send = send.trim()
-    if (send != "") {
-        socket.send("[" + send + "]")
-        lastSent = send
-    } else {
-        socket.send("[" + lastSent + "]")
+    if (lastSent.length > 0) {
+        sendCommand(lastSent)
     }
     textbox.value = ""
 }
 
+/**
+ * send a game command in whichever format the server agreed to
+ * @param {Array} args - words and numbers of the command
+ */
+function sendCommand(args) {
+    if (socket.protocol === JSON_PROTOCOL) {
+        socket.send(JSON.stringify({ type: 'action', args: args }))
+        return
+    }
+    let send = args.map(arg => typeof arg === 'string' ? "\"" + arg + "\"" : "" + arg)
+    socket.send("[" + send.join(" ") + "]")
+}
+
 function concatTypedArrays(a, b) {
     var c = new (a.constructor)(a.length + b.length);
     c.set(a, 0);
@@ -379,10 +597,81 @@ function concatTypedArrays(a, b) {
     return c;
 }
 
+/**
+ * connect to the server, taking over our last player if we have a token for it
+ */
+function connect() {
+    let url = wsUrl
+    if (reconnectToken !== null) {
+        url += "/?resume=" + reconnectToken
+    }
+    socket = new WebSocket(url, [JSON_PROTOCOL])
+    buffer = new Uint8Array(0)
+    socket.binaryType = 'arraybuffer';
+    socket.onmessage = function (evt) {
+        if (typeof evt.data === 'string') {
+            let event = JSON.parse(evt.data)
+            if (event.image !== undefined) {
+                event.image = jsonImg(event.image)
+            }
+            if (event.delta !== undefined) {
+                event.delta = jsonDelta(event.delta)
+            }
+            try {
+                handleEvent(event)
+            } catch (err) {
+                if (!(err instanceof InitDataNotInitialized)) {
+                    throw err
+                }
+            }
+            return
+        }
+        let data = new Uint8Array(evt.data)
+        buffer = concatTypedArrays(buffer, data)
+
+        while (true) {
+            try {
+                const { pkt, extra } = getPacket(buffer)
+                handleEvent(packetToEvent(pkt))
+                buffer = extra
+            } catch (err) {
+                if (err instanceof PacketIncomplete) {
+                    break
+                } else if (err instanceof PacketBroken) {
+                    buffer = new Uint8Array(0)
+                    break
+                } else if (err instanceof InitDataNotInitialized) {
+                    buffer = new Uint8Array(0)
+                    break
+                } else {
+                    throw err
+                }
+            }
+        }
+
+    };
+    // only retry if the connection dropped, not if the server closed it on purpose
+    socket.onclose = function (evt) {
+        if (evt.code !== 1006 || reconnectToken === null) {
+            displayString("disconnected from the server\n")
+            return
+        }
+        if (reconnectTries >= MAX_RECONNECT_TRIES) {
+            displayString("couldn't reconnect to the server\n")
+            return
+        }
+        if (reconnectTries == 0) {
+            displayString("lost connection, reconnecting...\n")
+        }
+        reconnectTries++
+        setTimeout(connect, RECONNECT_DELAY)
+    };
+}
+
 $(document).ready(function () {
     (async () => {
         let ws = await fetch('/ws-server-loc').then(function (response) { return response.json() })
-        socket = new WebSocket("ws://" + ws['ip'] + ":" + ws['port'])
+        wsUrl = "ws://" + ws['ip'] + ":" + ws['port']
         canvas = document.getElementById("canvas")
         ct = canvas.getContext("2d");
         ct.imageSmoothingEnabled = false;
@@ -394,7 +683,6 @@ $(document).ready(function () {
         text = document.getElementById("text")
         textbox = document.getElementById("textbox")
         initData = null
-        buffer = new Uint8Array(0)
         text.scrollTop = text.scrollHeight;
 
         textbox.addEventListener('keydown', (e) => {
@@ -403,39 +691,7 @@ $(document).ready(function () {
             }
         });
 
-        setTimeout(function () {
-            // auto display (10fps)
-            window.setInterval(function () {
-                socket.send("[\"disp\"]")
-            }, 100);
-        }, 1000);
-
-        socket.binaryType = 'arraybuffer';
-        socket.onmessage = function (evt) {
-            let data = new Uint8Array(evt.data)
-            buffer = concatTypedArrays(buffer, data)
-
-            while (true) {
-                try {
-                    const { pkt, extra } = getPacket(buffer)
-                    handlePacket(pkt)
-                    buffer = extra
-                } catch (err) {
-                    if (err instanceof PacketIncomplete) {
-                        break
-                    } else if (err instanceof PacketBroken) {
-                        buffer = new Uint8Array(0)
-                        break
-                    } else if (err instanceof InitDataNotInitialized) {
-                        buffer = new Uint8Array(0)
-                        break
-                    } else {
-                        throw err
-                    }
-                }
-            }
-
-        };
+        connect()
     })();
 });
 
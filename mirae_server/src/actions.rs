@@ -1,6 +1,9 @@
 use crate::{
-    combat::{BattleMap, EntityType, ID},
-    display::{Bounds, Image},
+    admin::{self, AdminData},
+    auth::{self, LoginAttempts},
+    chat,
+    combat::{broadcast, combatant, BattleMap, Combatants, EntityType, ID},
+    display::Bounds,
     entity::Entity,
     gamedata::{
         block::Block,
@@ -8,18 +11,17 @@ use crate::{
         mobtemplate::MobTemplate,
     },
     player::Player,
+    player_ids::PlayerIds,
+    player_save_folder, save, sight, trading,
     vector3::Vector3,
     world::World,
-    PLAYER_SAVE_FOLDER,
 };
 use anyhow::{anyhow, Result};
-use fs::File;
 use rand::{thread_rng, Rng};
 use serde_jacl::structs::{Literal, Number};
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     fs,
-    io::Write,
     path::Path,
     sync::{Arc, RwLock},
 };
@@ -39,6 +41,7 @@ pub fn dispatch(data: ActionData) -> Result<()> {
                 "do" => do_ability,
                 "upgrade" => upgrade,
                 "battle" => battle,
+                "join" => join,
                 "map" => map,
                 "pass" => pass,
                 "run" => run,
@@ -48,6 +51,9 @@ pub fn dispatch(data: ActionData) -> Result<()> {
                 "descr" | "describe" => describe,
                 "trade" => trade,
                 "mine" => mine,
+                "say" | "shout" | "whisper" => chat,
+                "mute" | "unmute" => mute,
+                "admin" => admin,
                 _ => return Err(anyhow!("invalid command")),
             };
             func(data)
@@ -56,23 +62,83 @@ pub fn dispatch(data: ActionData) -> Result<()> {
     }
 }
 
-fn get_player_and_opponent<'a>(
-    opponent: ID,
+// borrows everyone in a battle at the same time
+pub fn get_entities<'a>(
+    ids: &Vec<ID>,
     players: &'a mut Vec<Option<Player>>,
-    player_id: usize,
     world: &'a mut World,
-) -> Result<(&'a mut Player, Box<&'a mut dyn Entity>)> {
-    match opponent.enity_type {
-        EntityType::Mob => {
-            let player = get_mut(players, player_id)?;
-            let opponent = world.get_mob_mut(opponent.id)?;
-            Ok((player, Box::new(opponent as &mut dyn Entity)))
+) -> Result<Combatants<'a>> {
+    let mut entities: Combatants<'a> = HashMap::new();
+    let mut mob_ids = Vec::new();
+    for id in ids {
+        if id.enity_type == EntityType::Mob {
+            mob_ids.push(id.id);
         }
-        EntityType::Player => {
-            let (player, opponent) = get_two_mut(player_id, opponent.id, players)?;
-            Ok((player, Box::new(opponent as &mut dyn Entity)))
+    }
+    for (i, player) in players.iter_mut().enumerate() {
+        if ids.contains(&ID::player(i)) {
+            if let Some(player) = player {
+                entities.insert(ID::player(i), player);
+            }
+        }
+    }
+    for mob in world.get_mobs_mut(&mob_ids)? {
+        entities.insert(mob.id(), mob);
+    }
+    if entities.len() != ids.len() {
+        return Err(anyhow!("invalid player id"));
+    }
+    Ok(entities)
+}
+
+// picks an enemy by its number in the 'battle' listing or by its name,
+// falling back to the only enemy if nothing was given
+fn find_target(
+    battle_map: &BattleMap,
+    player_id: ID,
+    target: Option<Literal>,
+    entities: &Combatants,
+) -> Result<ID> {
+    let participants = battle_map.participants(player_id)?;
+    let target = match target {
+        None => return battle_map.default_target(player_id),
+        Some(Literal::Number(Number::Int(i))) => {
+            if i < 1 || i as usize > participants.len() {
+                return Err(anyhow!(format!(
+                    "there's no combatant number {}, see 'battle'",
+                    i
+                )));
+            }
+            participants[i as usize - 1]
+        }
+        Some(Literal::String(name)) => {
+            let matches: Vec<ID> = participants
+                .iter()
+                .filter(|id| entities[id].name() == name)
+                .cloned()
+                .collect();
+            match matches.len() {
+                0 => {
+                    return Err(anyhow!(format!(
+                        "nobody called '{}' is in this battle",
+                        name
+                    )))
+                }
+                1 => matches[0],
+                _ => {
+                    return Err(anyhow!(format!(
+                        "there's more than one '{}', use their number from 'battle'",
+                        name
+                    )))
+                }
+            }
         }
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+    if !battle_map.enemies(player_id)?.contains(&target) {
+        return Err(anyhow!("you can only target your enemies"));
     }
+    Ok(target)
 }
 
 pub fn get_mut(players: &mut Vec<Option<Player>>, player_id: usize) -> Result<&mut Player> {
@@ -133,30 +199,48 @@ pub struct ActionData<'a> {
     pub players: Arc<RwLock<Vec<Option<Player>>>>,
     pub world: Arc<RwLock<World>>,
     pub battle_map: Arc<RwLock<BattleMap>>,
+    pub logins: Arc<RwLock<LoginAttempts>>,
+    pub ids: Arc<RwLock<PlayerIds>>,
     pub g: &'a GameData,
 }
 
 fn run_turn_with(
     battle_map: &mut BattleMap,
     player_id: ID,
+    target: Option<Literal>,
     players: &mut Vec<Option<Player>>,
     world: &mut World,
     g: &GameData,
     func: &dyn Fn(
-        &mut Player,
+        &mut dyn Entity,
         Option<Box<&mut dyn Entity>>,
         &GameData,
         &mut BattleMap,
     ) -> Result<()>,
 ) -> Result<()> {
-    if let Ok(opponent) = battle_map.get_opponent(player_id) {
-        if battle_map.turn(opponent)? {
+    if battle_map.in_battle(player_id) {
+        if !battle_map.turn(player_id)? {
             Err(anyhow!("it's not your turn!"))
+        } else if battle_map.stunned(player_id)? {
+            Err(anyhow!(
+                "you can't do anything while you're stunned or charging, use 'pass'"
+            ))
         } else {
-            let (player, opp) = get_player_and_opponent(opponent, players, player_id.id, world)?;
-            func(player, Some(opp), g, battle_map)?;
-            let (player, opp) = get_player_and_opponent(opponent, players, player_id.id, world)?;
-            battle_map.do_turn(Box::new(player), opp, g)
+            let ids = battle_map.participants(player_id)?;
+            let mut entities = get_entities(&ids, players, world)?;
+            let target = find_target(battle_map, player_id, target, &entities)?;
+            let player = entities
+                .remove(&player_id)
+                .ok_or(anyhow!("you aren't in this battle"))?;
+            let res = func(
+                &mut *player,
+                Some(Box::new(combatant(&mut entities, target)?)),
+                g,
+                battle_map,
+            );
+            entities.insert(player_id, player);
+            res?;
+            battle_map.do_turn(player_id, &mut entities, g)
         }
     } else {
         let player = get_mut(players, player_id.id)?;
@@ -178,6 +262,7 @@ fn use_ability(mut data: ActionData) -> Result<()> {
         }
         _ => return Err(anyhow!(BAD_ARGS)),
     }
+    let target = data.params.pop_front();
     let item_name;
     if let Some(name) = player.equipped().items().next() {
         item_name = name;
@@ -185,7 +270,11 @@ fn use_ability(mut data: ActionData) -> Result<()> {
         return Err(anyhow!("you don't have any item equipped!"));
     }
 
-    let item = &data.g.items[item_name];
+    let item = data
+        .g
+        .items
+        .get(item_name)
+        .ok_or(anyhow!(format!("invalid item name! {:?}", item_name)))?;
     let item_name = item_name.clone();
 
     let ability;
@@ -211,7 +300,7 @@ fn use_ability(mut data: ActionData) -> Result<()> {
         .write()
         .map_err(|_| anyhow!("couldn't lock world"))?;
 
-    let func = |player: &mut Player,
+    let func = |player: &mut dyn Entity,
                 mut opp: Option<Box<&mut dyn Entity>>,
                 g: &GameData,
                 battle_map: &mut BattleMap| {
@@ -227,6 +316,7 @@ fn use_ability(mut data: ActionData) -> Result<()> {
     run_turn_with(
         &mut battle_map,
         player_id,
+        target,
         &mut players,
         &mut world,
         data.g,
@@ -254,7 +344,11 @@ fn do_ability(mut data: ActionData) -> Result<()> {
             Some(Literal::Number(Number::Int(number))),
         ) => {
             item_name = ItemName::checked_from(item, data.g)?;
-            let item = &data.g.items[&item_name];
+            let item = data
+                .g
+                .items
+                .get(&item_name)
+                .ok_or(anyhow!(format!("invalid item name! {:?}", item_name)))?;
 
             if let Some(a) = item.abilities.get(&ability_name) {
                 ability = a.clone();
@@ -284,6 +378,7 @@ fn do_ability(mut data: ActionData) -> Result<()> {
         }
         _ => return Err(anyhow!(BAD_ARGS)),
     };
+    let target = data.params.pop_front();
 
     let mut battle_map = data
         .battle_map
@@ -295,7 +390,7 @@ fn do_ability(mut data: ActionData) -> Result<()> {
         .map_err(|_| anyhow!("couldn't lock world"))?;
     let player_id = ID::player(data.player_id);
 
-    let func = |player: &mut Player,
+    let func = |player: &mut dyn Entity,
                 mut opp: Option<Box<&mut dyn Entity>>,
                 g: &GameData,
                 battle_map: &mut BattleMap|
@@ -318,6 +413,7 @@ fn do_ability(mut data: ActionData) -> Result<()> {
     run_turn_with(
         &mut battle_map,
         player_id,
+        target,
         &mut players,
         &mut world,
         data.g,
@@ -431,7 +527,7 @@ fn step(mut data: ActionData) -> Result<()> {
         .battle_map
         .read()
         .map_err(|_| anyhow!("couldn't lock battle map"))?;
-    if battle_map.get_opponent(ID::player(data.player_id)).is_ok() {
+    if battle_map.in_battle(ID::player(data.player_id)) {
         return Err(anyhow!(
             "you can't move while fighting something, try using \"run\""
         ));
@@ -536,6 +632,14 @@ fn step(mut data: ActionData) -> Result<()> {
     Ok(())
 }
 
+// "disp full" and "map full" resend the whole image instead of what changed
+fn wants_full(params: &VecDeque<Literal>) -> bool {
+    match params.get(1) {
+        Some(Literal::String(s)) => s == "full",
+        _ => false,
+    }
+}
+
 fn map(data: ActionData) -> Result<()> {
     let world = data
         .world
@@ -555,13 +659,24 @@ fn map(data: ActionData) -> Result<()> {
     let max_map_size = 30;
     let min_resolution = 2;
     let resolution = min_resolution.max((world.blocks().dim.x() as usize) / (max_map_size));
-    let img = Image::new(&world, &players, data.g, &bounds, resolution)?;
+    // only what the player has explored shows up
+    let img = sight::look(
+        &world,
+        &mut players,
+        data.g,
+        data.player_id,
+        &bounds,
+        resolution,
+    )?;
     let player = get_mut(&mut players, data.player_id)?;
+    if wants_full(&data.params) {
+        player.forget_displays();
+    }
     player.send_display(img, true);
     Ok(())
 }
 
-const VIEW_DIST: usize = 5;
+pub const VIEW_DIST: usize = 5;
 fn disp(data: ActionData) -> Result<()> {
     let world = data
         .world
@@ -573,8 +688,13 @@ fn disp(data: ActionData) -> Result<()> {
         .map_err(|_| anyhow!("couldn't lock players"))?;
     let posn = *(get(&players, data.player_id)?.loc());
     let bounds = Bounds::get_bounds_centered(posn, VIEW_DIST, world.blocks().dim);
-    let img = Image::new(&world, &players, data.g, &bounds, 1)?;
+    let img = sight::look(&world, &mut players, data.g, data.player_id, &bounds, 1)?;
     let player = get_mut(&mut players, data.player_id)?;
+    if wants_full(&data.params) {
+        player.forget_displays();
+    }
+    // from now on the server sends updates when something in view changes
+    player.view = Some(bounds);
     player.send_display(img, false);
     Ok(())
 }
@@ -610,10 +730,10 @@ fn do_turn_if_in_battle(data: ActionData) -> Result<()> {
         .map_err(|_| anyhow!("couldn't lock world"))?;
 
     let player_id = player.id();
-    if let Ok(opponent) = battle_map.get_opponent(player_id) {
-        let (player, opp) =
-            get_player_and_opponent(opponent, &mut players, player_id.id, &mut world)?;
-        battle_map.do_turn(Box::new(player), opp, data.g)?;
+    if battle_map.in_battle(player_id) {
+        let ids = battle_map.participants(player_id)?;
+        let mut entities = get_entities(&ids, &mut players, &mut world)?;
+        battle_map.do_turn(player_id, &mut entities, data.g)?;
     }
     Ok(())
 }
@@ -718,7 +838,14 @@ fn upgrade(mut data: ActionData) -> Result<()> {
 }
 
 const BATTLE_DIST: f64 = 10.0;
-fn battle(data: ActionData) -> Result<()> {
+fn battle(mut data: ActionData) -> Result<()> {
+    data.params.pop_front(); // ignore the first parameter
+    let free_for_all = match data.params.pop_front() {
+        Some(Literal::String(s)) if s == "all" => true,
+        None => false,
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+
     let mut battle_map = data
         .battle_map
         .write()
@@ -728,13 +855,21 @@ fn battle(data: ActionData) -> Result<()> {
         .write()
         .map_err(|_| anyhow!("couldn't lock players"))?;
 
-    if battle_map.get_opponent(ID::player(data.player_id)).is_ok() {
-        return Err(anyhow!("you are already fighting something"));
+    let player_id = ID::player(data.player_id);
+    if battle_map.in_battle(player_id) {
+        if free_for_all {
+            return Err(anyhow!("you are already fighting something"));
+        }
+        let mut world = data
+            .world
+            .write()
+            .map_err(|_| anyhow!("couldn't lock world"))?;
+        return battle_status(&battle_map, player_id, &mut players, &mut world, data.g);
     }
 
     let battle_dist_sqr = BATTLE_DIST * BATTLE_DIST;
     let player_loc = get(&players, data.player_id)?.loc().clone();
-    let mut opponent_id = None;
+    let mut opponent_ids = Vec::new();
     for i in 0..players.len() {
         let player = &mut players[i];
         if i == data.player_id {
@@ -742,21 +877,171 @@ fn battle(data: ActionData) -> Result<()> {
         }
         if let Some(player) = player.as_mut() {
             if (player.loc().clone() - player_loc).sqr_mag() < battle_dist_sqr
-                && battle_map.get_opponent(ID::player(i)).is_err()
+                && !battle_map.in_battle(ID::player(i))
             {
-                opponent_id = Some(i);
-                break;
+                opponent_ids.push(i);
+                if !free_for_all {
+                    break;
+                }
             }
         }
     }
 
-    if let Some(opp) = opponent_id {
-        let (player, opponent) = get_two_mut(data.player_id, opp, &mut players)?;
-        battle_map.init_battle(Box::new(player), Box::new(opponent), false, data.g)?;
-        Ok(())
+    if opponent_ids.len() == 0 {
+        return Err(anyhow!("no player in range"));
+    }
+
+    let (player, opponent) = get_two_mut(data.player_id, opponent_ids[0], &mut players)?;
+    battle_map.init_battle(Box::new(player), Box::new(opponent), false, data.g)?;
+
+    // everyone else in range joins on a side of their own
+    for i in opponent_ids.into_iter().skip(1) {
+        let opponent = get_mut(&mut players, i)?;
+        battle_map.join_battle(Box::new(opponent), player_id, None)?;
+    }
+    if free_for_all {
+        let ids = battle_map.participants(player_id)?;
+        let names: Vec<String> = ids
+            .iter()
+            .map(|id| get(&players, id.id).map(|player| player.name()))
+            .collect::<Result<_>>()?;
+        for id in &ids {
+            get_mut(&mut players, id.id)?
+                .send_text(format!("free-for-all between {}!\n", names.join(", ")));
+        }
+    }
+    Ok(())
+}
+
+// lists everyone in the player's battle, numbered for picking targets
+fn battle_status(
+    battle_map: &BattleMap,
+    player_id: ID,
+    players: &mut Vec<Option<Player>>,
+    world: &mut World,
+    g: &GameData,
+) -> Result<()> {
+    let ids = battle_map.participants(player_id)?;
+    let enemies = battle_map.enemies(player_id)?;
+    let holder = battle_map.turn_holder(player_id)?;
+    let mut entities = get_entities(&ids, players, world)?;
+    let mut text = String::from("in this battle:\n");
+    for (i, id) in ids.iter().enumerate() {
+        let entity = combatant(&mut entities, *id)?;
+        let relation = if *id == player_id {
+            "you"
+        } else if enemies.contains(id) {
+            "enemy"
+        } else {
+            "ally"
+        };
+        text += &format!(
+            "{}: {} ({}) health {}/{}{}\n",
+            i + 1,
+            entity.name(),
+            relation,
+            entity.stats().health(),
+            entity.stats().get("max_health", g)?,
+            if holder == Some(*id) { " <- turn" } else { "" }
+        );
+    }
+    combatant(&mut entities, player_id)?.send_text(text);
+    Ok(())
+}
+
+// joins a battle going on nearby, either on the side of the named player
+// or against everyone else in it
+fn join(mut data: ActionData) -> Result<()> {
+    data.params.pop_front(); // ignore the first parameter
+    let ally_name = match data.params.pop_front() {
+        Some(Literal::String(s)) => Some(s),
+        None => None,
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+
+    let mut battle_map = data
+        .battle_map
+        .write()
+        .map_err(|_| anyhow!("couldn't lock battle map"))?;
+    let mut players = data
+        .players
+        .write()
+        .map_err(|_| anyhow!("couldn't lock players"))?;
+    let mut world = data
+        .world
+        .write()
+        .map_err(|_| anyhow!("couldn't lock world"))?;
+
+    let player_id = ID::player(data.player_id);
+    if battle_map.in_battle(player_id) {
+        return Err(anyhow!("you are already fighting something"));
+    }
+
+    let battle_dist_sqr = BATTLE_DIST * BATTLE_DIST;
+    let player_loc = get(&players, data.player_id)?.loc().clone();
+    let mut member = None;
+    for i in 0..players.len() {
+        if let Some(other) = &players[i] {
+            if i == data.player_id
+                || !battle_map.in_battle(other.id())
+                || (other.loc().clone() - player_loc).sqr_mag() >= battle_dist_sqr
+            {
+                continue;
+            }
+            match &ally_name {
+                Some(name) if *name != other.name() => {}
+                _ => {
+                    member = Some(other.id());
+                    break;
+                }
+            }
+        }
+    }
+    let named = ally_name.is_some();
+    let member = match (member, ally_name) {
+        (Some(member), _) => member,
+        (None, Some(name)) => {
+            return Err(anyhow!(format!(
+                "'{}' isn't fighting anything near you",
+                name
+            )))
+        }
+        (None, None) => return Err(anyhow!("there's no battle in range")),
+    };
+
+    // with no one named, join the players when they're fighting mobs
+    // and fight everyone otherwise
+    let mob = battle_map
+        .participants(member)?
+        .into_iter()
+        .find(|id| id.enity_type == EntityType::Mob);
+    let ally = if named {
+        Some(member)
+    } else if let Some(mob) = mob {
+        battle_map
+            .enemies(mob)?
+            .into_iter()
+            .find(|id| id.enity_type == EntityType::Player)
     } else {
-        Err(anyhow!("no player in range"))
+        None
+    };
+
+    let player = get_mut(&mut players, data.player_id)?;
+    battle_map.join_battle(Box::new(player), member, ally)?;
+    if let Some(mob) = mob {
+        let img = world.get_mob(mob.id)?.display_img.clone();
+        get_mut(&mut players, data.player_id)?.send_image(img);
     }
+
+    let ids = battle_map.participants(player_id)?;
+    let mut entities = get_entities(&ids, &mut players, &mut world)?;
+    let name = combatant(&mut entities, player_id)?.name();
+    broadcast(
+        &mut entities,
+        player_id,
+        format!("{} joined the battle!\n", name),
+    );
+    Ok(())
 }
 
 fn run(data: ActionData) -> Result<()> {
@@ -772,35 +1057,53 @@ fn run(data: ActionData) -> Result<()> {
     if num_honour >= 20 {
         return Err(anyhow!("You're too honourable to run away sirrr"));
     }
-    let posn = player.return_posn.clone();
-    player.loc_mut().set(posn);
 
     let mut battle_map = data
         .battle_map
         .write()
         .map_err(|_| anyhow!("couldn't lock battle map"))?;
 
-    if let Ok(opponent) = battle_map.get_opponent(ID::player(data.player_id)) {
-        let mut world = data
-            .world
-            .write()
-            .map_err(|_| anyhow!("couldn't lock world"))?;
-        let (player, opp) =
-            get_player_and_opponent(opponent, &mut players, data.player_id, &mut world)?;
-
-        if opp.id().enity_type == EntityType::Player {
-            return Err(anyhow!("you can't run away from players"));
-        }
+    let player_id = ID::player(data.player_id);
+    if !battle_map.in_battle(player_id) {
+        return Err(anyhow!("you aren't fighting anything"));
+    }
+    let enemies = battle_map.enemies(player_id)?;
+    if enemies
+        .iter()
+        .any(|enemy| enemy.enity_type == EntityType::Player)
+    {
+        return Err(anyhow!("you can't run away from players"));
+    }
 
-        if let Ok(x) = opp.run() {
-            player.send_text(format!("{}: \"{}\"\n", opp.name(), x));
-            player.send_image("none".into());
+    let mut world = data
+        .world
+        .write()
+        .map_err(|_| anyhow!("couldn't lock world"))?;
+    let ids = battle_map.participants(player_id)?;
+    let mut entities = get_entities(&ids, &mut players, &mut world)?;
+    for enemy in &enemies {
+        let enemy = combatant(&mut entities, *enemy)?;
+        if let Ok(x) = enemy.run() {
+            let text = format!("{}: \"{}\"\n", enemy.name(), x);
+            combatant(&mut entities, player_id)?.send_text(text);
         }
     }
+    let player = combatant(&mut entities, player_id)?;
+    player.send_image("none".into());
+    let name = player.name();
+    broadcast(&mut entities, player_id, format!("{} ran away!\n", name));
+
+    for id in battle_map.leave_battle(player_id)? {
+        let entity = combatant(&mut entities, id)?;
+        entity.send_text("the battle is over!\n".into());
+        entity.send_image("none".into());
+    }
+    drop(entities);
 
-    battle_map
-        .end_battle(ID::player(data.player_id))
-        .map_err(|_| anyhow!("you aren't fighting anything"))
+    let player = get_mut(&mut players, data.player_id)?;
+    let posn = player.return_posn.clone();
+    player.loc_mut().set(posn);
+    Ok(())
 }
 
 fn pass(data: ActionData) -> Result<()> {
@@ -809,31 +1112,43 @@ fn pass(data: ActionData) -> Result<()> {
             .battle_map
             .write()
             .map_err(|_| anyhow!("couldn't lock battle map"))?;
-        if let Err(_) = battle_map.get_opponent(ID::player(data.player_id)) {
+        if !battle_map.in_battle(ID::player(data.player_id)) {
             return Err(anyhow!("you aren't fighting anything"));
         }
     }
     do_turn_if_in_battle(data)
 }
 
+// passwords that look like numbers are sent as numbers by the client
+pub fn password_param(param: Option<Literal>) -> Result<String> {
+    match param {
+        Some(Literal::String(s)) => Ok(s),
+        Some(Literal::Number(Number::Int(i))) => Ok(i.to_string()),
+        _ => Err(anyhow!(BAD_ARGS)),
+    }
+}
+
 fn account(mut data: ActionData) -> Result<()> {
     data.params.pop_front(); // ignore first argument
 
+    let world = data
+        .world
+        .read()
+        .map_err(|_| anyhow!("couldn't lock world"))?;
     let mut players = data
         .players
         .write()
         .map_err(|_| anyhow!("couldn't lock players"))?;
+    let mut logins = data
+        .logins
+        .write()
+        .map_err(|_| anyhow!("couldn't lock logins"))?;
 
     let player = get_mut(&mut players, data.player_id)?;
 
-    let flag;
-    let name;
-    match (data.params.pop_front(), data.params.pop_front()) {
-        (Some(Literal::String(f)), Some(Literal::String(n))) => {
-            flag = f;
-            name = n;
-        }
-        (None, None) => {
+    let flag = match data.params.pop_front() {
+        Some(Literal::String(f)) => f,
+        None => {
             let username;
             if let Some(uname) = &player.username {
                 username = uname.clone();
@@ -845,23 +1160,68 @@ fn account(mut data: ActionData) -> Result<()> {
             return Ok(());
         }
         _ => return Err(anyhow!(BAD_ARGS)),
+    };
+
+    if flag == "passwd" {
+        let old = password_param(data.params.pop_front())?;
+        let new = password_param(data.params.pop_front())?;
+        let name = player
+            .username
+            .clone()
+            .ok_or(anyhow!("you need to be logged in to change your password"))?;
+        auth::check_password(&new)?;
+        logins.attempt(&name, &old)?;
+        auth::set_password(&name, &new)?;
+        player.send_text(format!("changed the password for '{}'\n", name));
+        return Ok(());
     }
 
-    let save_file = format!("{}/{}", PLAYER_SAVE_FOLDER, name);
+    let name = match data.params.pop_front() {
+        Some(Literal::String(n)) => n,
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+    let password = password_param(data.params.pop_front())?;
+    auth::check_username(&name)?;
+
+    let save_file = format!("{}/{}", player_save_folder(), name);
     match flag.as_str() {
         "create" => {
             if Path::new(&save_file).exists() {
                 return Err(anyhow!("A save file already exists with that username!"));
             }
-            let mut f = File::create(save_file)?;
-            f.write_all(&player.save()?.as_bytes())?;
+            auth::check_password(&password)?;
+            auth::set_password(&name, &password)?;
+            save::write_file(&save_file, player.save()?.as_bytes(), 0)?;
             player.send_text(format!("created account '{}'\n", name));
         }
         "delete" => {
             if !Path::new(&save_file).exists() {
                 return Err(anyhow!("No save file exists with that username!"));
             }
-            fs::remove_file(&save_file)?;
+            if !auth::has_password(&name) {
+                return Err(anyhow!(format!(
+                    "'{}' doesn't have a password yet, ask an admin to set one with 'setpass'",
+                    name
+                )));
+            }
+            logins.attempt(&name, &password)?;
+            for (id, other) in players.iter().enumerate() {
+                if let Some(other) = other {
+                    if id != data.player_id && other.username.as_ref() == Some(&name) {
+                        return Err(anyhow!("someone is logged in to that account right now"));
+                    }
+                }
+            }
+            save::remove_file(&save_file)?;
+            auth::remove_password(&name)?;
+            data.ids
+                .write()
+                .map_err(|_| anyhow!("couldn't lock player ids"))?
+                .remove(&name)?;
+            let player = get_mut(&mut players, data.player_id)?;
+            if player.username.as_ref() == Some(&name) {
+                player.username = None;
+            }
             player.send_text(format!("deleted account '{}'\n", name));
         }
         "login" => {
@@ -884,19 +1244,77 @@ fn account(mut data: ActionData) -> Result<()> {
             let player = get_mut(&mut players, data.player_id)?;
 
             let err = "No save file exists with that username!";
-            player.load(fs::read_to_string(save_file).map_err(|_| anyhow!(err))?)?;
+            let save = fs::read_to_string(save_file).map_err(|_| anyhow!(err))?;
+            // accounts made before passwords existed could be claimed by anyone,
+            // so they stay locked until an operator sets their password
+            if !auth::has_password(&name) {
+                return Err(anyhow!(format!(
+                    "'{}' doesn't have a password yet, ask an admin to set one with 'setpass'",
+                    name
+                )));
+            }
+            logins.attempt(&name, &password)?;
+            player.load(save, &world, data.g)?;
+            player.number = data
+                .ids
+                .write()
+                .map_err(|_| anyhow!("couldn't lock player ids"))?
+                .account(&name)?;
             player.username = Some(name);
             player.send_text(format!("you are now logged in as '{}'\n", player.name()));
         }
         _ => {
             return Err(anyhow!(
-                "expected first arg to be either login, create, or delete"
+                "expected first arg to be either login, create, delete, or passwd"
             ))
         }
     };
     Ok(())
 }
 
+fn admin(mut data: ActionData) -> Result<()> {
+    data.params.pop_front(); // ignore first arg
+
+    let mut battle_map = data
+        .battle_map
+        .write()
+        .map_err(|_| anyhow!("couldn't lock battle map"))?;
+    let mut players = data
+        .players
+        .write()
+        .map_err(|_| anyhow!("couldn't lock players"))?;
+    let mut world = data
+        .world
+        .write()
+        .map_err(|_| anyhow!("couldn't lock world"))?;
+
+    let is_admin = match &get(&players, data.player_id)?.username {
+        Some(name) => auth::is_admin(name)?,
+        None => false,
+    };
+    if !is_admin {
+        return Err(anyhow!("you need to be logged in to an admin account"));
+    }
+    let mut logins = data
+        .logins
+        .write()
+        .map_err(|_| anyhow!("couldn't lock logins"))?;
+    let text = admin::dispatch(AdminData {
+        params: data.params,
+        world: &mut world,
+        players: &mut players,
+        battle_map: &mut battle_map,
+        g: data.g,
+        logins: &mut logins,
+        console: false,
+    })?;
+    // admins can kick themselves
+    if let Ok(player) = get_mut(&mut players, data.player_id) {
+        player.send_text(format!("{}\n", text));
+    }
+    Ok(())
+}
+
 fn scan(data: ActionData) -> Result<()> {
     let start = {
         let players = data
@@ -967,18 +1385,18 @@ fn info(mut data: ActionData) -> Result<()> {
     data.params.pop_front(); // ignore first argument
 
     let type_;
-    let name;
+    let arg;
     match (data.params.pop_front(), data.params.pop_front()) {
-        (Some(Literal::String(t)), Some(Literal::String(n))) => {
-            type_ = t;
-            name = n;
-        }
-        (Some(Literal::String(t)), None) => {
+        (Some(Literal::String(t)), a) => {
             type_ = t;
-            name = "".into();
+            arg = a;
         }
         _ => return Err(anyhow!(BAD_ARGS)),
     }
+    let name = match &arg {
+        Some(Literal::String(n)) => n.clone(),
+        _ => "".into(),
+    };
 
     let mut players = data
         .players
@@ -1000,7 +1418,11 @@ fn info(mut data: ActionData) -> Result<()> {
         }
         "item" => {
             let item_name = ItemName::checked_from(name, data.g)?;
-            let item = &data.g.items[&item_name];
+            let item = data
+                .g
+                .items
+                .get(&item_name)
+                .ok_or(anyhow!(format!("invalid item name! {:?}", item_name)))?;
             player.send_text(format!("{:#?}\n", item));
         }
         "opp" => {
@@ -1008,16 +1430,18 @@ fn info(mut data: ActionData) -> Result<()> {
                 .battle_map
                 .read()
                 .map_err(|_| anyhow!("couldn't lock battle map"))?;
-            if let Ok(opponent) = battle_map.get_opponent(ID::player(data.player_id)) {
+            let player_id = ID::player(data.player_id);
+            if battle_map.in_battle(player_id) {
                 let mut world = data
                     .world
                     .write()
                     .map_err(|_| anyhow!("couldn't lock world"))?;
-                let (player, opp) =
-                    get_player_and_opponent(opponent, &mut players, data.player_id, &mut world)?;
+                let ids = battle_map.participants(player_id)?;
+                let mut entities = get_entities(&ids, &mut players, &mut world)?;
+                let target = find_target(&battle_map, player_id, arg, &entities)?;
 
-                let info = get_info(opp, data.g);
-                player.send_text(info);
+                let info = get_info(Box::new(combatant(&mut entities, target)?), data.g);
+                combatant(&mut entities, player_id)?.send_text(info);
             } else {
                 return Err(anyhow!("you're not fighting anything"));
             }
@@ -1063,7 +1487,11 @@ fn describe(mut data: ActionData) -> Result<()> {
         }
         "item" => {
             let item_name = ItemName::checked_from(name, data.g)?;
-            let item = &data.g.items[&item_name];
+            let item = data
+                .g
+                .items
+                .get(&item_name)
+                .ok_or(anyhow!(format!("invalid item name! {:?}", item_name)))?;
             player.send_text(format!("\"{}\"\n", item.description));
         }
         _ => {
@@ -1075,6 +1503,24 @@ fn describe(mut data: ActionData) -> Result<()> {
     Ok(())
 }
 
+// finds a player by their name or player number
+pub fn find_player(players: &Vec<Option<Player>>, who: Literal) -> Result<usize> {
+    let found = match who {
+        Literal::Number(Number::Int(i)) => players
+            .iter()
+            .flatten()
+            .find(|player| player.number as i64 == i)
+            .map(|player| player.id().id),
+        Literal::String(name) => players
+            .iter()
+            .flatten()
+            .find(|player| player.name() == name)
+            .map(|player| player.id().id),
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+    found.ok_or(anyhow!("there's no player like that online"))
+}
+
 fn trade(mut data: ActionData) -> Result<()> {
     data.params.pop_front(); // ignore first arg
 
@@ -1083,6 +1529,44 @@ fn trade(mut data: ActionData) -> Result<()> {
         .write()
         .map_err(|_| anyhow!("couldn't lock players"))?;
 
+    // trading with other players
+    let subcommand = match data.params.get(0) {
+        Some(Literal::String(s)) => Some(s.clone()),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        data.params.pop_front();
+        let args = (data.params.pop_front(), data.params.pop_front());
+        return match (subcommand.as_str(), args.0, args.1) {
+            ("with", Some(who), None) => {
+                let partner_id = find_player(&players, who)?;
+                let battle_map = data
+                    .battle_map
+                    .read()
+                    .map_err(|_| anyhow!("couldn't lock battle map"))?;
+                trading::start(&mut players, &battle_map, data.player_id, partner_id)
+            }
+            (
+                "offer" | "remove",
+                Some(Literal::String(item)),
+                Some(Literal::Number(Number::Int(n))),
+            ) => {
+                if n < 1 {
+                    return Err(anyhow!("the number of items must be at least one"));
+                }
+                let item = ItemName::checked_from(item, data.g)?;
+                let count = if subcommand == "offer" { n } else { -n };
+                trading::change_offer(&mut players, data.player_id, item, count)
+            }
+            ("accept", None, None) => trading::accept(&mut players, data.player_id),
+            ("cancel", None, None) => trading::cancel(&mut players, data.player_id),
+            _ => Err(anyhow!(BAD_ARGS)),
+        };
+    }
+    if data.params.len() == 0 && get(&players, data.player_id)?.trade.is_some() {
+        return trading::status(&mut players, data.player_id);
+    }
+
     let player = get_mut(&mut players, data.player_id)?;
 
     let world = data
@@ -1138,6 +1622,66 @@ fn trade(mut data: ActionData) -> Result<()> {
     Ok(())
 }
 
+// the client splits what's typed on spaces, so put the words back together
+pub fn message_text(params: &mut VecDeque<Literal>) -> String {
+    let words: Vec<String> = params
+        .drain(..)
+        .map(|word| match word {
+            Literal::String(s) => s,
+            Literal::Number(Number::Int(i)) => i.to_string(),
+            Literal::Number(Number::Flt(f)) => f.to_string(),
+            Literal::Bool(b) => b.to_string(),
+        })
+        .collect();
+    words.join(" ")
+}
+
+fn chat(mut data: ActionData) -> Result<()> {
+    let verb = match data.params.pop_front() {
+        Some(Literal::String(s)) => s,
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+    let mut players = data
+        .players
+        .write()
+        .map_err(|_| anyhow!("couldn't lock players"))?;
+
+    match verb.as_str() {
+        "say" => chat::say(&mut players, data.player_id, message_text(&mut data.params)),
+        "shout" => chat::shout(&mut players, data.player_id, message_text(&mut data.params)),
+        "whisper" => {
+            let to = data.params.pop_front().ok_or(anyhow!(BAD_ARGS))?;
+            let to_id = find_player(&players, to)?;
+            let msg = message_text(&mut data.params);
+            chat::whisper(&mut players, data.player_id, to_id, msg)
+        }
+        _ => Err(anyhow!(BAD_ARGS)),
+    }
+}
+
+fn mute(mut data: ActionData) -> Result<()> {
+    let muted = match data.params.pop_front() {
+        Some(Literal::String(s)) => s == "mute",
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+    let mut players = data
+        .players
+        .write()
+        .map_err(|_| anyhow!("couldn't lock players"))?;
+
+    // players without an account can only be picked out by their number
+    let name = match (data.params.pop_front(), data.params.pop_front()) {
+        (Some(Literal::String(name)), None) => Some(name),
+        (Some(Literal::Number(Number::Int(i))), None) => {
+            let id = find_player(&players, Literal::Number(Number::Int(i)))?;
+            Some(get(&players, id)?.name())
+        }
+        (None, None) if muted => None,
+        _ => return Err(anyhow!(BAD_ARGS)),
+    };
+    chat::mute(&mut players, data.player_id, name, muted)
+}
+
 fn mine(mut data: ActionData) -> Result<()> {
     data.params.pop_front(); // ignore first arg
 
@@ -1188,7 +1732,7 @@ fn mine(mut data: ActionData) -> Result<()> {
         let block = world.get_block_at(data.g, curr)?;
         if let Some(break_into) = &block.break_into {
             let block_id = data.g.get_block_id_by_blockname(break_into)?;
-            world.blocks_mut().set(curr, block_id)?;
+            world.set_block(data.g, curr, block_id)?;
             if let Some(drop) = &block.drop {
                 player.inventory_mut().add(drop.clone(), 1);
                 player.send_text(format!("+1 '{}'\n", drop.0))
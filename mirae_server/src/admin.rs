@@ -0,0 +1,315 @@
+// commands for running a live server, used from the console
+// and by players logged in to admin accounts
+use crate::{
+    actions::{find_player, get, get_entities, get_mut, message_text, password_param},
+    auth::{self, LoginAttempts},
+    combat::{combatant, BattleMap, EntityType, ID},
+    entity::Entity,
+    gamedata::gamedata::{BlockName, GameData, ItemName, MobName, Named},
+    player::Player,
+    player_save_folder,
+    vector3::Vector3,
+    world::World,
+};
+use anyhow::{anyhow, Result};
+use serde_jacl::structs::{Literal, Number};
+use std::{collections::VecDeque, path::Path};
+
+pub const COMMANDS: [&str; 12] = [
+    "kick",
+    "tp",
+    "give",
+    "spawn",
+    "setblock",
+    "broadcast",
+    "heal",
+    "who",
+    "battles",
+    "op",
+    "deop",
+    "setpass",
+];
+
+pub struct AdminData<'a> {
+    pub params: VecDeque<Literal>,
+    pub world: &'a mut World,
+    pub players: &'a mut Vec<Option<Player>>,
+    pub battle_map: &'a mut BattleMap,
+    pub g: &'a GameData,
+    pub logins: &'a mut LoginAttempts,
+    // only the console can change who is an admin
+    pub console: bool,
+}
+
+// returns what to show whoever ran the command
+pub fn dispatch(data: AdminData) -> Result<String> {
+    match data.params.get(0) {
+        Some(Literal::String(s)) => {
+            let func = match s.as_str() {
+                "kick" => kick,
+                "tp" => tp,
+                "give" => give,
+                "spawn" => spawn,
+                "setblock" => setblock,
+                "broadcast" => broadcast,
+                "heal" => heal,
+                "who" => who,
+                "battles" => battles,
+                "op" | "deop" => op,
+                "setpass" => setpass,
+                _ => {
+                    return Err(anyhow!(format!(
+                        "invalid admin command, choose one of the following {:?}",
+                        COMMANDS
+                    )))
+                }
+            };
+            func(data)
+        }
+        _ => Err(anyhow!("expected string as first parameter")),
+    }
+}
+
+fn player_param(
+    players: &Vec<Option<Player>>,
+    param: Option<Literal>,
+    help: &str,
+) -> Result<usize> {
+    match param {
+        Some(who) => find_player(players, who),
+        None => Err(anyhow!(help.to_string())),
+    }
+}
+
+fn posn_params(params: &mut VecDeque<Literal>, help: &str) -> Result<Vector3> {
+    match (params.pop_front(), params.pop_front(), params.pop_front()) {
+        (
+            Some(Literal::Number(Number::Int(x))),
+            Some(Literal::Number(Number::Int(y))),
+            Some(Literal::Number(Number::Int(z))),
+        ) => Ok(Vector3::new(x as isize, y as isize, z as isize)),
+        _ => Err(anyhow!(help.to_string())),
+    }
+}
+
+fn kick(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let help = "\"kick\" <player number | username> [reason]";
+    let id = player_param(data.players, data.params.pop_front(), help)?;
+    let reason = message_text(&mut data.params);
+    let player = get_mut(data.players, id)?;
+    let name = player.name();
+    player.kick(if reason.is_empty() {
+        "you were kicked from the server".into()
+    } else {
+        format!("you were kicked from the server: {}", reason)
+    });
+    Ok(format!("kicked {}", name))
+}
+
+fn tp(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let help = "\"tp\" <player number | username> <x> <y> <z>";
+    let id = player_param(data.players, data.params.pop_front(), help)?;
+    let posn = posn_params(&mut data.params, help)?;
+    let player = get_mut(data.players, id)?;
+    if data.battle_map.in_battle(ID::player(id)) {
+        return Err(anyhow!(format!("{} is in a battle", player.name())));
+    }
+    if !Player::can_stand_at(posn, data.world, data.g) {
+        return Err(anyhow!(format!("nobody can stand at {:?}", posn)));
+    }
+    player.loc_mut().set(posn);
+    player.send_text(format!("you were teleported to {:?}\n", posn));
+    Ok(format!("teleported {} to {:?}", player.name(), posn))
+}
+
+fn give(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let help = "\"give\" <player number | username> <item> <count>";
+    let id = player_param(data.players, data.params.pop_front(), help)?;
+    let (item, count) = match (data.params.pop_front(), data.params.pop_front()) {
+        (Some(Literal::String(item)), Some(Literal::Number(Number::Int(count)))) if count > 0 => {
+            (ItemName::checked_from(item, data.g)?, count as u64)
+        }
+        _ => return Err(anyhow!(help)),
+    };
+    let player = get_mut(data.players, id)?;
+    player.inventory_mut().add(item.clone(), count);
+    player.send_text(format!("+{} '{}'\n", count, item.0));
+    Ok(format!("gave {} {} of '{}'", player.name(), count, item.0))
+}
+
+fn spawn(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let help = "\"spawn\" <mob> <x> <y> <z>";
+    let mob = match data.params.pop_front() {
+        Some(Literal::String(mob)) => MobName::checked_from(mob, data.g)?,
+        _ => return Err(anyhow!(help)),
+    };
+    let posn = posn_params(&mut data.params, help)?;
+    let mob_id = data.g.get_mob_id_by_name(&mob)?;
+    data.world.place_mob(posn, mob_id, data.g)?;
+    Ok(format!("spawned '{}' at {:?}", mob.0, posn))
+}
+
+fn setblock(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let help = "\"setblock\" <block> <x> <y> <z>";
+    let block = match data.params.pop_front() {
+        Some(Literal::String(block)) => BlockName::checked_from(block, data.g)?,
+        _ => return Err(anyhow!(help)),
+    };
+    let posn = posn_params(&mut data.params, help)?;
+    let block_id = data.g.get_block_id_by_blockname(&block)?;
+    data.world.set_block(data.g, posn, block_id)?;
+    Ok(format!("set the block at {:?} to '{}'", posn, block.0))
+}
+
+fn broadcast(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let text = message_text(&mut data.params);
+    if text.is_empty() {
+        return Err(anyhow!("\"broadcast\" <text>"));
+    }
+    let mut count = 0;
+    for player in data.players.iter_mut().flatten() {
+        player.send_chat(format!("[server]: {}\n", text));
+        count += 1;
+    }
+    Ok(format!("sent to {} player(s)", count))
+}
+
+fn heal(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    let help = "\"heal\" <player number | username>";
+    let id = player_param(data.players, data.params.pop_front(), help)?;
+    let player = get_mut(data.players, id)?;
+    player.stats_mut().reset_health(data.g);
+    player.stats_mut().reset_energy(data.g);
+    player.send_text("you were healed\n".into());
+    Ok(format!("healed {}", player.name()))
+}
+
+fn who(data: AdminData) -> Result<String> {
+    let mut lines = Vec::new();
+    for (id, player) in data.players.iter().enumerate() {
+        if let Some(player) = player {
+            lines.push(format!(
+                "{}: {} at {:?}{}{}",
+                player.number,
+                player.name(),
+                player.loc(),
+                if data.battle_map.in_battle(ID::player(id)) {
+                    ", in battle"
+                } else {
+                    ""
+                },
+                if player.disconnected.is_some() {
+                    ", disconnected"
+                } else {
+                    ""
+                }
+            ));
+        }
+    }
+    if lines.len() == 0 {
+        return Ok("nobody is online".into());
+    }
+    Ok(format!("{} online:\n{}", lines.len(), lines.join("\n")))
+}
+
+fn battles(data: AdminData) -> Result<String> {
+    let mut lines = Vec::new();
+    let handles: Vec<_> = data.battle_map.battles().cloned().collect();
+    for (i, handle) in handles.iter().enumerate() {
+        let battle = data.battle_map.data_from_handle(handle)?;
+        let ids = battle.ids().clone();
+        let holder = battle.turn_holder();
+        let mut kinds = Vec::new();
+        for id in &ids {
+            kinds.push(match id.enity_type {
+                EntityType::Player => format!("player {}", get(data.players, id.id)?.number),
+                EntityType::Mob => format!("mob {}", id.id),
+            });
+        }
+        let mut entities = get_entities(&ids, data.players, data.world)?;
+        lines.push(format!("battle {}:", i + 1));
+        for (id, kind) in ids.iter().zip(kinds) {
+            let entity = combatant(&mut entities, *id)?;
+            lines.push(format!(
+                "    {} ({}) side {}, health {}{}",
+                entity.name(),
+                kind,
+                battle.combat_data(*id)?.side,
+                entity.stats().health(),
+                if holder == Some(*id) { " <- turn" } else { "" }
+            ));
+        }
+    }
+    if lines.len() == 0 {
+        return Ok("there are no battles going on".into());
+    }
+    Ok(lines.join("\n"))
+}
+
+fn op(mut data: AdminData) -> Result<String> {
+    let admin = match data.params.pop_front() {
+        Some(Literal::String(s)) => s == "op",
+        _ => return Err(anyhow!("expected string as first parameter")),
+    };
+    if !data.console {
+        return Err(anyhow!(
+            "admins can only be changed from the server console"
+        ));
+    }
+    let name = match data.params.pop_front() {
+        Some(Literal::String(name)) => name,
+        _ => return Err(anyhow!("\"op\" or \"deop\" <username>")),
+    };
+    auth::check_username(&name)?;
+    auth::set_admin(&name, admin)?;
+
+    let text = if admin {
+        "you are now an admin\n"
+    } else {
+        "you are no longer an admin\n"
+    };
+    for player in data.players.iter_mut().flatten() {
+        if player.username.as_ref() == Some(&name) {
+            player.send_text(text.into());
+        }
+    }
+    Ok(format!(
+        "'{}' {}",
+        name,
+        if admin {
+            "is now an admin"
+        } else {
+            "is no longer an admin"
+        }
+    ))
+}
+
+// accounts made before passwords existed can't be logged in to until they're given one here
+fn setpass(mut data: AdminData) -> Result<String> {
+    data.params.pop_front();
+    if !data.console {
+        return Err(anyhow!("passwords can only be set from the server console"));
+    }
+    let help = "\"setpass\" <username> <password>";
+    let name = match data.params.pop_front() {
+        Some(Literal::String(name)) => name,
+        _ => return Err(anyhow!(help)),
+    };
+    let password = password_param(data.params.pop_front()).map_err(|_| anyhow!(help))?;
+    auth::check_username(&name)?;
+    auth::check_password(&password)?;
+    if !Path::new(&format!("{}/{}", player_save_folder(), name)).exists() {
+        return Err(anyhow!(format!("there's no account called '{}'", name)));
+    }
+    auth::set_password(&name, &password)?;
+    // failed attempts with the old password shouldn't keep them out
+    data.logins.succeed(&name);
+    Ok(format!("set the password for '{}'", name))
+}
@@ -0,0 +1,189 @@
+use crate::{admins_file, player_auth_folder, save};
+use anyhow::{anyhow, Result};
+use rand::{thread_rng, Rng};
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+// bcrypt cost, every hash runs on the logic thread so this is kept lower than bcrypt's default
+const HASH_COST: u32 = 10;
+const MIN_PASSWORD_LEN: usize = 6;
+const MAX_NAME_LEN: usize = 32;
+const TOKEN_LEN: usize = 32;
+
+// failed attempts on an account before we start making people wait
+const FREE_ATTEMPTS: u32 = 3;
+const MAX_LOCKOUT: Duration = Duration::from_secs(300);
+
+// usernames are used as file names, so only allow characters that are safe in a path
+pub fn check_username(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(anyhow!(format!(
+            "usernames must be between 1 and {} characters long",
+            MAX_NAME_LEN
+        )));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(anyhow!(
+            "usernames can only contain letters, numbers, '_' and '-'"
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_password(password: &str) -> Result<()> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(anyhow!(format!(
+            "passwords must be at least {} characters long",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(())
+}
+
+// hex string for things like the admin token, that can't be guessed
+pub fn random_token() -> String {
+    let mut rng = thread_rng();
+    (0..TOKEN_LEN)
+        .map(|_| format!("{:x}", rng.gen_range(0, 16)))
+        .collect()
+}
+
+// doesn't stop early, so how long it takes doesn't give away how much of the token was right
+pub fn tokens_match(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn auth_file(name: &str) -> String {
+    format!("{}/{}", player_auth_folder(), name)
+}
+
+pub fn has_password(name: &str) -> bool {
+    Path::new(&auth_file(name)).exists()
+}
+
+pub fn set_password(name: &str, password: &str) -> Result<()> {
+    let hash = bcrypt::hash(password, HASH_COST)?;
+    save::write_file(&auth_file(name), hash.as_bytes(), 0)
+}
+
+pub fn verify_password(name: &str, password: &str) -> Result<bool> {
+    let hash = fs::read_to_string(auth_file(name))?;
+    Ok(bcrypt::verify(password, hash.trim())?)
+}
+
+pub fn remove_password(name: &str) -> Result<()> {
+    if has_password(name) {
+        fs::remove_file(auth_file(name))?;
+    }
+    Ok(())
+}
+
+// accounts that can use admin commands in game, stored one username per line
+fn admins() -> Result<HashSet<String>> {
+    match fs::read_to_string(admins_file()) {
+        Ok(text) => Ok(text
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn is_admin(name: &str) -> Result<bool> {
+    Ok(admins()?.contains(name))
+}
+
+pub fn set_admin(name: &str, admin: bool) -> Result<()> {
+    let mut admins = admins()?;
+    if admin {
+        admins.insert(name.to_string());
+    } else {
+        admins.remove(name);
+    }
+    let mut names: Vec<String> = admins.into_iter().collect();
+    names.sort();
+    let text: String = names.iter().map(|name| format!("{}\n", name)).collect();
+    save::write_file(&admins_file(), text.as_bytes(), 0)
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+// failed password attempts per account, so nobody can guess passwords quickly
+pub struct LoginAttempts {
+    failures: HashMap<String, Failures>,
+}
+
+impl LoginAttempts {
+    pub fn new() -> Self {
+        LoginAttempts {
+            failures: HashMap::new(),
+        }
+    }
+
+    fn lockout(count: u32) -> Duration {
+        if count < FREE_ATTEMPTS {
+            Duration::from_secs(0)
+        } else {
+            let secs = 1u64 << (count - FREE_ATTEMPTS).min(16);
+            Duration::from_secs(secs).min(MAX_LOCKOUT)
+        }
+    }
+
+    pub fn check(&self, name: &str) -> Result<()> {
+        if let Some(failures) = self.failures.get(name) {
+            let lockout = Self::lockout(failures.count);
+            let elapsed = failures.last.elapsed();
+            if elapsed < lockout {
+                return Err(anyhow!(format!(
+                    "too many failed attempts on '{}', try again in {} seconds",
+                    name,
+                    (lockout - elapsed).as_secs() + 1
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn fail(&mut self, name: &str) {
+        let failures = self.failures.entry(name.to_string()).or_insert(Failures {
+            count: 0,
+            last: Instant::now(),
+        });
+        failures.count += 1;
+        failures.last = Instant::now();
+    }
+
+    pub fn succeed(&mut self, name: &str) {
+        self.failures.remove(name);
+    }
+
+    // checks the password for an account, counting the attempt if it's wrong
+    pub fn attempt(&mut self, name: &str, password: &str) -> Result<()> {
+        self.check(name)?;
+        if verify_password(name, password)? {
+            self.succeed(name);
+            Ok(())
+        } else {
+            self.fail(name);
+            Err(anyhow!("wrong password"))
+        }
+    }
+}
@@ -0,0 +1,122 @@
+// messages between players. chat goes out in its own packet type
+// so the client can show it apart from the game's text
+use crate::{
+    actions::{get, get_mut},
+    entity::Entity,
+    player::Player,
+};
+use anyhow::{anyhow, Result};
+
+// how far away players can be and still hear 'say'
+pub const SAY_DIST: f64 = 20.0;
+pub const MAX_MESSAGE_LEN: usize = 256;
+
+fn check_message(msg: &str) -> Result<()> {
+    if msg.trim().is_empty() {
+        return Err(anyhow!("you can't send an empty message"));
+    }
+    let len = msg.chars().count();
+    if len > MAX_MESSAGE_LEN {
+        return Err(anyhow!(format!(
+            "messages can be at most {} characters, yours is {}",
+            MAX_MESSAGE_LEN, len
+        )));
+    }
+    Ok(())
+}
+
+// sends to everyone who passes the filter and hasn't muted the sender
+fn broadcast<F: Fn(&Player) -> bool>(
+    players: &mut Vec<Option<Player>>,
+    sender_name: &str,
+    text: String,
+    filter: F,
+) {
+    for player in players.iter_mut().flatten() {
+        if filter(player) && !player.muted.contains(sender_name) {
+            player.send_chat(text.clone());
+        }
+    }
+}
+
+pub fn say(players: &mut Vec<Option<Player>>, player_id: usize, msg: String) -> Result<()> {
+    check_message(&msg)?;
+    let player = get(players, player_id)?;
+    let name = player.name();
+    let loc = *player.loc();
+    let text = format!("[say] {}: {}\n", name, msg);
+    broadcast(players, &name, text, |p| {
+        (*p.loc() - loc).sqr_mag() < SAY_DIST * SAY_DIST
+    });
+    Ok(())
+}
+
+pub fn shout(players: &mut Vec<Option<Player>>, player_id: usize, msg: String) -> Result<()> {
+    check_message(&msg)?;
+    let name = get(players, player_id)?.name();
+    let text = format!("[shout] {}: {}\n", name, msg);
+    broadcast(players, &name, text, |_| true);
+    Ok(())
+}
+
+pub fn whisper(
+    players: &mut Vec<Option<Player>>,
+    player_id: usize,
+    to_id: usize,
+    msg: String,
+) -> Result<()> {
+    check_message(&msg)?;
+    if player_id == to_id {
+        return Err(anyhow!("you can't whisper to yourself"));
+    }
+    let name = get(players, player_id)?.name();
+    let to = get_mut(players, to_id)?;
+    let to_name = to.name();
+    // whispers to someone who muted you are dropped without telling you
+    if !to.muted.contains(&name) {
+        to.send_chat(format!("[whisper from {}]: {}\n", name, msg));
+    }
+    get_mut(players, player_id)?.send_chat(format!("[whisper to {}]: {}\n", to_name, msg));
+    Ok(())
+}
+
+// with no name, lists who the player has muted
+pub fn mute(
+    players: &mut Vec<Option<Player>>,
+    player_id: usize,
+    name: Option<String>,
+    muted: bool,
+) -> Result<()> {
+    let player = get_mut(players, player_id)?;
+    let name = match name {
+        Some(name) => name,
+        None => {
+            let mut names: Vec<&String> = player.muted.iter().collect();
+            names.sort();
+            let text = if names.len() == 0 {
+                "you haven't muted anyone\n".into()
+            } else {
+                let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                format!("muted: {}\n", names.join(", "))
+            };
+            player.send_text(text);
+            return Ok(());
+        }
+    };
+
+    if muted {
+        if name == player.name() {
+            return Err(anyhow!("you can't mute yourself"));
+        }
+        if !player.muted.insert(name.clone()) {
+            return Err(anyhow!(format!("'{}' is already muted", name)));
+        }
+        player.send_text(format!("muted '{}'\n", name));
+    } else {
+        if !player.muted.remove(&name) {
+            return Err(anyhow!(format!("'{}' isn't muted", name)));
+        }
+        player.send_text(format!("unmuted '{}'\n", name));
+    }
+    Ok(())
+}
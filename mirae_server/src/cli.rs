@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use std::str::FromStr;
+
+pub const USAGE: &str = "usage:
+    mirae_server [options] (--seed <seed> | --load <world name>)
+    mirae_server validate [--gamemode <path>]
+
+options:
+    --gamemode <path>   gamemode file to play (default: pvp/gamemode.jacl)
+    --port <port>       port to accept connections on (default: 9000)
+    --admin-port <port> also take console commands on this port on 127.0.0.1,
+                        from connections that send the token in <save dir>/admin_token
+    --autosave <secs>   how often to save the world, 0 to turn it off (default: 300)
+    --keep-saves <n>    older copies of each world and player save to keep (default: 3)
+    --max-players <n>   how many players can be online at once (default: 256)
+    --reconnect-grace <secs>
+                        how long players stay in the game after losing their connection,
+                        so they can reconnect to it (default: 60)
+    --save-dir <dir>    folder for world and player saves (default: save)";
+
+pub const DEFAULT_SAVE_DIR: &str = "save";
+const DEFAULT_GAMEMODE: &str = "pvp/gamemode.jacl";
+const DEFAULT_PORT: u16 = 9000;
+const DEFAULT_AUTOSAVE: u64 = 300;
+const DEFAULT_KEEP_SAVES: usize = 3;
+const DEFAULT_MAX_PLAYERS: usize = 256;
+const DEFAULT_RECONNECT_GRACE: u64 = 60;
+
+pub enum WorldSource {
+    Seed(u64),
+    Load(String),
+}
+
+impl WorldSource {
+    // what the world is autosaved as
+    pub fn save_name(&self) -> String {
+        match self {
+            WorldSource::Seed(seed) => format!("seed_{}", seed),
+            WorldSource::Load(name) => name.clone(),
+        }
+    }
+}
+
+pub struct RunArgs {
+    pub port: u16,
+    pub admin_port: Option<u16>,
+    pub world: WorldSource,
+    // seconds between world autosaves, 0 if it's off
+    pub autosave: u64,
+    pub keep_saves: usize,
+    pub max_players: usize,
+    // seconds disconnected players are kept for, 0 removes them right away
+    pub reconnect_grace: u64,
+}
+
+pub enum Command {
+    Run(RunArgs),
+    // check the gamemode without starting the server
+    Validate,
+    Help,
+}
+
+pub struct Args {
+    pub command: Command,
+    pub gamemode: String,
+    pub save_dir: String,
+}
+
+fn parse_port(val: String) -> Result<u16> {
+    val.parse()
+        .map_err(|_| anyhow!(format!("invalid port '{}'", val)))
+}
+
+fn parse_number<T: FromStr>(arg: &str, val: String) -> Result<T> {
+    val.parse()
+        .map_err(|_| anyhow!(format!("invalid value '{}' for {}", val, arg)))
+}
+
+impl Args {
+    // args shouldn't include the name of the binary
+    pub fn parse(args: impl Iterator<Item = String>) -> Result<Args> {
+        let mut args = args.peekable();
+        let validate = args.peek().map(|arg| arg == "validate").unwrap_or(false);
+        if validate {
+            args.next();
+        }
+
+        let mut gamemode = None;
+        let mut port = None;
+        let mut admin_port = None;
+        let mut autosave = None;
+        let mut keep_saves = None;
+        let mut max_players = None;
+        let mut reconnect_grace = None;
+        let mut save_dir = None;
+        let mut world = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(anyhow!(format!("{} needs a value", arg)));
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    return Ok(Args {
+                        command: Command::Help,
+                        gamemode: DEFAULT_GAMEMODE.into(),
+                        save_dir: DEFAULT_SAVE_DIR.into(),
+                    })
+                }
+                "--gamemode" => gamemode = Some(value()?),
+                "--save-dir" => save_dir = Some(value()?),
+                "--port" => port = Some(parse_port(value()?)?),
+                "--admin-port" => admin_port = Some(parse_port(value()?)?),
+                "--autosave" => autosave = Some(parse_number(&arg, value()?)?),
+                "--keep-saves" => keep_saves = Some(parse_number(&arg, value()?)?),
+                "--max-players" => match parse_number(&arg, value()?)? {
+                    0 => return Err(anyhow!("--max-players has to be at least 1")),
+                    n => max_players = Some(n),
+                },
+                "--reconnect-grace" => reconnect_grace = Some(parse_number(&arg, value()?)?),
+                "--seed" | "--load" if world.is_some() => {
+                    return Err(anyhow!("only one of --seed and --load can be given"))
+                }
+                "--seed" => {
+                    let val = value()?;
+                    world = Some(WorldSource::Seed(
+                        val.parse()
+                            .map_err(|_| anyhow!(format!("invalid seed '{}'", val)))?,
+                    ));
+                }
+                "--load" => world = Some(WorldSource::Load(value()?)),
+                _ => return Err(anyhow!(format!("unknown argument '{}'", arg))),
+            }
+        }
+
+        let command = if validate {
+            if port.is_some()
+                || admin_port.is_some()
+                || world.is_some()
+                || autosave.is_some()
+                || keep_saves.is_some()
+                || max_players.is_some()
+                || reconnect_grace.is_some()
+            {
+                return Err(anyhow!("validate only takes --gamemode"));
+            }
+            Command::Validate
+        } else {
+            Command::Run(RunArgs {
+                port: port.unwrap_or(DEFAULT_PORT),
+                admin_port,
+                world: world.ok_or(anyhow!("either --seed or --load is needed"))?,
+                autosave: autosave.unwrap_or(DEFAULT_AUTOSAVE),
+                keep_saves: keep_saves.unwrap_or(DEFAULT_KEEP_SAVES),
+                max_players: max_players.unwrap_or(DEFAULT_MAX_PLAYERS),
+                reconnect_grace: reconnect_grace.unwrap_or(DEFAULT_RECONNECT_GRACE),
+            })
+        };
+        Ok(Args {
+            command,
+            gamemode: gamemode.unwrap_or(DEFAULT_GAMEMODE.into()),
+            save_dir: save_dir.unwrap_or(DEFAULT_SAVE_DIR.into()),
+        })
+    }
+}
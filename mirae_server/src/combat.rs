@@ -1,10 +1,16 @@
 use crate::{
     entity::Entity,
-    gamedata::gamedata::{DmgType, GameData},
+    gamedata::{
+        gamedata::{DmgType, GameData, ItemName},
+        item::Ability,
+    },
     stat::default_empty_fields,
 };
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
 
 #[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
 pub enum EntityType {
@@ -34,72 +40,141 @@ impl ID {
     }
 }
 
+// every entity in a battle, borrowed at once so that a turn can affect all of them
+pub type Combatants<'a> = HashMap<ID, &'a mut dyn Entity>;
+
+pub fn combatant<'a, 'b>(
+    entities: &'b mut Combatants<'a>,
+    id: ID,
+) -> Result<&'b mut (dyn Entity + 'a)> {
+    match entities.get_mut(&id) {
+        Some(entity) => Ok(&mut **entity),
+        None => Err(anyhow!(format!("{:?} isn't in this battle", id))),
+    }
+}
+
+// sends text to everyone in the battle except `except`
+pub fn broadcast(entities: &mut Combatants, except: ID, text: String) {
+    for (id, entity) in entities.iter_mut() {
+        if *id != except {
+            entity.send_text(text.clone());
+        }
+    }
+}
+
 #[derive(Hash, Eq, PartialEq, Copy, Clone)]
 pub struct BattleHandle(usize);
 
 pub struct BattleMap {
     id_to_handle: HashMap<ID, BattleHandle>,
-    handle_to_ids: HashMap<BattleHandle, (ID, ID)>,
     handle_to_data: HashMap<BattleHandle, BattleData>,
     curr_handle: BattleHandle,
 }
 
 pub struct BattleData {
     id_to_data: HashMap<ID, CombatData>,
+    // everyone in the order they joined,
+    // which is how they're numbered for targeting and who wins ties on speed
+    order: Vec<ID>,
     defense_turn: bool,
+    // when anyone last did something in this battle
+    last_action: Instant,
 }
 
 impl BattleData {
-    pub fn opponent_combat_data(&self, id: ID) -> Result<&CombatData> {
-        for (k, v) in &self.id_to_data {
-            if &id != k {
-                return Ok(v);
-            }
-        }
-        Err(anyhow!("bad battledata struct!"))
-    }
-
     pub fn combat_data(&self, id: ID) -> Result<&CombatData> {
         self.id_to_data
             .get(&id)
             .ok_or(anyhow!("bad battledata struct!"))
     }
 
-    pub fn opponent_combat_data_mut(&mut self, id: ID) -> Result<&mut CombatData> {
-        for (k, v) in &mut self.id_to_data {
-            if &id != k {
-                return Ok(v);
-            }
-        }
-        Err(anyhow!("bad battledata struct!"))
-    }
-
     pub fn combat_data_mut(&mut self, id: ID) -> Result<&mut CombatData> {
         self.id_to_data
             .get_mut(&id)
             .ok_or(anyhow!("bad battledata struct!"))
     }
 
-    pub fn ids(&self) -> (ID, ID) {
-        let mut ids = self.id_to_data.keys();
-        let err = "there should be 2 ids in hashmap";
-        (
-            ids.next().expect(err).clone(),
-            ids.next().expect(err).clone(),
-        )
+    pub fn ids(&self) -> &Vec<ID> {
+        &self.order
+    }
+
+    pub fn enemies(&self, id: ID) -> Result<Vec<ID>> {
+        let side = self.combat_data(id)?.side;
+        Ok(self
+            .order
+            .iter()
+            .filter(|other| self.id_to_data[other].side != side)
+            .cloned()
+            .collect())
+    }
+
+    pub fn num_sides(&self) -> usize {
+        let sides: HashSet<usize> = self.id_to_data.values().map(|cd| cd.side).collect();
+        sides.len()
+    }
+
+    // whoever has built up the most speed gets to act
+    pub fn turn_holder(&self) -> Option<ID> {
+        let mut holder: Option<ID> = None;
+        for id in &self.order {
+            let ahead = match holder {
+                Some(curr) => self.id_to_data[id].acc_speed > self.id_to_data[&curr].acc_speed,
+                None => true,
+            };
+            if ahead {
+                holder = Some(*id);
+            }
+        }
+        holder
     }
 }
 
 pub struct CombatData {
     pub acc_speed: f64,
-    pub stunned: bool,
+    pub side: usize,
+    // who did the most damage in the last hit, they get the credit for a kill
+    pub last_hit_by: Option<ID>,
+    // turns in a row that were passed because this entity took too long
+    pub timeouts: u64,
     pub status_effects: Vec<(StatusEffect, usize)>,
 }
 
+impl CombatData {
+    fn new(acc_speed: f64, side: usize) -> Self {
+        CombatData {
+            acc_speed,
+            side,
+            last_hit_by: None,
+            timeouts: 0,
+            status_effects: Vec::new(),
+        }
+    }
+
+    // charging up an ability stops you from acting, just like being stunned
+    pub fn stunned(&self) -> bool {
+        self.status_effects.iter().any(|(se, _)| match se {
+            StatusEffect::Stun | StatusEffect::Charge(_, _, _) => true,
+            _ => false,
+        })
+    }
+
+    fn has_stun(&self) -> bool {
+        self.status_effects.iter().any(|(se, _)| match se {
+            StatusEffect::Stun => true,
+            _ => false,
+        })
+    }
+}
+
+// stun and charge last for a number of the entity's turns,
+// every other effect lasts for a number of the entity's defense turns
 #[derive(Clone, Debug)]
 pub enum StatusEffect {
     Stun,
-    Damage(HashMap<DmgType, f64>),
+    // the ability, the item it belongs to and who it's aimed at
+    Charge(Ability, Option<ItemName>, ID),
+    // who the damage came from, so counters know where to go
+    Damage(ID, HashMap<DmgType, f64>),
     Block(HashMap<DmgType, f64>),
     Counter(HashMap<DmgType, f64>),
 }
@@ -108,7 +183,6 @@ impl BattleMap {
     pub fn new() -> Self {
         BattleMap {
             id_to_handle: HashMap::new(),
-            handle_to_ids: HashMap::new(),
             handle_to_data: HashMap::new(),
             curr_handle: BattleHandle(0),
         }
@@ -142,17 +216,44 @@ impl BattleMap {
         self.data_from_handle_mut(&handle)
     }
 
-    pub fn get_opponent(&self, id: ID) -> Result<ID> {
-        let handle = self.get_handle(id)?;
-        let (id1, id2) = self
-            .handle_to_ids
-            .get(handle)
-            .ok_or(anyhow!("bad battle handle"))?;
-        if id1 == &id {
-            Ok(id2.clone())
+    pub fn in_battle(&self, id: ID) -> bool {
+        self.id_to_handle.contains_key(&id)
+    }
+
+    // everyone in the same battle as id, including id
+    pub fn participants(&self, id: ID) -> Result<Vec<ID>> {
+        Ok(self.get_battle_data(id)?.ids().clone())
+    }
+
+    pub fn enemies(&self, id: ID) -> Result<Vec<ID>> {
+        self.get_battle_data(id)?.enemies(id)
+    }
+
+    pub fn side(&self, id: ID) -> Result<usize> {
+        Ok(self.get_battle_data(id)?.combat_data(id)?.side)
+    }
+
+    // the target to use when none was given, only works when there's a single enemy
+    pub fn default_target(&self, id: ID) -> Result<ID> {
+        let enemies = self.enemies(id)?;
+        if enemies.len() == 1 {
+            Ok(enemies[0])
         } else {
-            Ok(id1.clone())
+            Err(anyhow!(
+                "there's more than one enemy, pick a target from 'battle'"
+            ))
+        }
+    }
+
+    // who gets the credit for killing id
+    pub fn killer(&self, id: ID) -> Result<Option<ID>> {
+        let battle_data = self.get_battle_data(id)?;
+        if let Some(last_hit_by) = battle_data.combat_data(id)?.last_hit_by {
+            if battle_data.id_to_data.contains_key(&last_hit_by) {
+                return Ok(Some(last_hit_by));
+            }
         }
+        Ok(battle_data.enemies(id)?.first().cloned())
     }
 
     pub fn add_effect(&mut self, id: ID, effect: StatusEffect, num_turns: usize) -> Result<()> {
@@ -162,11 +263,26 @@ impl BattleMap {
         Ok(())
     }
 
+    pub fn stunned(&self, id: ID) -> Result<bool> {
+        Ok(self.get_battle_data(id)?.combat_data(id)?.stunned())
+    }
+
     pub fn turn(&self, id: ID) -> Result<bool> {
-        let battle_data = self.get_battle_data(id)?;
-        let id_cd = battle_data.combat_data(id)?;
-        let op_cd = battle_data.opponent_combat_data(id)?;
-        Ok(id_cd.acc_speed > op_cd.acc_speed)
+        Ok(self.get_battle_data(id)?.turn_holder() == Some(id))
+    }
+
+    pub fn turn_holder(&self, id: ID) -> Result<Option<ID>> {
+        Ok(self.get_battle_data(id)?.turn_holder())
+    }
+
+    // the entity holding up a battle, if nobody has done anything for longer than timeout
+    pub fn overdue(&self, handle: &BattleHandle, timeout: Duration) -> Result<Option<ID>> {
+        let battle_data = self.data_from_handle(handle)?;
+        if battle_data.last_action.elapsed() > timeout {
+            Ok(battle_data.turn_holder())
+        } else {
+            Ok(None)
+        }
     }
 
     pub fn init_battle(
@@ -176,39 +292,36 @@ impl BattleMap {
         defender_trades: bool,
         g: &GameData,
     ) -> Result<()> {
-        if self.get_handle(attacker.id()).is_ok() || self.get_handle(defender.id()).is_ok() {
-            return Err(anyhow!("trying to init a battle where one or more entities are already in battles"));
+        if self.in_battle(attacker.id()) || self.in_battle(defender.id()) {
+            return Err(anyhow!(
+                "trying to init a battle where one or more entities are already in battles"
+            ));
         }
 
         let battle_handle = self.curr_handle;
         self.curr_handle = BattleHandle(battle_handle.0 + 1);
         self.id_to_handle.insert(attacker.id(), battle_handle);
         self.id_to_handle.insert(defender.id(), battle_handle);
-        self.handle_to_ids
-            .insert(battle_handle, (attacker.id(), defender.id()));
 
         let speed = "speed";
-        let attacker_cd = CombatData {
-            acc_speed: attacker.stats().get(speed, g)? + 0.5,
-            stunned: false,
-            status_effects: Vec::new(),
-        };
-        let defender_cd = CombatData {
-            acc_speed: if defender_trades {
+        let attacker_cd = CombatData::new(attacker.stats().get(speed, g)? + 0.5, 0);
+        let defender_cd = CombatData::new(
+            if defender_trades {
                 0.0
             } else {
                 defender.stats().get(speed, g)?
             },
-            stunned: false,
-            status_effects: Vec::new(),
-        };
+            1,
+        );
 
         let mut id_to_data = HashMap::new();
         id_to_data.insert(attacker.id(), attacker_cd);
         id_to_data.insert(defender.id(), defender_cd);
         let battle_data = BattleData {
             id_to_data,
+            order: vec![attacker.id(), defender.id()],
             defense_turn: false,
+            last_action: Instant::now(),
         };
 
         self.handle_to_data.insert(battle_handle, battle_data);
@@ -226,40 +339,114 @@ impl BattleMap {
         Ok(())
     }
 
-    pub fn end_battle(&mut self, id: ID) -> Result<()> {
-        let opponent = self.get_opponent(id)?;
+    // adds entity to the battle that member is in,
+    // on the same side as ally, or on a new side of its own if there's no ally
+    pub fn join_battle(
+        &mut self,
+        entity: Box<&mut dyn Entity>,
+        member: ID,
+        ally: Option<ID>,
+    ) -> Result<()> {
+        if self.in_battle(entity.id()) {
+            return Err(anyhow!("you are already fighting something"));
+        }
+        let handle = self.get_handle(member)?.clone();
+        let battle_data = self.data_from_handle_mut(&handle)?;
+        let side = match ally {
+            Some(ally) => battle_data.combat_data(ally)?.side,
+            None => {
+                battle_data
+                    .id_to_data
+                    .values()
+                    .map(|cd| cd.side)
+                    .max()
+                    .unwrap_or(0)
+                    + 1
+            }
+        };
+        // newcomers go to the back of the queue
+        let acc_speed = battle_data
+            .id_to_data
+            .values()
+            .map(|cd| cd.acc_speed)
+            .fold(std::f64::INFINITY, f64::min);
+        battle_data
+            .id_to_data
+            .insert(entity.id(), CombatData::new(acc_speed, side));
+        battle_data.order.push(entity.id());
+        self.id_to_handle.insert(entity.id(), handle);
+        entity.send_text("you joined the battle!\n".into());
+        Ok(())
+    }
+
+    // takes id out of its battle. if that leaves fewer than two sides the battle is over,
+    // and everyone that was still in it is returned
+    pub fn leave_battle(&mut self, id: ID) -> Result<Vec<ID>> {
         let handle = self.get_handle(id)?.clone();
-        self.handle_to_ids.remove(&handle);
-        self.handle_to_data.remove(&handle);
         self.id_to_handle.remove(&id);
-        self.id_to_handle.remove(&opponent);
+        let battle_data = self.data_from_handle_mut(&handle)?;
+        battle_data.id_to_data.remove(&id);
+        battle_data.order.retain(|other| *other != id);
+        if battle_data.num_sides() >= 2 {
+            // whoever's turn it is now gets a fresh deadline
+            battle_data.last_action = Instant::now();
+            return Ok(Vec::new());
+        }
+        let remaining = battle_data.ids().clone();
+        for other in &remaining {
+            self.id_to_handle.remove(other);
+        }
+        self.handle_to_data.remove(&handle);
+        Ok(remaining)
+    }
+
+    pub fn end_battle(&mut self, id: ID) -> Result<()> {
+        let handle = self.get_handle(id)?.clone();
+        if let Some(battle_data) = self.handle_to_data.remove(&handle) {
+            for other in battle_data.ids() {
+                self.id_to_handle.remove(other);
+            }
+        }
         Ok(())
     }
 
     pub fn handle_status_effects(
         &mut self,
-        entity: Box<&mut dyn Entity>,
+        id: ID,
+        entities: &mut Combatants,
         g: &GameData,
     ) -> Result<()> {
-        let opponent = self.get_opponent(entity.id())?;
-        let battle_data = self.get_battle_data(entity.id())?;
-        let combat_data = battle_data.combat_data(entity.id())?;
+        let battle_data = self.get_battle_data(id)?;
+        let combat_data = battle_data.combat_data(id)?;
         let mut net_dmg = default_empty_fields(&HashMap::new(), 0.0, &g.dmg);
+        let mut dmg_by_source: HashMap<ID, HashMap<DmgType, f64>> = HashMap::new();
         let status_effects = combat_data.status_effects.clone();
         for (se, _) in &status_effects {
             match se {
-                StatusEffect::Damage(dmg) => {
+                StatusEffect::Damage(source, dmg) => {
                     net_dmg = add(&net_dmg, dmg);
+                    let from_source = dmg_by_source
+                        .entry(*source)
+                        .or_insert_with(|| default_empty_fields(&HashMap::new(), 0.0, &g.dmg));
+                    *from_source = add(from_source, dmg);
                 }
                 _ => {}
             }
         }
 
+        // counters go back to whoever did the damage, if they're still fighting
+        let in_battle: Vec<ID> = dmg_by_source
+            .keys()
+            .filter(|source| battle_data.id_to_data.contains_key(source))
+            .cloned()
+            .collect();
         for (se, _) in &status_effects {
             match se {
                 StatusEffect::Counter(dmg) => {
-                    let counter_damage = mul(dmg, &net_dmg);
-                    self.add_effect(opponent, StatusEffect::Damage(counter_damage), 1)?;
+                    for source in &in_battle {
+                        let counter_damage = mul(dmg, &dmg_by_source[source]);
+                        self.add_effect(*source, StatusEffect::Damage(id, counter_damage), 1)?;
+                    }
                 }
                 _ => {}
             }
@@ -274,6 +461,7 @@ impl BattleMap {
             }
         }
 
+        let entity = combatant(entities, id)?;
         let mut total_dmg = 0.0;
         for (dmg_type, val) in &net_dmg {
             let val = val * entity.defense_buffs()[dmg_type];
@@ -289,30 +477,30 @@ impl BattleMap {
 
         entity.stats_mut().change_health(-total_dmg, g);
 
-        let mut stunned = false;
-        for (se, _) in &status_effects {
-            match se {
-                StatusEffect::Stun => {
-                    stunned = true;
-                }
-                _ => {}
-            }
-        }
-
-        let battle_data = self.get_battle_data_mut(entity.id())?;
-        let combat_data = battle_data.combat_data_mut(entity.id())?;
+        let biggest_hit = dmg_by_source
+            .iter()
+            .map(|(source, dmg)| (*source, dmg.values().sum::<f64>()))
+            .filter(|(_, total)| *total > f64::EPSILON)
+            .fold(
+                None,
+                |best: Option<(ID, f64)>, (source, total)| match best {
+                    Some((_, best_total)) if best_total >= total => best,
+                    _ => Some((source, total)),
+                },
+            );
 
-        if stunned {
-            entity.send_text("you are stunned!\n".into());
-        }
-        if combat_data.stunned && !stunned {
-            entity.send_text("you are no longer stunned!\n".into());
+        let battle_data = self.get_battle_data_mut(id)?;
+        let combat_data = battle_data.combat_data_mut(id)?;
+        if let Some((source, _)) = biggest_hit {
+            combat_data.last_hit_by = Some(source);
         }
 
-        combat_data.stunned = stunned;
-
-        for (_, num_turns) in &mut combat_data.status_effects {
-            *num_turns -= 1;
+        // stun and charge are counted down by reduce_stun
+        for (se, num_turns) in &mut combat_data.status_effects {
+            match se {
+                StatusEffect::Stun | StatusEffect::Charge(_, _, _) => {}
+                _ => *num_turns -= 1,
+            }
         }
         combat_data
             .status_effects
@@ -320,13 +508,22 @@ impl BattleMap {
         Ok(())
     }
 
-    fn reduce_stun(&mut self, id: ID) -> Result<()> {
+    // counts down stuns and charges by one turn,
+    // returning the charged abilities that are now ready to be used
+    fn reduce_stun(&mut self, id: ID) -> Result<Vec<(Ability, Option<ItemName>, ID)>> {
         let combat_data = self.get_battle_data_mut(id)?.combat_data_mut(id)?;
+        let mut charged = Vec::new();
         for (se, num_turns) in &mut combat_data.status_effects {
             match se {
                 StatusEffect::Stun => {
                     *num_turns -= 1;
                 }
+                StatusEffect::Charge(ability, item, target) => {
+                    *num_turns -= 1;
+                    if *num_turns == 0 {
+                        charged.push((ability.clone(), item.clone(), *target));
+                    }
+                }
                 _ => {}
             }
         }
@@ -334,117 +531,201 @@ impl BattleMap {
             .status_effects
             .retain(|(_, num_turns)| *num_turns != 0);
 
-        Ok(())
+        Ok(charged)
     }
 
-    fn report_single_stats(
-        a: Box<&mut dyn Entity>,
-        b: Box<&mut dyn Entity>,
-        opponent: bool,
+    // uses up one turn of an entity that's stunned or charging
+    fn spend_stunned_turn(
+        &mut self,
+        id: ID,
+        entities: &mut Combatants,
         g: &GameData,
     ) -> Result<()> {
-        let opponent_text = if opponent { " opponent's " } else { " " };
-        let (health, max_health, energy, max_energy) = if opponent {
-            (
-                b.stats().health(),
-                b.stats().get("max_health", g)?,
-                b.stats().energy(),
-                b.stats().get("max_energy", g)?,
-            )
-        } else {
-            (
-                a.stats().health(),
-                a.stats().get("max_health", g)?,
-                a.stats().energy(),
-                a.stats().get("max_energy", g)?,
-            )
-        };
-        a.send_text(format!(
-            "your{}health is now {}/{}\n",
-            opponent_text, health, max_health
-        ));
-        a.send_text(format!(
-            "your{}energy is now {}/{}\n",
-            opponent_text, energy, max_energy
-        ));
+        let name = combatant(entities, id)?.name();
+        let was_stunned = self.get_battle_data(id)?.combat_data(id)?.has_stun();
+        if was_stunned {
+            combatant(entities, id)?.send_text("you are stunned and lose your turn!\n".into());
+            broadcast(
+                entities,
+                id,
+                format!("{} is stunned and loses their turn!\n", name),
+            );
+        }
+
+        let charged = self.reduce_stun(id)?;
+
+        if was_stunned && !self.get_battle_data(id)?.combat_data(id)?.has_stun() {
+            combatant(entities, id)?.send_text("you are no longer stunned!\n".into());
+            broadcast(entities, id, format!("{} is no longer stunned!\n", name));
+        }
+
+        for (ability, item, target) in charged {
+            let ability_name = ability.name.clone();
+            // the target might have left while this was charging up
+            let target = if self.enemies(id)?.contains(&target) {
+                Ok(target)
+            } else {
+                self.default_target(id)
+            };
+            let target = match target {
+                Ok(target) => target,
+                Err(_) => {
+                    combatant(entities, id)?.send_text(format!(
+                        "'{}' fizzled out: its target is gone\n",
+                        ability_name
+                    ));
+                    continue;
+                }
+            };
+            combatant(entities, id)?.send_text(format!("you unleash '{}'!\n", ability_name));
+            broadcast(
+                entities,
+                id,
+                format!("{} unleashes '{}'!\n", name, ability_name),
+            );
+
+            let entity = entities
+                .remove(&id)
+                .ok_or(anyhow!("entity should be in the battle"))?;
+            let res = match entities.get_mut(&target) {
+                Some(opponent) => {
+                    let mut opp: Option<Box<&mut dyn Entity>> = Some(Box::new(&mut **opponent));
+                    entity.run_ability(&mut opp, self, ability, &item, g)
+                }
+                None => Err(anyhow!("its target is gone")),
+            };
+            // the entity might not be able to afford the ability anymore
+            if let Err(e) = res {
+                entity.send_text(format!("'{}' fizzled out: {}\n", ability_name, e));
+            }
+            entities.insert(id, entity);
+        }
         Ok(())
     }
 
-    fn report_stats(a: Box<&mut dyn Entity>, b: Box<&mut dyn Entity>, g: &GameData) -> Result<()> {
-        BattleMap::report_single_stats(Box::new(*a), Box::new(*b), false, g)?;
-        BattleMap::report_single_stats(Box::new(*a), Box::new(*b), true, g)?;
-        BattleMap::report_single_stats(Box::new(*b), Box::new(*a), false, g)?;
-        BattleMap::report_single_stats(Box::new(*b), Box::new(*a), true, g)?;
+    fn report_stats(ids: &Vec<ID>, entities: &mut Combatants, g: &GameData) -> Result<()> {
+        let mut lines = Vec::new();
+        for id in ids {
+            let entity = combatant(entities, *id)?;
+            lines.push((
+                *id,
+                entity.name(),
+                format!(
+                    "health is now {}/{}\n",
+                    entity.stats().health(),
+                    entity.stats().get("max_health", g)?
+                ),
+                format!(
+                    "energy is now {}/{}\n",
+                    entity.stats().energy(),
+                    entity.stats().get("max_energy", g)?
+                ),
+            ));
+        }
+        for id in ids {
+            let entity = combatant(entities, *id)?;
+            for (other, name, health, energy) in &lines {
+                let whose = if other == id {
+                    "your".to_string()
+                } else {
+                    format!("{}'s", name)
+                };
+                entity.send_text(format!("{} {}", whose, health));
+                entity.send_text(format!("{} {}", whose, energy));
+            }
+        }
         Ok(())
     }
 
-    pub fn do_turn(
-        &mut self,
-        a: Box<&mut dyn Entity>,
-        b: Box<&mut dyn Entity>,
-        g: &GameData,
-    ) -> Result<()> {
-        let speed = "speed";
-        let a_speed = a.stats().get(speed, g)?;
-        let b_speed = b.stats().get(speed, g)?;
-        let a_turn = self.turn(a.id())?;
+    // called whenever actor has taken an action (or passed) in its battle
+    pub fn do_turn(&mut self, actor: ID, entities: &mut Combatants, g: &GameData) -> Result<()> {
+        let ids = self.participants(actor)?;
+        let mut speeds = HashMap::new();
+        for id in &ids {
+            speeds.insert(*id, combatant(entities, *id)?.stats().get("speed", g)?);
+        }
+        let actor_turn = self.turn(actor)?;
 
-        let battle_data = self.get_battle_data_mut(a.id())?;
+        let battle_data = self.get_battle_data_mut(actor)?;
 
         if !battle_data.defense_turn {
-            if a_turn && !battle_data.combat_data(b.id())?.stunned {
-                battle_data.combat_data_mut(b.id())?.acc_speed += b_speed;
-            } else if !battle_data.combat_data(a.id())?.stunned {
-                battle_data.combat_data_mut(a.id())?.acc_speed += a_speed;
+            let mut stunned = HashSet::new();
+            for id in &ids {
+                if battle_data.combat_data(*id)?.stunned() {
+                    stunned.insert(*id);
+                }
+            }
+
+            // everyone else catches up to whoever just had their turn,
+            // stunned entities don't, and the actor doesn't pull ahead while they sit it out
+            if actor_turn {
+                for id in &ids {
+                    if *id != actor && !stunned.contains(id) {
+                        battle_data.combat_data_mut(*id)?.acc_speed += speeds[id];
+                    }
+                }
+            } else if !stunned.contains(&actor) {
+                battle_data.combat_data_mut(actor)?.acc_speed += speeds[&actor];
+            }
+
+            // the actor has either passed while stunned or just started charging
+            if stunned.contains(&actor) {
+                self.spend_stunned_turn(actor, entities, g)?;
+            }
+            // stunned entities lose the turns they would have had
+            if actor_turn {
+                for id in &ids {
+                    if *id != actor && stunned.contains(id) {
+                        self.spend_stunned_turn(*id, entities, g)?;
+                    }
+                }
             }
-            let battle_data;
-            let new_turn = self.turn(a.id())?;
-
-            let mut a_stun = self
-                .get_battle_data_mut(a.id())?
-                .combat_data(a.id())?
-                .stunned;
-            let mut b_stun = self
-                .get_battle_data_mut(b.id())?
-                .combat_data(b.id())?
-                .stunned;
-            while a_stun && b_stun {
-                self.reduce_stun(a.id())?;
-                self.reduce_stun(b.id())?;
-                a_stun = self
-                    .get_battle_data_mut(a.id())?
-                    .combat_data(a.id())?
-                    .stunned;
-                b_stun = self
-                    .get_battle_data_mut(b.id())?
-                    .combat_data(b.id())?
-                    .stunned;
+            loop {
+                let mut all_stunned = true;
+                for id in &ids {
+                    all_stunned &= self.stunned(*id)?;
+                }
+                if !all_stunned {
+                    break;
+                }
+                for id in &ids {
+                    self.spend_stunned_turn(*id, entities, g)?;
+                }
             }
-            battle_data = self.get_battle_data_mut(a.id())?;
-            battle_data.defense_turn = a_turn != new_turn;
+
+            let battle_data = self.get_battle_data_mut(actor)?;
+            let holder = battle_data.turn_holder();
+            battle_data.defense_turn = actor_turn != (holder == Some(actor));
             if battle_data.defense_turn {
-                if a_turn {
-                    a.send_text("your turn is over!\n".into());
-                    b.send_text("it's your turn!\n".into());
-                } else {
-                    b.send_text("your turn is over!\n".into());
-                    a.send_text("it's your turn!\n".into());
+                if let Some(holder) = holder {
+                    if actor_turn {
+                        combatant(entities, actor)?.send_text("your turn is over!\n".into());
+                    }
+                    let name = combatant(entities, holder)?.name();
+                    combatant(entities, holder)?.send_text("it's your turn!\n".into());
+                    for id in &ids {
+                        if *id != holder {
+                            combatant(entities, *id)?.send_text(format!("it's {}'s turn!\n", name));
+                        }
+                    }
                 }
             }
         } else {
             battle_data.defense_turn = false;
-            if a_turn {
-                self.handle_status_effects(Box::new(*a), g)?;
-            } else {
-                self.handle_status_effects(Box::new(*b), g)?;
+            if let Some(holder) = battle_data.turn_holder() {
+                self.handle_status_effects(holder, entities, g)?;
             }
         }
-        BattleMap::report_stats(a, b, g)?;
+        BattleMap::report_stats(&ids, entities, g)?;
+
+        let battle_data = self.get_battle_data_mut(actor)?;
+        battle_data.last_action = Instant::now();
+        battle_data.combat_data_mut(actor)?.timeouts = 0;
         Ok(())
     }
 
-    pub fn battles(&self) -> std::collections::hash_map::Keys<BattleHandle, (ID, ID)> {
-        self.handle_to_ids.keys()
+    pub fn battles(&self) -> std::collections::hash_map::Keys<BattleHandle, BattleData> {
+        self.handle_to_data.keys()
     }
 }
 
@@ -463,3 +744,126 @@ fn add(a: &HashMap<DmgType, f64>, b: &HashMap<DmgType, f64>) -> HashMap<DmgType,
     }
     new
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{player::Player, read_gamemode};
+    use crossbeam::channel::unbounded;
+    use rand::{prelude::StdRng, SeedableRng};
+
+    fn gamedata() -> Result<GameData> {
+        read_gamemode("pvp/gamemode.jacl")?.into_gamedata()
+    }
+
+    fn player(id: usize, g: &GameData) -> Result<Player> {
+        let (sender, _) = unbounded();
+        let mut rng = StdRng::seed_from_u64(id as u64);
+        Player::new(id, id as u32, sender, g, &mut rng)
+    }
+
+    fn charge_left(battle_map: &BattleMap, id: ID) -> Result<Option<usize>> {
+        let combat_data = battle_map.get_battle_data(id)?.combat_data(id)?;
+        Ok(combat_data
+            .status_effects
+            .iter()
+            .find_map(|(se, num_turns)| match se {
+                StatusEffect::Charge(_, _, _) => Some(*num_turns),
+                _ => None,
+            }))
+    }
+
+    fn damaged(battle_map: &BattleMap, id: ID) -> Result<bool> {
+        let combat_data = battle_map.get_battle_data(id)?.combat_data(id)?;
+        Ok(combat_data.status_effects.iter().any(|(se, _)| match se {
+            StatusEffect::Damage(_, _) => true,
+            _ => false,
+        }))
+    }
+
+    #[test]
+    fn stunned_combatant_loses_exactly_its_stunned_turns() -> Result<()> {
+        let g = gamedata()?;
+        for stun in 0..4 {
+            let mut a = player(0, &g)?;
+            let mut b = player(1, &g)?;
+            let (a_id, b_id) = (a.id(), b.id());
+            let mut battle_map = BattleMap::new();
+            battle_map.init_battle(Box::new(&mut a), Box::new(&mut b), false, &g)?;
+            if stun > 0 {
+                battle_map.add_effect(b_id, StatusEffect::Stun, stun)?;
+            }
+            let mut entities: Combatants = HashMap::new();
+            entities.insert(a_id, &mut a);
+            entities.insert(b_id, &mut b);
+
+            // with the same speed they'd take turns, so every extra turn a gets is one b lost
+            let mut a_turns = 0;
+            while battle_map.turn_holder(a_id)? == Some(a_id) {
+                assert!(
+                    a_turns <= stun,
+                    "b is still stunned after {} turns",
+                    a_turns
+                );
+                battle_map.do_turn(a_id, &mut entities, &g)?;
+                a_turns += 1;
+            }
+            assert_eq!(a_turns, stun + 1);
+            assert!(!battle_map.stunned(b_id)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn charged_ability_waits_for_its_wind_up() -> Result<()> {
+        let g = gamedata()?;
+        let charge = 2;
+        let mut a = player(0, &g)?;
+        let mut b = player(1, &g)?;
+        let (a_id, b_id) = (a.id(), b.id());
+        let mut battle_map = BattleMap::new();
+        battle_map.init_battle(Box::new(&mut a), Box::new(&mut b), false, &g)?;
+
+        let stick = ItemName::from("stick".to_string());
+        let mut hit = g.items[&stick].abilities["hit"].clone();
+        hit.charge = charge;
+        hit.accuracy = 1.0;
+        a.run_ability(
+            &mut Some(Box::new(&mut b)),
+            &mut battle_map,
+            hit,
+            &Some(stick),
+            &g,
+        )?;
+        assert!(battle_map.stunned(a_id)?);
+        assert!(!damaged(&battle_map, b_id)?);
+
+        let mut entities: Combatants = HashMap::new();
+        entities.insert(a_id, &mut a);
+        entities.insert(b_id, &mut b);
+
+        // whoever's turn it is passes, a's turns go to charging until the hit lands
+        let mut turns_charged = 0;
+        let mut left = charge_left(&battle_map, a_id)?;
+        while let Some(before) = left {
+            assert!(
+                !damaged(&battle_map, b_id)?,
+                "hit landed during the wind-up"
+            );
+            let holder = battle_map
+                .turn_holder(a_id)?
+                .expect("the battle has someone to act");
+            battle_map.do_turn(holder, &mut entities, &g)?;
+            left = charge_left(&battle_map, a_id)?;
+            if left != Some(before) {
+                turns_charged += 1;
+            }
+            assert!(turns_charged <= charge + 1, "charge never finished");
+        }
+        // one turn to start charging, then the wind-up
+        assert_eq!(turns_charged, charge + 1);
+        assert!(damaged(&battle_map, b_id)?);
+        assert!(!battle_map.stunned(a_id)?);
+        Ok(())
+    }
+}
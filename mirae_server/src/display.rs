@@ -1,10 +1,11 @@
 use crate::{
-    entity::Entity, gamedata::gamedata::GameData, player::Player, rgb::RGB, vector3::Vector3,
-    world::World,
+    entity::Entity, gamedata::gamedata::GameData, player::Player, rgb::RGB, sight::Sight,
+    vector3::Vector3, world::World,
 };
 use anyhow::{anyhow, Result};
+use serde::{Serialize, Serializer};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Bounds {
     posn: Vector3,
     width: usize,
@@ -58,6 +59,12 @@ impl Bounds {
         }
     }
 
+    pub fn posns(&self) -> impl Iterator<Item = Vector3> + '_ {
+        (0..self.height).flat_map(move |j| {
+            (0..self.width).map(move |i| self.posn + Vector3::new(i as isize, j as isize, 0))
+        })
+    }
+
     pub fn in_bounds(&self, posn: Vector3) -> bool {
         let diff = posn - self.posn;
         diff.x() < self.width as isize
@@ -68,23 +75,156 @@ impl Bounds {
     }
 }
 
+#[derive(Clone)]
 pub struct Image {
     pub width: u8,
     pub height: u8,
     pub blocks: Vec<u8>,
     pub entities: Option<Vec<u8>>,
-    pub players: Vec<u8>,
+    // player number and where they are in the image
+    pub players: Vec<(u32, u8, u8)>,
     pub res_is_1: bool,
+    // the world position of the top left corner, not sent to clients
+    origin: Vector3,
+}
+
+#[derive(Serialize)]
+pub struct PlayerMark {
+    number: u32,
+    x: u8,
+    y: u8,
+}
+
+fn player_marks(players: &[(u32, u8, u8)]) -> Vec<PlayerMark> {
+    players
+        .iter()
+        .map(|(number, x, y)| PlayerMark {
+            number: *number,
+            x: *x,
+            y: *y,
+        })
+        .collect()
+}
+
+// the player count is 2 bytes and each player number 4, both big endian
+fn header_bytes(vec: &mut Vec<u8>, width: u8, height: u8, res_is_1: bool, players: &[PlayerMark]) {
+    vec.push(width);
+    vec.push(height);
+    let num_players = players.len().min(u16::MAX as usize);
+    vec.extend_from_slice(&(num_players as u16).to_be_bytes());
+    if res_is_1 {
+        vec.push(1);
+    } else {
+        vec.push(0);
+    }
+    for player in players.iter().take(num_players) {
+        vec.extend_from_slice(&player.number.to_be_bytes());
+        vec.push(player.x);
+        vec.push(player.y);
+    }
+}
+
+// squares next to each other that all changed, starting at index start (row by row)
+#[derive(Serialize)]
+pub struct Run {
+    start: u16,
+    colors: Vec<u8>,
+    textures: Option<Vec<u8>>,
+    entities: Option<Vec<u8>>,
+}
+
+// what changed in an image since the last one sent to the same place.
+// the players are always sent in full, there aren't many of them
+#[derive(Serialize)]
+pub struct ImageDelta {
+    width: u8,
+    height: u8,
+    resolution_is_1: bool,
+    players: Vec<PlayerMark>,
+    runs: Vec<Run>,
+    #[serde(skip)]
+    players_changed: bool,
+}
+
+impl ImageDelta {
+    // nothing worth sending
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty() && !self.players_changed
+    }
+
+    // same header as a full image, then a 2 byte run count.
+    // each run is a 2 byte start and length, then the run's colors,
+    // and at resolution 1 its textures and then its entities
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        header_bytes(
+            &mut vec,
+            self.width,
+            self.height,
+            self.resolution_is_1,
+            &self.players,
+        );
+        vec.extend_from_slice(&(self.runs.len() as u16).to_be_bytes());
+        for run in self.runs {
+            vec.extend_from_slice(&run.start.to_be_bytes());
+            vec.extend_from_slice(&((run.colors.len() / 3) as u16).to_be_bytes());
+            vec.extend(run.colors);
+            if let Some(textures) = run.textures {
+                vec.extend(textures);
+            }
+            if let Some(entities) = run.entities {
+                vec.extend(entities);
+            }
+        }
+        vec
+    }
+}
+
+// how images look in the JSON protocol, with the colors and block textures split apart
+#[derive(Serialize)]
+struct ImageJson<'a> {
+    width: u8,
+    height: u8,
+    resolution_is_1: bool,
+    players: Vec<PlayerMark>,
+    // r, g, b for each square, row by row
+    colors: &'a [u8],
+    // block texture for each square, 255 if there isn't one, only at resolution 1
+    textures: Option<&'a [u8]>,
+    // mob image for each square, 255 if there isn't one, only at resolution 1
+    entities: Option<&'a [u8]>,
+}
+
+impl Serialize for Image {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let colors_len = (self.width as usize * self.height as usize * 3).min(self.blocks.len());
+        let (colors, textures) = self.blocks.split_at(colors_len);
+        ImageJson {
+            width: self.width,
+            height: self.height,
+            resolution_is_1: self.res_is_1,
+            players: player_marks(&self.players),
+            colors,
+            textures: if self.res_is_1 { Some(textures) } else { None },
+            entities: self.entities.as_ref().map(|entities| entities.as_slice()),
+        }
+        .serialize(serializer)
+    }
 }
 
 impl Image {
-    fn average_color(world: &World, bounds: &Bounds, gd: &GameData) -> Result<RGB> {
+    // of the squares in bounds that are known, unknown squares are left out
+    fn average_color(world: &World, bounds: &Bounds, gd: &GameData, sight: &Sight) -> Result<RGB> {
         let mut r = 0;
         let mut g = 0;
         let mut b = 0;
+        let mut known = 0;
         for y in 0..bounds.height {
             for x in 0..bounds.width {
                 let loc = bounds.posn + Vector3::new(x as isize, y as isize, 0);
+                if !sight.knows(loc) {
+                    continue;
+                }
                 let rgb = world
                     .colors()
                     .get(loc)?
@@ -92,20 +232,23 @@ impl Image {
                 r += rgb.r as usize;
                 g += rgb.g as usize;
                 b += rgb.b as usize;
+                known += 1;
             }
         }
-        r /= bounds.height * bounds.width;
-        g /= bounds.height * bounds.width;
-        b /= bounds.height * bounds.width;
-        let rgb = RGB::new(r as u8, g as u8, b as u8);
+        if known == 0 {
+            return Ok(RGB::black());
+        }
+        let rgb = RGB::new((r / known) as u8, (g / known) as u8, (b / known) as u8);
         Ok(rgb)
     }
 
+    // squares that aren't known are black and have no texture
     fn display_blocks(
         world: &World,
         bounds: &Bounds,
         resolution: usize,
         g: &GameData,
+        sight: &Sight,
     ) -> Result<Vec<u8>> {
         let mut display = Vec::new();
         if resolution != 1 {
@@ -118,7 +261,7 @@ impl Image {
                         width: resolution,
                         height: resolution,
                     };
-                    let rgb = Image::average_color(world, &bounds, g)?;
+                    let rgb = Image::average_color(world, &bounds, g, sight)?;
                     display.push(rgb.r);
                     display.push(rgb.g);
                     display.push(rgb.b);
@@ -128,6 +271,10 @@ impl Image {
             for j in 0..bounds.height {
                 for i in 0..bounds.width {
                     let loc = bounds.posn + Vector3::new(i as isize, j as isize, 0);
+                    if !sight.knows(loc) {
+                        display.extend_from_slice(&[0, 0, 0]);
+                        continue;
+                    }
                     let mut rgb = world.colors().get(loc)?;
                     let block = world.get_block_at(g, loc)?;
                     if block.texture.is_none() {
@@ -142,7 +289,11 @@ impl Image {
             for j in 0..bounds.height {
                 for i in 0..bounds.width {
                     let loc = bounds.posn + Vector3::new(i as isize, j as isize, 0);
-                    if let Some(texture_id) = g.block_id_to_img_id.get(&world.blocks().get(loc)?) {
+                    if !sight.knows(loc) {
+                        display.push(u8::MAX);
+                    } else if let Some(texture_id) =
+                        g.block_id_to_img_id.get(&world.blocks().get(loc)?)
+                    {
                         display.push(*texture_id);
                     } else {
                         display.push(u8::MAX);
@@ -153,12 +304,19 @@ impl Image {
         Ok(display)
     }
 
-    fn display_entity(world: &World, bounds: &Bounds, g: &GameData) -> Result<Vec<u8>> {
+    fn display_entity(
+        world: &World,
+        bounds: &Bounds,
+        g: &GameData,
+        sight: &Sight,
+    ) -> Result<Vec<u8>> {
         let mut display = Vec::new();
         for j in 0..bounds.height {
             for i in 0..bounds.width {
                 let posn = bounds.posn + Vector3::new(i as isize, j as isize, 0);
-                if let Some(curr) = world.mobs().get(posn)?.as_u16() {
+                if !sight.sees(posn) {
+                    display.push(u8::MAX);
+                } else if let Some(curr) = world.mobs().get(posn)?.as_u16() {
                     let val = g
                         .mob_id_to_img_id
                         .get(&curr)
@@ -177,7 +335,8 @@ impl Image {
         players: &Vec<Option<Player>>,
         bounds: &Bounds,
         resolution: usize,
-    ) -> Result<Vec<u8>> {
+        sight: &Sight,
+    ) -> Result<Vec<(u32, u8, u8)>> {
         let mut display = Vec::new();
         for j in 0..(bounds.height / resolution) {
             for i in 0..(bounds.width / resolution) {
@@ -188,13 +347,9 @@ impl Image {
                     width: resolution,
                     height: resolution,
                 };
-                for id in 0..players.len() {
-                    if let Some(p) = players[id].as_ref() {
-                        if bounds.in_bounds(p.loc().clone()) {
-                            display.push(id as u8);
-                            display.push(i as u8);
-                            display.push(j as u8);
-                        }
+                for p in players.iter().flatten() {
+                    if bounds.in_bounds(p.loc().clone()) && sight.sees(*p.loc()) {
+                        display.push((p.number, i as u8, j as u8));
                     }
                 }
             }
@@ -208,15 +363,16 @@ impl Image {
         g: &GameData,
         bounds: &Bounds,
         resolution: usize,
+        sight: &Sight,
     ) -> Result<Self> {
         let mut entities = None;
         if resolution == 0 {
             return Err(anyhow!("resolution cannot be 0"));
         } else if resolution == 1 {
-            entities = Some(Image::display_entity(world, bounds, g)?);
+            entities = Some(Image::display_entity(world, bounds, g, sight)?);
         }
-        let blocks = Image::display_blocks(world, bounds, resolution, g)?;
-        let players = Image::display_players(players, bounds, resolution)?;
+        let blocks = Image::display_blocks(world, bounds, resolution, g, sight)?;
+        let players = Image::display_players(players, bounds, resolution, sight)?;
         Ok(Self {
             blocks,
             entities,
@@ -224,20 +380,86 @@ impl Image {
             width: (bounds.width / resolution) as u8,
             height: (bounds.height / resolution) as u8,
             res_is_1: resolution == 1,
+            origin: bounds.posn,
+        })
+    }
+
+    fn cells(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    fn cell_changed(&self, prev: &Image, i: usize) -> bool {
+        let n = self.cells();
+        self.blocks[3 * i..3 * i + 3] != prev.blocks[3 * i..3 * i + 3]
+            || (self.res_is_1 && self.blocks[3 * n + i] != prev.blocks[3 * n + i])
+            || match (&self.entities, &prev.entities) {
+                (Some(entities), Some(prev_entities)) => entities[i] != prev_entities[i],
+                _ => false,
+            }
+    }
+
+    fn run(&self, start: usize, end: usize) -> Run {
+        let n = self.cells();
+        Run {
+            start: start as u16,
+            colors: self.blocks[3 * start..3 * end].to_vec(),
+            textures: if self.res_is_1 {
+                Some(self.blocks[3 * n + start..3 * n + end].to_vec())
+            } else {
+                None
+            },
+            entities: self
+                .entities
+                .as_ref()
+                .map(|entities| entities[start..end].to_vec()),
+        }
+    }
+
+    // None if the images don't cover the same squares, so the whole image has to be sent
+    pub fn delta_from(&self, prev: &Image) -> Option<ImageDelta> {
+        if self.width != prev.width
+            || self.height != prev.height
+            || self.res_is_1 != prev.res_is_1
+            || self.origin != prev.origin
+            || self.blocks.len() != prev.blocks.len()
+            || self.entities.is_some() != prev.entities.is_some()
+        {
+            return None;
+        }
+
+        let n = self.cells();
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < n {
+            if !self.cell_changed(prev, i) {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            while i < n && self.cell_changed(prev, i) {
+                i += 1;
+            }
+            runs.push(self.run(start, i));
+        }
+        Some(ImageDelta {
+            width: self.width,
+            height: self.height,
+            resolution_is_1: self.res_is_1,
+            players: player_marks(&self.players),
+            runs,
+            players_changed: self.players != prev.players,
         })
     }
 
     pub fn into_bytes(mut self) -> Vec<u8> {
         let mut vec = Vec::new();
-        vec.push(self.width);
-        vec.push(self.height);
-        vec.push((self.players.len() / 3) as u8);
-        if self.res_is_1 {
-            vec.push(1);
-        } else {
-            vec.push(0);
-        }
-        vec.append(&mut self.players);
+        header_bytes(
+            &mut vec,
+            self.width,
+            self.height,
+            self.res_is_1,
+            &player_marks(&self.players),
+        );
         vec.append(&mut self.blocks);
         if let Some(mut entities) = self.entities {
             vec.append(&mut entities);
@@ -245,3 +467,121 @@ impl Image {
         vec
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u8, height: u8, res_is_1: bool) -> Image {
+        let n = width as usize * height as usize;
+        let blocks_len = if res_is_1 { 4 * n } else { 3 * n };
+        Image {
+            width,
+            height,
+            blocks: (0..blocks_len).map(|i| (i % 251) as u8).collect(),
+            entities: if res_is_1 {
+                Some(vec![u8::MAX; n])
+            } else {
+                None
+            },
+            players: vec![(3, 1, 1)],
+            res_is_1,
+            origin: Vector3::new(10, 10, 0),
+        }
+    }
+
+    fn header_len(bytes: &[u8]) -> usize {
+        5 + 6 * u16::from_be_bytes([bytes[2], bytes[3]]) as usize
+    }
+
+    // patches a full image with a delta the way a client would
+    fn apply(full: Vec<u8>, delta: &[u8], res_is_1: bool) -> Vec<u8> {
+        let n = delta[0] as usize * delta[1] as usize;
+        let header = header_len(delta);
+        let mut body = full[header_len(&full)..].to_vec();
+        let mut i = header + 2;
+        for _ in 0..u16::from_be_bytes([delta[header], delta[header + 1]]) {
+            let start = u16::from_be_bytes([delta[i], delta[i + 1]]) as usize;
+            let len = u16::from_be_bytes([delta[i + 2], delta[i + 3]]) as usize;
+            i += 4;
+            body[3 * start..3 * (start + len)].copy_from_slice(&delta[i..i + 3 * len]);
+            i += 3 * len;
+            if res_is_1 {
+                body[3 * n + start..3 * n + start + len].copy_from_slice(&delta[i..i + len]);
+                i += len;
+                body[4 * n + start..4 * n + start + len].copy_from_slice(&delta[i..i + len]);
+                i += len;
+            }
+        }
+        assert_eq!(i, delta.len(), "delta has bytes left over");
+        [&delta[..header], &body[..]].concat()
+    }
+
+    fn assert_round_trips(prev: &Image, next: &Image) {
+        let delta = next
+            .delta_from(prev)
+            .expect("images cover the same squares");
+        let patched = apply(
+            prev.clone().into_bytes(),
+            &delta.into_bytes(),
+            next.res_is_1,
+        );
+        assert_eq!(patched, next.clone().into_bytes());
+    }
+
+    #[test]
+    fn delta_round_trips() {
+        let prev = image(9, 7, true);
+        let n = 9 * 7;
+        let mut next = prev.clone();
+        // the first square, a run in the middle and a run that reaches the last square
+        next.blocks[0] ^= 1;
+        next.blocks[3 * n + 20] ^= 1;
+        next.blocks[3 * n + 21] ^= 1;
+        next.entities.as_mut().unwrap()[n - 2] = 4;
+        next.blocks[3 * (n - 1) + 2] ^= 1;
+        next.players = vec![(3, 2, 1), (5, 8, 6)];
+        assert_round_trips(&prev, &next);
+
+        let delta = next.delta_from(&prev).unwrap();
+        let starts: Vec<u16> = delta.runs.iter().map(|run| run.start).collect();
+        assert_eq!(starts, vec![0, 20, n as u16 - 2]);
+    }
+
+    #[test]
+    fn delta_round_trips_at_lower_resolution() {
+        let prev = image(6, 4, false);
+        let mut next = prev.clone();
+        for byte in next.blocks.iter_mut().skip(30) {
+            *byte = 0;
+        }
+        assert_round_trips(&prev, &next);
+    }
+
+    #[test]
+    fn unchanged_images_have_empty_deltas() {
+        let prev = image(5, 5, true);
+        assert!(prev.delta_from(&prev).unwrap().is_empty());
+
+        let mut moved = prev.clone();
+        moved.players[0].1 = 2;
+        let delta = moved.delta_from(&prev).unwrap();
+        assert!(delta.runs.is_empty() && !delta.is_empty());
+        assert_round_trips(&prev, &moved);
+    }
+
+    #[test]
+    fn other_squares_need_a_full_image() {
+        let prev = image(5, 5, true);
+        let mut wider = image(6, 5, true);
+        wider.origin = prev.origin;
+        assert!(wider.delta_from(&prev).is_none());
+
+        let mut moved = prev.clone();
+        moved.origin = Vector3::new(11, 10, 0);
+        assert!(moved.delta_from(&prev).is_none());
+
+        let zoomed = image(5, 5, false);
+        assert!(zoomed.delta_from(&prev).is_none());
+    }
+}
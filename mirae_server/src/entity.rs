@@ -68,12 +68,12 @@ pub trait Entity {
 
     fn unwear(&mut self, item_name: &ItemName, g: &GameData) -> Result<()> {
         if self.worn().get(item_name) > 0 {
-            self.worn_mut().change(item_name.clone(), -1)?;
-            self.inventory_mut().add(item_name.clone(), 1);
             let item = g
                 .items
                 .get(item_name)
                 .ok_or(anyhow!(format!("invalid item name! {:?}", item_name)))?;
+            self.worn_mut().change(item_name.clone(), -1)?;
+            self.inventory_mut().add(item_name.clone(), 1);
             self.stats_mut().remove_buffs(&item.buffs.stat_buffs(g), g);
             for (dmg_type, buff) in item.buffs.attack_buffs(g) {
                 let curr = *self
@@ -143,25 +143,8 @@ pub trait Entity {
         }
     }
 
-    fn run_ability(
-        &mut self,
-        opponent: &mut Option<Box<&mut dyn Entity>>,
-        battle_map: &mut BattleMap,
-        ability: Ability,
-        item: &Option<ItemName>,
-        g: &GameData,
-    ) -> Result<()> {
-        if self.rng().gen::<f64>() > ability.accuracy
-            || self.rng().gen::<f64>() > self.stats().get("accuracy", g)?
-        {
-            self.send_text("missed!\n".into());
-            if let Some(opponent) = opponent {
-                opponent.send_text(format!("{} missed!\n", self.name()));
-            }
-            return Ok(());
-        }
-
-        // checks to ensure we can actually use the ability
+    // checks to ensure we can actually use the ability
+    fn check_ability(&self, ability: &Ability) -> Result<()> {
         if self.xp() + ability.xp < 0 {
             return Err(anyhow!(format!(
                 "you need at least {} xp to use this ability",
@@ -186,6 +169,55 @@ pub trait Entity {
                 ability.require_items
             )));
         }
+        Ok(())
+    }
+
+    fn run_ability(
+        &mut self,
+        opponent: &mut Option<Box<&mut dyn Entity>>,
+        battle_map: &mut BattleMap,
+        ability: Ability,
+        item: &Option<ItemName>,
+        g: &GameData,
+    ) -> Result<()> {
+        // charged abilities wind up for a number of turns in battle,
+        // then get run again with no charge once they're ready
+        if ability.charge > 0 {
+            if let Some(opponent) = opponent {
+                self.check_ability(&ability)?;
+                self.send_text(format!(
+                    "you start charging '{}' for {} turns...\n",
+                    ability.name, ability.charge
+                ));
+                opponent.send_text(format!(
+                    "{} starts charging '{}'!\n",
+                    self.name(),
+                    ability.name
+                ));
+                // one extra turn for the turn that starts the charge
+                let num_turns = ability.charge as usize + 1;
+                let mut ability = ability;
+                ability.charge = 0;
+                battle_map.add_effect(
+                    self.id(),
+                    StatusEffect::Charge(ability, item.clone(), opponent.id()),
+                    num_turns,
+                )?;
+                return Ok(());
+            }
+        }
+
+        if self.rng().gen::<f64>() > ability.accuracy
+            || self.rng().gen::<f64>() > self.stats().get("accuracy", g)?
+        {
+            self.send_text("missed!\n".into());
+            if let Some(opponent) = opponent {
+                opponent.send_text(format!("{} missed!\n", self.name()));
+            }
+            return Ok(());
+        }
+
+        self.check_ability(&ability)?;
 
         // if we're in battle, do damage calcs
         if let Some(opponent) = opponent {
@@ -198,7 +230,7 @@ pub trait Entity {
 
             battle_map.add_effect(
                 opponent.id(),
-                StatusEffect::Damage(mul(&ability.damage(g), self.attack_buffs())),
+                StatusEffect::Damage(self.id(), mul(&ability.damage(g), self.attack_buffs())),
                 ability.repeat as usize + 1,
             )?;
 
@@ -214,6 +246,16 @@ pub trait Entity {
                 ability.repeat as usize + 1,
             )?;
 
+            if ability.stun > 0 {
+                battle_map.add_effect(opponent.id(), StatusEffect::Stun, ability.stun as usize)?;
+                opponent.send_text(format!("you are stunned for {} turns!\n", ability.stun));
+                self.send_text(format!(
+                    "{} is stunned for {} turns!\n",
+                    opponent.name(),
+                    ability.stun
+                ));
+            }
+
             if ability.text != "" {
                 opponent.send_text(format!("{}\n", ability.text.clone()));
             }
@@ -159,6 +159,15 @@ impl MobInfo {
             spawn_chance: 0.0,
         }
     }
+
+    // whether a mob with these tags passes the exclude and require rules,
+    // favoring is left to chance so it isn't checked here
+    pub fn allows(&self, tags: &Vec<String>) -> bool {
+        if self.exclude.iter().any(|tag| tags.contains(tag)) {
+            return false;
+        }
+        self.require.len() == 0 || self.require.iter().any(|tag| tags.contains(tag))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -175,3 +184,10 @@ pub struct Block {
     pub drop: Option<ItemName>,
     pub texture: Option<String>,
 }
+
+impl Block {
+    // solid blocks hide what's behind them, unless they let light through
+    pub fn blocks_sight(&self) -> bool {
+        self.solid && self.transparency == RGB::black()
+    }
+}
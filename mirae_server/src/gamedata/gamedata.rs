@@ -2,16 +2,14 @@ use super::{
     block::{Block, BlockDeser},
     item::{Item, ItemDeser},
     mobtemplate::{MobTemplate, MobTemplateDeser},
+    serde_defaults::zero_u64,
     structures::{Structure, StructureDeser},
     terrain::{Biome, BiomeDeser, Terrain, TerrainDeser},
 };
-use crate::{
-    playerout::{Packet, PacketType},
-    world::MobU16,
-};
-use anyhow::{anyhow, Result};
+use crate::world::MobU16;
+use anyhow::{anyhow, Error, Result};
 use bimap::BiMap;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_jacl::de::from_str;
 use std::{
     collections::{HashMap, HashSet},
@@ -35,8 +33,8 @@ pub trait Named<T: From<String> + Debug + Hash + Eq + Named = Self> {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub struct DmgType(String);
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct DmgType(pub String);
 
 impl From<String> for DmgType {
     fn from(s: String) -> Self {
@@ -70,7 +68,7 @@ impl Named for StatType {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
-pub struct StructureName(String);
+pub struct StructureName(pub String);
 
 impl From<String> for StructureName {
     fn from(s: String) -> Self {
@@ -87,7 +85,7 @@ impl Named for StructureName {
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
-pub struct BiomeName(String);
+pub struct BiomeName(pub String);
 
 impl From<String> for BiomeName {
     fn from(s: String) -> Self {
@@ -156,14 +154,53 @@ impl Named for BlockName {
 
 #[derive(Debug, Deserialize)]
 pub struct GameMode {
-    items: String,
-    terrain: String,
-    biomes: String,
-    structures: String,
-    dmg: String,
-    stat: String,
-    mobs: String,
-    blocks: String,
+    pub items: String,
+    pub terrain: String,
+    pub biomes: String,
+    pub structures: String,
+    pub dmg: String,
+    pub stat: String,
+    pub mobs: String,
+    pub blocks: String,
+    #[serde(default = "TurnTimeout::disabled")]
+    turn_timeout: TurnTimeout,
+}
+
+// how long players get to act in battle before their turn is passed for them
+#[derive(Debug, Deserialize, Clone)]
+pub struct TurnTimeout {
+    pub secs: u64,
+    // timeouts in a row before a player forfeits the battle, 0 means never
+    #[serde(default = "zero_u64")]
+    pub forfeit_after: u64,
+}
+
+impl TurnTimeout {
+    fn disabled() -> Self {
+        Self {
+            secs: u64::MAX,
+            forfeit_after: 0,
+        }
+    }
+}
+
+fn read_jacl<T: DeserializeOwned>(path: &str, errors: &mut Vec<Error>) -> Option<T> {
+    let read = || -> Result<T> { Ok(from_str(&fs::read_to_string(path)?)?) };
+    match read() {
+        Ok(val) => Some(val),
+        Err(e) => {
+            errors.push(anyhow!(format!("{}: {}", path, e)));
+            None
+        }
+    }
+}
+
+fn combine_errors(errors: Vec<Error>) -> Error {
+    let mut text = format!("found {} problem(s) with the game data:", errors.len());
+    for e in errors {
+        text += &format!("\n  {}", e);
+    }
+    anyhow!(text)
 }
 
 type A = (
@@ -178,16 +215,48 @@ type A = (
 );
 
 impl GameMode {
-    fn parse_data(&self) -> Result<A> {
-        let deser = GameDataDeser {
-            terrain: from_str(&fs::read_to_string(&self.terrain)?)?,
-            dmg: from_str(&fs::read_to_string(&self.dmg)?)?,
-            stat: from_str(&fs::read_to_string(&self.stat)?)?,
-            items: from_str(&fs::read_to_string(&self.items)?)?,
-            mob_templates: from_str(&fs::read_to_string(&self.mobs)?)?,
-            blocks: from_str(&fs::read_to_string(&self.blocks)?)?,
-            structures: from_str(&fs::read_to_string(&self.structures)?)?,
-            biomes: from_str(&fs::read_to_string(&self.biomes)?)?,
+    // keeps going after errors so that every problem with the data can be reported at once
+    fn parse_data(&self) -> std::result::Result<A, Vec<Error>> {
+        let mut errors = Vec::new();
+        let terrain = read_jacl(&self.terrain, &mut errors);
+        let dmg = read_jacl(&self.dmg, &mut errors);
+        let stat = read_jacl(&self.stat, &mut errors);
+        let items = read_jacl(&self.items, &mut errors);
+        let mob_templates = read_jacl(&self.mobs, &mut errors);
+        let blocks = read_jacl(&self.blocks, &mut errors);
+        let structures = read_jacl(&self.structures, &mut errors);
+        let biomes = read_jacl(&self.biomes, &mut errors);
+        let deser = match (
+            terrain,
+            dmg,
+            stat,
+            items,
+            mob_templates,
+            blocks,
+            structures,
+            biomes,
+        ) {
+            (
+                Some(terrain),
+                Some(dmg),
+                Some(stat),
+                Some(items),
+                Some(mob_templates),
+                Some(blocks),
+                Some(structures),
+                Some(biomes),
+            ) => GameDataDeser {
+                terrain,
+                dmg,
+                stat,
+                items,
+                mob_templates,
+                blocks,
+                structures,
+                biomes,
+            },
+            // everything after this needs all the files
+            _ => return Err(errors),
         };
 
         let dmg_types = deser.dmg.into_iter().map(|x| DmgType(x)).collect();
@@ -197,10 +266,12 @@ impl GameMode {
         let mut items = HashMap::new();
         for (name, v) in deser.items {
             let name = ItemName::from(name);
-            items.insert(
-                name.clone(),
-                v.into_item(&dmg_types, &stat_types, &item_names, name)?,
-            );
+            match v.into_item(&dmg_types, &stat_types, &item_names, name.clone()) {
+                Ok(item) => {
+                    items.insert(name, item);
+                }
+                Err(e) => errors.push(anyhow!(format!("{}: item {:?}: {}", self.items, name.0, e))),
+            }
         }
 
         let mut mob_names = HashSet::new();
@@ -208,10 +279,12 @@ impl GameMode {
         for (name, v) in deser.mob_templates {
             let name = MobName::from(name);
             mob_names.insert(name.clone());
-            mob_templates.insert(
-                name.clone(),
-                v.into_mobtemplate(&dmg_types, &item_names, &stat_types, name)?,
-            );
+            match v.into_mobtemplate(&dmg_types, &item_names, &stat_types, name.clone()) {
+                Ok(mob_template) => {
+                    mob_templates.insert(name, mob_template);
+                }
+                Err(e) => errors.push(anyhow!(format!("{}: mob {:?}: {}", self.mobs, name.0, e))),
+            }
         }
 
         let block_names = deser
@@ -233,18 +306,27 @@ impl GameMode {
             })
             .collect();
 
-        let folder = Path::new(&self.structures)
-            .parent()
-            .ok_or(anyhow!("invalid path"))?;
+        let folder = match Path::new(&self.structures).parent() {
+            Some(folder) => folder,
+            None => {
+                errors.push(anyhow!(format!("{}: invalid path", self.structures)));
+                return Err(errors);
+            }
+        };
         let mut structure_names = HashSet::new();
         let mut structures = HashMap::new();
         for (name, v) in deser.structures {
             let name = StructureName(name);
             structure_names.insert(name.clone());
-            structures.insert(
-                name.clone(),
-                v.get_structures(folder, &block_names, &mob_names, name)?,
-            );
+            match v.get_structures(folder, &block_names, &mob_names, name.clone()) {
+                Ok(structure) => {
+                    structures.insert(name, structure);
+                }
+                Err(e) => errors.push(anyhow!(format!(
+                    "{}: structure {:?}: {}",
+                    self.structures, name.0, e
+                ))),
+            }
         }
 
         let mut biomes = HashMap::new();
@@ -252,14 +334,28 @@ impl GameMode {
         for (name, v) in deser.biomes {
             let name = BiomeName(name);
             biome_names.insert(name.clone());
-            biomes.insert(
-                name.clone(),
-                v.into_biome(name, &structure_names, &block_names)?,
-            );
+            match v.into_biome(name.clone(), &structure_names, &block_names) {
+                Ok(biome) => {
+                    biomes.insert(name, biome);
+                }
+                Err(e) => errors.push(anyhow!(format!(
+                    "{}: biome {:?}: {}",
+                    self.biomes, name.0, e
+                ))),
+            }
         }
 
-        let terrain = deser.terrain.into_terrain(&biome_names, &structure_names)?;
+        let terrain = match deser.terrain.into_terrain(&biome_names, &structure_names) {
+            Ok(terrain) => terrain,
+            Err(e) => {
+                errors.push(anyhow!(format!("{}: {}", self.terrain, e)));
+                return Err(errors);
+            }
+        };
 
+        if errors.len() > 0 {
+            return Err(errors);
+        }
         Ok((
             terrain,
             dmg_types,
@@ -282,7 +378,7 @@ impl GameMode {
             blocks_,
             biomes_,
             structures_,
-        ) = self.parse_data()?;
+        ) = self.parse_data().map_err(combine_errors)?;
 
         GameData::new(
             terrain_,
@@ -293,6 +389,7 @@ impl GameMode {
             blocks_,
             biomes_,
             structures_,
+            self.turn_timeout.clone(),
             None,
             None,
         )
@@ -312,7 +409,7 @@ impl GameMode {
             blocks_,
             biomes_,
             structures_,
-        ) = self.parse_data()?;
+        ) = self.parse_data().map_err(combine_errors)?;
 
         GameData::new(
             terrain_,
@@ -323,6 +420,7 @@ impl GameMode {
             blocks_,
             biomes_,
             structures_,
+            self.turn_timeout.clone(),
             Some(block_names),
             Some(mob_names),
         )
@@ -353,10 +451,12 @@ pub struct GameData {
     pub stat: HashSet<StatType>,
     pub structures: HashMap<StructureName, Vec<Structure>>,
     pub items: HashMap<ItemName, Item>,
+    pub turn_timeout: TurnTimeout,
     pub biomes: IDMap<u8, BiomeName, Biome>,
     pub mob_templates: IDMap<MobU16, MobName, MobTemplate>,
     pub blocks: IDMap<u8, BlockName, Block>,
-    pub init_packet: Packet,
+    // what the client needs to load the game's images, sent when it connects
+    pub init_data: serde_json::Value,
     pub mob_id_to_img_id: HashMap<u16, u8>,
     pub block_id_to_img_id: HashMap<u8, u8>,
 }
@@ -383,23 +483,46 @@ fn get_idmap<A: Eq + Debug + Hash + Copy, B: Hash + Eq + Clone, C>(
     })
 }
 
-fn get_idmap_from_names<A: Eq + Debug + Hash + Copy, B: Hash + Eq + Clone + From<String>, C>(
+// ids are handed out in the order of names, with anything that isn't in names
+// (added to the game data since the names were saved) getting the next free ids
+fn get_idmap_from_names<
+    A: Eq + Debug + Hash + Copy,
+    B: Hash + Eq + Clone + Debug + From<String>,
+    C,
+>(
     map: HashMap<B, C>,
     names: Vec<String>,
     increment: fn(A) -> A,
     start: A,
-) -> IDMap<A, B, C> {
+    max: A,
+) -> Result<IDMap<A, B, C>> {
     let mut id_map = BiMap::new();
     let mut max_id = start;
     for name in names {
-        id_map.insert(max_id, name.into());
+        let name = B::from(name);
+        if !map.contains_key(&name) {
+            return Err(anyhow!(format!(
+                "{:?} has an id in the world but isn't in the game data",
+                name
+            )));
+        }
+        id_map.insert(max_id, name);
         max_id = increment(max_id);
     }
-    IDMap {
+    for name in map.keys() {
+        if !id_map.contains_right(name) {
+            if max_id == max {
+                return Err(anyhow!(format!("number of ids cannot exceed {:?}", max)));
+            }
+            id_map.insert(max_id, name.clone());
+            max_id = increment(max_id);
+        }
+    }
+    Ok(IDMap {
         name_to_item: map,
         id_to_name: id_map,
         max_id,
-    }
+    })
 }
 
 impl GameData {
@@ -412,6 +535,7 @@ impl GameData {
         blocks: HashMap<BlockName, Block>,
         biomes: HashMap<BiomeName, Biome>,
         structures: HashMap<StructureName, Vec<Structure>>,
+        turn_timeout: TurnTimeout,
         block_names: Option<Vec<String>>,
         mob_names: Option<Vec<String>>,
     ) -> Result<Self> {
@@ -421,7 +545,8 @@ impl GameData {
                 mob_names,
                 |x: MobU16| MobU16(x.0 + 1),
                 MobU16(0),
-            )
+                MobU16::empty(),
+            )?
         } else {
             get_idmap(
                 mob_templates,
@@ -459,7 +584,7 @@ impl GameData {
         let mob_images_to_load: Vec<String> = mob_images_to_load.into_iter().collect();
 
         let blocks = if let Some(block_names) = block_names {
-            get_idmap_from_names(blocks, block_names, |x| x + 1, 0u8)
+            get_idmap_from_names(blocks, block_names, |x| x + 1, 0u8, u8::MAX)?
         } else {
             get_idmap(blocks, |x| x + 1, 0u8, u8::MAX)?
         };
@@ -505,20 +630,35 @@ impl GameData {
             mob_templates,
             biomes: get_idmap(biomes, |x| x + 1, 0u8, u8::MAX)?,
             structures,
-            init_packet: Packet {
-                p_type: PacketType::Init,
-                content: serde_json::to_string(&Content {
-                    mob_img_id_to_img,
-                    mob_images_to_load,
-                    block_img_id_to_img
-                })?
-                .into_bytes(),
-            },
+            turn_timeout,
+            init_data: serde_json::to_value(&Content {
+                mob_img_id_to_img,
+                mob_images_to_load,
+                block_img_id_to_img
+            })?,
             mob_id_to_img_id,
             block_id_to_img_id
         })
     }
 
+    // block names in id order, for keeping ids the same across saves and reloads
+    pub fn block_names(&self) -> Vec<String> {
+        let mut block_names = vec!["".to_string(); self.blocks.max_id as usize];
+        for (k, v) in &self.blocks.id_to_name {
+            block_names[*k as usize] = v.0.clone();
+        }
+        block_names
+    }
+
+    // mob names in id order, for keeping ids the same across saves and reloads
+    pub fn mob_names(&self) -> Vec<String> {
+        let mut mob_names = vec!["".to_string(); self.mob_templates.max_id.0 as usize];
+        for (k, v) in &self.mob_templates.id_to_name {
+            mob_names[k.0 as usize] = v.0.clone();
+        }
+        mob_names
+    }
+
     pub fn get_mob_name_by_id(&self, id: MobU16) -> Result<MobName> {
         Ok(self
             .mob_templates
@@ -227,9 +227,19 @@ impl Ability {
     pub fn counter(&self, g: &GameData) -> HashMap<DmgType, f64> {
         default_empty_fields(&self.counter, 0.0, &g.dmg)
     }
+
+    // damage types this ability mentions explicitly
+    pub fn dmg_types(&self) -> HashSet<DmgType> {
+        self.damage
+            .keys()
+            .chain(self.block.keys())
+            .chain(self.counter.keys())
+            .cloned()
+            .collect()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Buffs {
     defense_buffs: HashMap<DmgType, f64>,
     attack_buffs: HashMap<DmgType, f64>,
@@ -248,6 +258,15 @@ impl Buffs {
     pub fn stat_buffs(&self, g: &GameData) -> HashMap<StatType, f64> {
         default_empty_fields(&self.stat_buffs, 1.0, &g.stat)
     }
+
+    // damage types these buffs mention explicitly
+    pub fn dmg_types(&self) -> HashSet<DmgType> {
+        self.defense_buffs
+            .keys()
+            .chain(self.attack_buffs.keys())
+            .cloned()
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -0,0 +1,435 @@
+// cross-reference checks over game data that loaded fine,
+// for mistakes that don't stop the game from running
+use super::{
+    gamedata::{BiomeName, BlockName, DmgType, GameData, GameMode, ItemName, MobName},
+    mobtemplate::InventoryBuilder,
+    structures::Structure,
+};
+use crate::player::STARTING_ITEMS;
+use std::{collections::HashSet, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Lint {
+    pub severity: Severity,
+    pub file: String,
+    // what the problem is with, e.g. mob "goblin"
+    pub name: String,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}: {}: {}: {}",
+            severity, self.file, self.name, self.message
+        )
+    }
+}
+
+pub fn lint(m: &GameMode, g: &GameData) -> Vec<Lint> {
+    let mut linter = Linter {
+        m,
+        g,
+        lints: Vec::new(),
+    };
+    let world_blocks = linter.world_blocks();
+    let placed_mobs = linter.placed_mobs();
+    let natural_mobs = linter.natural_mobs(&world_blocks);
+
+    linter.check_blocks();
+    linter.check_mobs(&placed_mobs, &natural_mobs);
+    let spawned_mobs = placed_mobs.union(&natural_mobs).cloned().collect();
+    linter.check_items(&world_blocks, &spawned_mobs);
+    linter.check_dmg_types();
+    linter.check_biomes();
+    linter.check_structures();
+
+    let mut lints = linter.lints;
+    lints.sort_by(|a, b| {
+        (a.severity, &a.file, &a.name, &a.message).cmp(&(b.severity, &b.file, &b.name, &b.message))
+    });
+    lints
+}
+
+struct Linter<'a> {
+    m: &'a GameMode,
+    g: &'a GameData,
+    lints: Vec<Lint>,
+}
+
+impl<'a> Linter<'a> {
+    fn push(&mut self, severity: Severity, file: &str, name: String, message: String) {
+        self.lints.push(Lint {
+            severity,
+            file: file.into(),
+            name,
+            message,
+        });
+    }
+
+    fn used_biomes(&self) -> HashSet<&BiomeName> {
+        self.g
+            .terrain
+            .full_passes
+            .iter()
+            .flat_map(|full_pass| full_pass.layers.iter().flatten())
+            .map(|pass| &pass.biome)
+            .collect()
+    }
+
+    // structures that have at least one biome to spawn in
+    fn spawned_structures(&self) -> Vec<&Structure> {
+        self.g
+            .terrain
+            .structure_spawn
+            .iter()
+            .filter(|(_, spawn)| spawn.biomes.len() > 0 && spawn.cap > 0)
+            .filter_map(|(name, _)| self.g.structures.get(name))
+            .flatten()
+            .collect()
+    }
+
+    // every block that can end up in a generated world
+    fn world_blocks(&self) -> HashSet<BlockName> {
+        let mut blocks = HashSet::new();
+        // the world starts out filled with the first block
+        if let Ok(block) = self.g.get_block_name_by_id(0) {
+            blocks.insert(block);
+        }
+        for biome in self.used_biomes() {
+            if let Some(biome) = self.g.biomes.name_to_item.get(biome) {
+                for cutoff in biome.terrain_pass.iter().chain(biome.biome_pass.iter()) {
+                    blocks.insert(cutoff.then.clone());
+                }
+            }
+        }
+        for structure in self.spawned_structures() {
+            blocks.extend(structure.block_names());
+        }
+
+        // players can break blocks into other ones
+        let mut to_check: Vec<BlockName> = blocks.iter().cloned().collect();
+        while let Some(block) = to_check.pop() {
+            let break_into = self
+                .g
+                .blocks
+                .name_to_item
+                .get(&block)
+                .and_then(|block| block.break_into.clone());
+            if let Some(break_into) = break_into {
+                if blocks.insert(break_into.clone()) {
+                    to_check.push(break_into);
+                }
+            }
+        }
+        blocks
+    }
+
+    fn placed_mobs(&self) -> HashSet<MobName> {
+        self.spawned_structures()
+            .into_iter()
+            .flat_map(|structure| structure.mob_names())
+            .collect()
+    }
+
+    // mobs that can spawn on their own on some block in the world
+    fn natural_mobs(&self, world_blocks: &HashSet<BlockName>) -> HashSet<MobName> {
+        let spawn_blocks: Vec<_> = world_blocks
+            .iter()
+            .filter_map(|block| self.g.blocks.name_to_item.get(block))
+            .filter(|block| block.mob_spawn.spawn_chance > 0.0)
+            .collect();
+        self.g
+            .mob_templates
+            .name_to_item
+            .values()
+            .filter(|mob| !mob.dont_spawn)
+            .filter(|mob| {
+                spawn_blocks
+                    .iter()
+                    .any(|block| block.mob_spawn.allows(&mob.tags))
+            })
+            .map(|mob| mob.name.clone())
+            .collect()
+    }
+
+    fn check_blocks(&mut self) {
+        let file = &self.m.blocks;
+        for (name, block) in &self.g.blocks.name_to_item {
+            let name = format!("block {:?}", name.0);
+            if let Some(break_into) = &block.break_into {
+                if !self.g.blocks.name_to_item.contains_key(break_into) {
+                    self.push(
+                        Severity::Error,
+                        file,
+                        name.clone(),
+                        format!("breaks into {:?}, which isn't a block", break_into.0),
+                    );
+                }
+            }
+            if let Some(drop) = &block.drop {
+                if !self.g.items.contains_key(drop) {
+                    self.push(
+                        Severity::Error,
+                        file,
+                        name.clone(),
+                        format!("drops {:?}, which isn't an item", drop.0),
+                    );
+                }
+                if block.break_into.is_none() {
+                    self.push(
+                        Severity::Warning,
+                        file,
+                        name,
+                        format!(
+                            "drops {:?} but has no break_into, so it can't be broken to drop it",
+                            drop.0
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    fn check_mobs(&mut self, placed: &HashSet<MobName>, natural: &HashSet<MobName>) {
+        let file = &self.m.mobs;
+        for (name, mob) in &self.g.mob_templates.name_to_item {
+            let lint_name = format!("mob {:?}", name.0);
+            if mob.dont_spawn && !placed.contains(name) {
+                self.push(
+                    Severity::Warning,
+                    file,
+                    lint_name.clone(),
+                    "has dont_spawn set and no structure places it, so it never appears".into(),
+                );
+            } else if !mob.dont_spawn && !placed.contains(name) && !natural.contains(name) {
+                self.push(
+                    Severity::Warning,
+                    file,
+                    lint_name.clone(),
+                    "no block in any biome can spawn it and no structure places it".into(),
+                );
+            }
+            self.check_inventory(&lint_name, "tools", &mob.tools);
+            self.check_inventory(&lint_name, "drops", &mob.drops);
+        }
+    }
+
+    fn check_inventory(&mut self, name: &str, field: &str, inventory: &InventoryBuilder) {
+        let file = &self.m.mobs;
+        if inventory.min > inventory.max {
+            self.push(
+                Severity::Error,
+                file,
+                name.into(),
+                format!(
+                    "{} has a min of {} which is more than its max of {}",
+                    field, inventory.min, inventory.max
+                ),
+            );
+        }
+        if inventory.tags.len() > 0 {
+            let has_match = self
+                .g
+                .items
+                .values()
+                .any(|item| item.tags.iter().any(|tag| inventory.tags.contains(tag)));
+            if inventory.max > 0 && !has_match {
+                self.push(
+                    Severity::Error,
+                    file,
+                    name.into(),
+                    format!("no item has any of the {} tags {:?}", field, inventory.tags),
+                );
+            }
+        } else if inventory.items.len() > 0 {
+            // a sum under 1 is how a pick is allowed to come up empty,
+            // over 1 means the last entries are less likely than they were written as
+            let sum: f64 = inventory.probs.iter().sum();
+            if sum > 1.0 + 1e-6 {
+                self.push(
+                    Severity::Error,
+                    file,
+                    name.into(),
+                    format!("{} probs add up to {}, which is more than 1", field, sum),
+                );
+            } else if sum < 1.0 - 1e-6 {
+                self.push(
+                    Severity::Warning,
+                    file,
+                    name.into(),
+                    format!(
+                        "{} probs add up to {}, so {:.2} of picks give nothing",
+                        field,
+                        sum,
+                        1.0 - sum
+                    ),
+                );
+            }
+        }
+    }
+
+    // walks everything that gives players items, starting from what they spawn with
+    fn check_items(&mut self, world_blocks: &HashSet<BlockName>, spawned_mobs: &HashSet<MobName>) {
+        let mut reachable: HashSet<ItemName> = STARTING_ITEMS
+            .iter()
+            .map(|item| ItemName::from(item.to_string()))
+            .collect();
+        for block in world_blocks {
+            if let Some(block) = self.g.blocks.name_to_item.get(block) {
+                if let (Some(drop), Some(_)) = (&block.drop, &block.break_into) {
+                    reachable.insert(drop.clone());
+                }
+            }
+        }
+        let mobs: Vec<_> = spawned_mobs
+            .iter()
+            .filter_map(|mob| self.g.mob_templates.name_to_item.get(mob))
+            .collect();
+        for mob in &mobs {
+            let drops = &mob.drops;
+            if drops.tags.len() > 0 {
+                for (name, item) in &self.g.items {
+                    if item.tags.iter().any(|tag| drops.tags.contains(tag)) {
+                        reachable.insert(name.clone());
+                    }
+                }
+            } else {
+                for item in drops.items.iter().flatten() {
+                    reachable.insert(item.name.clone());
+                }
+            }
+            for item in &drops.required_items {
+                reachable.insert(item.name.clone());
+            }
+        }
+
+        loop {
+            let mut found = Vec::new();
+            for (name, item) in &self.g.items {
+                if !reachable.contains(name) {
+                    continue;
+                }
+                for ability in item.abilities.values() {
+                    if ability.require_items.keys().all(|i| reachable.contains(i)) {
+                        found.extend(ability.make_items.keys().cloned());
+                    }
+                }
+            }
+            for mob in &mobs {
+                for trade in &mob.trades {
+                    if reachable.contains(&trade.in_item) {
+                        found.push(trade.out_item.clone());
+                    }
+                }
+            }
+            let len = reachable.len();
+            reachable.extend(found);
+            if reachable.len() == len {
+                break;
+            }
+        }
+
+        let file = &self.m.items;
+        for name in self.g.items.keys() {
+            if !reachable.contains(name) {
+                self.push(
+                    Severity::Warning,
+                    file,
+                    format!("item {:?}", name.0),
+                    "can't be obtained from any block, mob, trade or ability".into(),
+                );
+            }
+        }
+    }
+
+    fn check_dmg_types(&mut self) {
+        let mut used: HashSet<DmgType> = HashSet::new();
+        for item in self.g.items.values() {
+            used.extend(item.buffs.dmg_types());
+            for ability in item.abilities.values() {
+                used.extend(ability.dmg_types());
+            }
+        }
+        for mob in self.g.mob_templates.name_to_item.values() {
+            for ability in mob.abilities.values() {
+                used.extend(ability.dmg_types());
+            }
+        }
+
+        let file = &self.m.dmg;
+        for dmg in &self.g.dmg {
+            if !used.contains(dmg) {
+                self.push(
+                    Severity::Warning,
+                    file,
+                    format!("damage type {:?}", dmg.0),
+                    "isn't used by any item or mob".into(),
+                );
+            }
+        }
+    }
+
+    fn check_biomes(&mut self) {
+        let used = self.used_biomes();
+        let unused: Vec<String> = self
+            .g
+            .biomes
+            .name_to_item
+            .keys()
+            .filter(|name| !used.contains(name))
+            .map(|name| name.0.clone())
+            .collect();
+        let file = &self.m.biomes;
+        for name in unused {
+            self.push(
+                Severity::Warning,
+                file,
+                format!("biome {:?}", name),
+                "isn't used by any terrain pass".into(),
+            );
+        }
+    }
+
+    fn check_structures(&mut self) {
+        let file = &self.m.structures;
+        for (name, structures) in &self.g.structures {
+            let lint_name = format!("structure {:?}", name.0);
+            if !self.g.terrain.structure_spawn.contains_key(name) {
+                self.push(
+                    Severity::Warning,
+                    file,
+                    lint_name.clone(),
+                    "isn't in structure_spawn in the terrain, so it never spawns".into(),
+                );
+            }
+            for structure in structures {
+                for color in structure.unused_colors() {
+                    self.push(
+                        Severity::Warning,
+                        file,
+                        lint_name.clone(),
+                        format!(
+                            "mapping color ({}, {}, {}) doesn't appear in {}",
+                            color.r,
+                            color.g,
+                            color.b,
+                            structure.source()
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
@@ -169,6 +169,37 @@ impl Quotes {
     }
 }
 
+fn default_move_chance() -> f64 {
+    0.5
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Movement {
+    // how far (in blocks) the mob will wander from where it started, 0 means it doesn't wander
+    #[serde(default = "zero_u64")]
+    pub wander_radius: u64,
+    // the mob walks towards players within this distance, 0 means it never chases
+    #[serde(default = "zero_u64")]
+    pub chase_dist: u64,
+    // probability that the mob moves on any given tick
+    #[serde(default = "default_move_chance")]
+    pub move_chance: f64,
+}
+
+impl Movement {
+    fn idle() -> Self {
+        Self {
+            wander_radius: 0,
+            chase_dist: 0,
+            move_chance: default_move_chance(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        (self.wander_radius == 0 && self.chase_dist == 0) || self.move_chance <= 0.0
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct MobTemplateDeser {
     #[serde(default = "zero_i64")]
@@ -197,6 +228,8 @@ pub struct MobTemplateDeser {
     tags: Vec<String>,
     #[serde(default = "false_bool")]
     dont_spawn: bool,
+    #[serde(default = "Movement::idle")]
+    movement: Movement,
 }
 
 #[derive(Debug, Clone)]
@@ -215,6 +248,7 @@ pub struct MobTemplate {
     pub trades: Vec<Trade>,
     pub tags: Vec<String>,
     pub dont_spawn: bool,
+    pub movement: Movement,
 }
 
 impl MobTemplateDeser {
@@ -251,6 +285,7 @@ impl MobTemplateDeser {
             trades,
             tags: self.tags,
             dont_spawn: self.dont_spawn,
+            movement: self.movement,
         })
     }
 }
@@ -4,6 +4,7 @@ mod serde_defaults;
 pub mod block;
 pub mod gamedata;
 pub mod item;
+pub mod lint;
 pub mod mobtemplate;
 pub mod structures;
 pub mod terrain;
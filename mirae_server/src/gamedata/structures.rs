@@ -2,11 +2,7 @@ use super::{
     gamedata::{BlockName, GameData, MobName, StructureName},
     serde_defaults::*,
 };
-use crate::{
-    rgb::RGB,
-    vector3::Vector3,
-    world::{Map, MobU16},
-};
+use crate::{map::Map, rgb::RGB, vector3::Vector3, world::MobU16};
 use anyhow::{anyhow, Result};
 use image::io::Reader as ImageReader;
 use rand::{prelude::StdRng, Rng};
@@ -55,7 +51,9 @@ impl StructureDeser {
         let mut i = 0;
         let mut rgb_to_block = HashMap::new();
         let mut mob_and_prob = Vec::new();
+        let mut colors = Vec::new();
         for sm in self.mapping {
+            colors.push(sm.color);
             let mut vec = Vec::new();
             let mut sum = 0.0;
             for v in sm.mobs {
@@ -84,9 +82,10 @@ impl StructureDeser {
             i += 1;
         }
         let mob_and_prob = Arc::new(mob_and_prob);
+        let colors = Arc::new(colors);
 
         for source in self.sources {
-            let path = folder.join(source);
+            let path = folder.join(&source);
             let image = ImageReader::open(path)?.decode()?;
             let image = image.as_rgba8().ok_or(anyhow!("bad image format!"))?;
             let x = image.width() as usize;
@@ -123,7 +122,9 @@ impl StructureDeser {
             }
             v.push(Structure {
                 structure_name: structure_name.clone(),
+                source,
                 mobgen: mob_and_prob.clone(),
+                colors: colors.clone(),
                 blocks: block_map,
                 mobs: mob_map,
             });
@@ -139,12 +140,49 @@ struct MobAndProb {
 
 pub struct Structure {
     structure_name: StructureName,
+    // the image this structure was read from
+    source: String,
     mobgen: Arc<Vec<Vec<MobAndProb>>>,
+    // the color of each mapping, in the same order as mobgen
+    colors: Arc<Vec<RGB>>,
     blocks: Map<Option<BlockName>>,
     mobs: Map<Option<usize>>,
 }
 
 impl Structure {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // colors from the mapping that never show up in the source image
+    pub fn unused_colors(&self) -> Vec<RGB> {
+        let used = self.used_mappings();
+        (0..self.colors.len())
+            .filter(|i| !used.contains(i))
+            .map(|i| self.colors[i])
+            .collect()
+    }
+
+    pub fn block_names(&self) -> HashSet<BlockName> {
+        (0..self.blocks.dim.dim() as usize)
+            .filter_map(|i| self.blocks.direct_get(i))
+            .collect()
+    }
+
+    // every mob this structure can place when it spawns
+    pub fn mob_names(&self) -> HashSet<MobName> {
+        self.used_mappings()
+            .into_iter()
+            .flat_map(|i| self.mobgen[i].iter().map(|m| m.mob.clone()))
+            .collect()
+    }
+
+    fn used_mappings(&self) -> HashSet<usize> {
+        (0..self.mobs.dim.dim() as usize)
+            .filter_map(|i| self.mobs.direct_get(i))
+            .collect()
+    }
+
     pub fn spawn_at(
         &self,
         loc: Vector3,
@@ -14,6 +14,8 @@ pub struct TerrainDeser {
     pub biome_octaves: u8,
     pub full_passes: Vec<TerrainPassDeser>,
     pub structure_spawn: HashMap<String, StructureSpawnDeser>,
+    #[serde(default = "MobRespawn::disabled")]
+    pub mob_respawn: MobRespawn,
 }
 
 #[derive(Debug)]
@@ -23,6 +25,34 @@ pub struct Terrain {
     pub biome_octaves: u8,
     pub full_passes: Vec<TerrainPass>,
     pub structure_spawn: HashMap<StructureName, StructureSpawn>,
+    pub mob_respawn: MobRespawn,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MobRespawn {
+    // seconds between respawn passes
+    #[serde(default = "u64_max")]
+    pub delay: u64,
+    // maximum number of mobs respawned in a single pass, 0 disables respawning
+    #[serde(default = "zero_u64")]
+    pub per_pass: u64,
+    // respawning stops once the world has this many mobs
+    #[serde(default = "u64_max")]
+    pub cap: u64,
+    // mobs never respawn closer than this to an online player
+    #[serde(default = "zero_u64")]
+    pub min_player_dist: u64,
+}
+
+impl MobRespawn {
+    fn disabled() -> Self {
+        Self {
+            delay: u64::MAX,
+            per_pass: 0,
+            cap: u64::MAX,
+            min_player_dist: 0,
+        }
+    }
 }
 
 impl TerrainDeser {
@@ -46,6 +76,7 @@ impl TerrainDeser {
             biome_octaves: self.biome_octaves,
             full_passes,
             structure_spawn,
+            mob_respawn: self.mob_respawn,
         })
     }
 }
@@ -1,59 +1,120 @@
 #![allow(dead_code)]
-use actions::{dispatch, get_mut, get_two_mut, ActionData};
+use actions::{dispatch, get_entities, get_mut, ActionData};
+use admin::AdminData;
 use anyhow::{anyhow, Error, Result};
-use combat::{BattleHandle, BattleMap, EntityType, ID};
+use auth::LoginAttempts;
+use cli::{Args, Command, WorldSource};
+use combat::{broadcast, combatant, BattleHandle, BattleMap, EntityType, ID};
 use display::{Bounds, Image};
 use entity::Entity;
-use gamedata::gamedata::{GameData, GameMode};
+use gamedata::{
+    gamedata::{GameData, GameMode},
+    lint::{lint, Severity},
+};
 use image::{ImageBuffer, Rgb};
-use io::{BufRead, Read, Write};
+use io::BufRead;
 use player::Player;
+use player_ids::PlayerIds;
 use playerout::PlayerOut;
+use protocol::{Event, Protocol};
+use save::Load;
 use rand::{prelude::StdRng, thread_rng, Rng, SeedableRng};
 use serde_jacl::{
     de::from_str,
     structs::{Literal, Number},
 };
+use sight::Sight;
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     env, fs, io,
-    iter::FromIterator,
     net::TcpStream,
-    sync::{Arc, RwLock},
+    process,
+    sync::{Arc, OnceLock, RwLock},
     thread::{self, spawn},
     time::{self, Instant},
 };
 use vector3::Vector3;
+use views::ViewTracker;
 use websocket::{
     sync::{Client, Server},
     OwnedMessage,
 };
 use world::World;
 
-use crossbeam::channel::{unbounded, Sender};
-use fs::{File, OpenOptions};
-use rgb::RGB;
+use crossbeam::channel::{select, unbounded, RecvTimeoutError, Sender};
 use time::Duration;
 
 mod actions;
+mod admin;
+mod auth;
+mod chat;
+mod cli;
 mod combat;
 mod display;
 mod entity;
 mod gamedata;
 mod inventory;
+mod map;
 mod mob;
 mod noise;
 mod player;
+mod player_ids;
 mod playerout;
+mod protocol;
+mod remote;
 mod rgb;
+mod save;
+mod sight;
 mod stat;
+mod trading;
 mod vector3;
+mod views;
 mod world;
 
-const PLAYER_SAVE_FOLDER: &str = "save/player_save";
-const WORLD_SAVE_FOLDER: &str = "save/world_save";
+// set once from the command line, before any saves are read or written
+static SAVE_DIR: OnceLock<String> = OnceLock::new();
+static KEEP_SAVES: OnceLock<usize> = OnceLock::new();
+
+fn save_dir() -> &'static str {
+    SAVE_DIR
+        .get()
+        .map(|dir| dir.as_str())
+        .unwrap_or(cli::DEFAULT_SAVE_DIR)
+}
+
+fn keep_saves() -> usize {
+    KEEP_SAVES.get().cloned().unwrap_or(0)
+}
+
+pub fn player_save_folder() -> String {
+    format!("{}/player_save", save_dir())
+}
+
+pub fn player_auth_folder() -> String {
+    format!("{}/player_auth", save_dir())
+}
+
+pub fn world_save_folder() -> String {
+    format!("{}/world_save", save_dir())
+}
+
+pub fn admins_file() -> String {
+    format!("{}/admins", save_dir())
+}
+
+pub fn player_ids_file() -> String {
+    format!("{}/player_ids", save_dir())
+}
+
+fn admin_token_file() -> String {
+    format!("{}/admin_token", save_dir())
+}
+
 const DEBUG_BLOCK_SIZE: u32 = 10;
-const TICK: u128 = 500;
+const TICK: Duration = Duration::from_millis(500);
+const PLAYER_SAVE: Duration = Duration::from_secs(10);
+// older copies of player saves are only kept this often, so they reach further back than a minute
+const PLAYER_ROTATE: Duration = Duration::from_secs(600);
 
 fn save_img(image: Image, save_location: &str) -> Result<()> {
     let width = (image.width as u32) * DEBUG_BLOCK_SIZE;
@@ -81,10 +142,10 @@ fn save_img(image: Image, save_location: &str) -> Result<()> {
 
 fn map(
     mut params: VecDeque<Literal>,
-    world: &World,
+    world: &mut World,
     players: &Vec<Option<Player>>,
     g: &GameData,
-) -> Result<()> {
+) -> Result<String> {
     let size;
     let layer;
     let save_location;
@@ -109,17 +170,24 @@ fn map(
         world.blocks().dim.x() as usize,
         world.blocks().dim.y() as usize,
     );
-    let image = Image::new(&world, &players, &g, &bounds, resolution as usize)?;
+    let image = Image::new(
+        &world,
+        &players,
+        &g,
+        &bounds,
+        resolution as usize,
+        &Sight::All,
+    )?;
     save_img(image, &save_location)?;
-    Ok(())
+    Ok(format!("saved the map to {}", save_location))
 }
 
 fn look(
     mut params: VecDeque<Literal>,
-    world: &World,
+    world: &mut World,
     players: &Vec<Option<Player>>,
     g: &GameData,
-) -> Result<()> {
+) -> Result<String> {
     let x;
     let y;
     let z;
@@ -155,208 +223,103 @@ fn look(
 
     let posn = Vector3::new(x as isize, y as isize, z as isize);
     let bounds = Bounds::get_bounds(&world, posn, width as usize, height as usize);
-    let image = Image::new(&world, &players, &g, &bounds, 1)?;
+    let image = Image::new(&world, &players, &g, &bounds, 1, &Sight::All)?;
     save_img(image, &save_location)?;
-    Ok(())
+    Ok(format!("saved the view to {}", save_location))
 }
 
 fn save_world(
     mut params: VecDeque<Literal>,
-    world: &World,
+    world: &mut World,
     _players: &Vec<Option<Player>>,
     g: &GameData,
-) -> Result<()> {
+) -> Result<String> {
     let help = "\"save\" <world_name>";
     let save_location;
     match params.pop_front() {
         Some(Literal::String(s)) => save_location = s,
         _ => return Err(anyhow!(help)),
     }
-    let mut file = File::create(format!("{}/{}", WORLD_SAVE_FOLDER, save_location))?;
-
-    // write seed
-    file.write_all(&world.seed.to_le_bytes())?;
-
-    let mut write_names = |names: Vec<String>| -> Result<()> {
-        // write number of names
-        file.write_all(&(names.len() as u32).to_le_bytes())?;
-        // write strings, null terminated
-        for name in names {
-            file.write_all(name.as_bytes())?;
-            file.write_all(&[0])?;
-        }
-        Ok(())
-    };
-
-    // write block names, then mob names
-    let mut block_names = vec!["".to_string(); g.blocks.max_id as usize];
-    for (k, v) in &g.blocks.id_to_name {
-        block_names[*k as usize] = v.0.clone();
-    }
-    write_names(block_names)?;
-
-    let mut mob_names = vec!["".to_string(); g.mob_templates.max_id.0 as usize];
-    for (k, v) in &g.mob_templates.id_to_name {
-        mob_names[k.0 as usize] = v.0.clone();
-    }
-    write_names(mob_names)?;
-
-    // write world dimensions
-    let dim = world.blocks().dim;
-    file.write_all(&(dim.x() as u16).to_le_bytes())?;
-    file.write_all(&(dim.y() as u16).to_le_bytes())?;
-    file.write_all(&(dim.z() as u16).to_le_bytes())?;
-
-    let size = dim.dim() as usize;
-
-    // write blocks, then mobs, then colors
-    let mut bytes = Vec::new();
-    for i in 0..size {
-        bytes.push(world.blocks().direct_get(i))
-    }
-    for i in 0..size {
-        let data = world.mobs().direct_get(i).0.to_le_bytes();
-        bytes.push(data[0]);
-        bytes.push(data[1]);
-    }
-    for i in 0..size {
-        let data = world.colors().direct_get(i);
-        bytes.push(data.r);
-        bytes.push(data.g);
-        bytes.push(data.b);
-    }
-
-    file.write_all(&bytes)?;
-    Ok(())
+    write_world_save(&save_location, world, g)?;
+    Ok(format!("saved the world as {}", save_location))
 }
 
-pub struct Load {
-    pub seed: u64,
-    pub block_names: Vec<String>,
-    pub mob_names: Vec<String>,
-    pub dim: Vector3,
-    pub blocks: Vec<u8>,
-    pub mobs: Vec<u16>,
-    pub colors: Vec<RGB>,
+fn write_world_save(name: &str, world: &mut World, g: &GameData) -> Result<()> {
+    let mut bytes = Vec::new();
+    save::write_world(&mut bytes, world, g)?;
+    save::write_file(
+        &format!("{}/{}", world_save_folder(), name),
+        &bytes,
+        keep_saves(),
+    )
 }
 
 fn load_world(name: &str) -> Result<Load> {
-    let mut file = File::open(format!("{}/{}", WORLD_SAVE_FOLDER, name))?;
-
-    // read the seed
-    let mut buf = [0; 8];
-    file.read_exact(&mut buf)?;
-    let seed = u64::from_le_bytes(buf);
-
-    let mut read_names = || -> Result<Vec<String>> {
-        let mut buf = [0; 4];
-        file.read_exact(&mut buf)?;
-        let num = u32::from_le_bytes(buf);
-        let mut names = Vec::new();
-
-        for _ in 0..num {
-            let mut string = Vec::new();
-            // read a single null-terminated string,
-            // read byte-by-byte till you see a 0
-            loop {
-                let mut val = [0; 1];
-                file.read_exact(&mut val)?;
-                let val = val[0];
-                if val == 0 {
-                    break;
-                }
-                string.push(val);
-            }
-            let string = String::from_utf8(string)?;
-            names.push(string);
-        }
-        Ok(names)
-    };
-
-    // read block names and mob names
-    let block_names = read_names()?;
-    let mob_names = read_names()?;
-
-    let mut read_u16 = || -> Result<u16> {
-        let mut buf = [0; 2];
-        file.read_exact(&mut buf)?;
-        Ok(u16::from_le_bytes(buf))
-    };
-
-    // read dimensions
-    let dim = Vector3::new(
-        read_u16()? as isize,
-        read_u16()? as isize,
-        read_u16()? as isize,
-    );
-
-    let size = dim.dim() as usize;
-
-    // read blocks, mobs, and colors
-    let mut blocks = vec![0; size];
-    file.read_exact(&mut blocks)?;
-
-    let mut mobs = vec![0; size * 2];
-    file.read_exact(&mut mobs)?;
-    let mobs = mobs
-        .chunks_exact(2)
-        .into_iter()
-        .map(|a| u16::from_le_bytes([a[0], a[1]]))
-        .collect();
+    let bytes = fs::read(format!("{}/{}", world_save_folder(), name))?;
+    save::read_world(&bytes)
+}
 
-    let mut colors = vec![0; size * 3];
-    file.read_exact(&mut colors)?;
-    let colors = colors
-        .chunks_exact(3)
-        .into_iter()
-        .map(|a| RGB::new(a[0], a[1], a[2]))
-        .collect();
+type ServerCommand<'a> =
+    dyn Fn(VecDeque<Literal>, &mut World, &Vec<Option<Player>>, &GameData) -> Result<String> + 'a;
 
-    Ok(Load {
-        seed,
-        block_names,
-        mob_names,
-        dim,
-        blocks,
-        mobs,
-        colors,
-    })
+fn read_gamemode(path: &str) -> Result<GameMode> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| anyhow!(format!("couldn't read gamemode '{}': {}", path, e)))?;
+    from_str(&text).map_err(|e| anyhow!(format!("couldn't parse gamemode '{}': {}", path, e)))
 }
 
-type ServerCommand =
-    dyn Fn(VecDeque<Literal>, &World, &Vec<Option<Player>>, &GameData) -> Result<()>;
+// checks that the gamemode loads and lints it, reporting every problem found with it
+fn validate(args: &Args) -> Result<()> {
+    let m = read_gamemode(&args.gamemode)?;
+    let g = m.into_gamedata()?;
+    let lints = lint(&m, &g);
+    for l in &lints {
+        println!("{}", l);
+    }
+    let errors = lints
+        .iter()
+        .filter(|l| l.severity == Severity::Error)
+        .count();
+    let warnings = lints.len() - errors;
+    if errors > 0 {
+        return Err(anyhow!(format!(
+            "{} has {} error(s) and {} warning(s)",
+            args.gamemode, errors, warnings
+        )));
+    }
+    println!("{} is valid, with {} warning(s)", args.gamemode, warnings);
+    Ok(())
+}
 
-fn init(args: &Vec<String>) -> Result<(GameData, World)> {
-    fs::create_dir_all(WORLD_SAVE_FOLDER)?;
-    fs::create_dir_all(PLAYER_SAVE_FOLDER)?;
+fn init(args: &Args, world_source: &WorldSource) -> Result<(GameData, World)> {
+    fs::create_dir_all(world_save_folder())?;
+    fs::create_dir_all(player_save_folder())?;
+    fs::create_dir_all(player_auth_folder())?;
 
-    let m: GameMode = from_str(&fs::read_to_string("pvp/gamemode.jacl")?)?;
+    let m = read_gamemode(&args.gamemode)?;
 
     let g;
-    let load;
-    if args[2] == "seed" {
-        load = None;
-        g = m.into_gamedata()?;
-    } else if args[2] == "load" {
-        let l = load_world(&args[3])?;
-        g = m.into_gamedata_with_names(l.block_names.clone(), l.mob_names.clone())?;
-        load = Some(l);
-    } else {
-        return Err(anyhow!("2nd argument must be 'seed' or 'load'"));
-    }
-
-    println!("read game data");
-    let start = Instant::now();
-    let world = if let Some(load) = load {
-        let w = World::from_load(load);
-        print!("loaded world");
-        w
-    } else {
-        println!("begin world generation...");
-        let w = World::from_seed(args[3].parse()?, &g);
-        print!("generated world");
-        w
-    }?;
+    let start;
+    let world = match world_source {
+        WorldSource::Seed(seed) => {
+            g = m.into_gamedata()?;
+            println!("read game data");
+            start = Instant::now();
+            println!("begin world generation...");
+            let w = World::from_seed(*seed, &g)?;
+            print!("generated world");
+            w
+        }
+        WorldSource::Load(name) => {
+            let load = load_world(name)?;
+            g = m.into_gamedata_with_names(load.block_names.clone(), load.mob_names.clone())?;
+            println!("read game data");
+            start = Instant::now();
+            let w = World::from_load(load, &g)?;
+            print!("loaded world");
+            w
+        }
+    };
     let duration = start.elapsed();
     println!(" in {:?}", duration);
 
@@ -364,49 +327,153 @@ fn init(args: &Vec<String>) -> Result<(GameData, World)> {
     Ok((g, world))
 }
 
-fn handle_server_commands(
+// re-reads the gamemode and swaps the new game data in for the running game,
+// blocks and mobs keep their ids so the world doesn't need to change
+fn reload(
+    gamemode: &str,
+    g_lock: &RwLock<Arc<GameData>>,
+    world: &mut World,
+    players: &Vec<Option<Player>>,
+    g: &GameData,
+) -> Result<String> {
+    let m = read_gamemode(gamemode)?;
+    let new_g = Arc::new(m.into_gamedata_with_names(g.block_names(), g.mob_names())?);
+    // players and mobs keep buffs and stats for every type, those can't change under them
+    if new_g.dmg != g.dmg {
+        return Err(anyhow!(
+            "the damage types changed, restart the server to use them"
+        ));
+    }
+    if new_g.stat != g.stat {
+        return Err(anyhow!(
+            "the stat types changed, restart the server to use them"
+        ));
+    }
+    // held items can't disappear or change buffs, taking off a worn item undoes the buffs it gave
+    for player in players.iter().flatten() {
+        check_held_items(player, g, &new_g)?;
+    }
+    for mob in world.spawned_mobs() {
+        check_held_items(mob, g, &new_g)?;
+    }
+    *g_lock.write().map_err(gamedata_op)? = new_g.clone();
+    // mobs that are already out use the new abilities and quotes
+    world.refresh_mobs(&new_g);
+
+    // the images for blocks and mobs might have changed
+    for player in players.iter().flatten() {
+        let mut p_out = PlayerOut::new();
+        p_out.add_event(init_event(&new_g, &player.resume_token));
+        player.send(p_out);
+    }
+    Ok(format!("reloaded {}", gamemode))
+}
+
+fn check_held_items(entity: &dyn Entity, g: &GameData, new_g: &GameData) -> Result<()> {
+    let held = entity
+        .inventory()
+        .items()
+        .chain(entity.equipped().items())
+        .chain(entity.worn().items())
+        .chain(entity.drops().items());
+    for item_name in held {
+        let item = new_g.items.get(item_name).ok_or(anyhow!(format!(
+            "{} has '{}', which isn't in the new items",
+            entity.name(),
+            item_name.0
+        )))?;
+        let changed = match g.items.get(item_name) {
+            Some(old) => old.buffs != item.buffs,
+            None => true,
+        };
+        if changed {
+            return Err(anyhow!(format!(
+                "{} has '{}', whose buffs changed, restart the server to use them",
+                entity.name(),
+                item_name.0
+            )));
+        }
+    }
+    Ok(())
+}
+
+// everything console commands need, so they can be run from stdin or the admin socket
+#[derive(Clone)]
+struct Console {
     world: Arc<RwLock<World>>,
     players: Arc<RwLock<Vec<Option<Player>>>>,
-    g: &GameData,
-) -> Result<()> {
-    let mut commands: HashMap<String, &ServerCommand> = HashMap::new();
-    commands.insert("map".into(), &map);
-    commands.insert("look".into(), &look);
-    commands.insert("save".into(), &save_world);
+    g_lock: Arc<RwLock<Arc<GameData>>>,
+    gamemode: String,
+    // admin commands are passed to the logic thread, since they change the game while it's running
+    logic: Sender<ConnA>,
+}
 
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let val = format!("[{}]", line?);
+impl Console {
+    // returns what the command printed
+    fn run(&self, line: &str) -> Result<String> {
+        let reload_gamedata = |params: VecDeque<Literal>,
+                               world: &mut World,
+                               players: &Vec<Option<Player>>,
+                               g: &GameData| {
+            if params.len() > 0 {
+                return Err(anyhow!("\"reload\" takes no arguments"));
+            }
+            reload(&self.gamemode, &self.g_lock, world, players, g)
+        };
+        let mut commands: HashMap<String, &ServerCommand<'_>> = HashMap::new();
+        commands.insert("map".into(), &map);
+        commands.insert("look".into(), &look);
+        commands.insert("save".into(), &save_world);
+        commands.insert("reload".into(), &reload_gamedata);
+
+        let val = format!("[{}]", line);
         let mut params = VecDeque::from(from_str::<Vec<Literal>>(&val)?);
-        let res = match params.pop_front() {
+        match params.pop_front() {
+            Some(Literal::String(s)) if admin::COMMANDS.contains(&s.as_str()) => {
+                params.push_front(Literal::String(s));
+                let (send, recv) = unbounded();
+                self.logic.send(ConnA::Admin((params, send)))?;
+                recv.recv()?
+            }
             Some(Literal::String(s)) => {
                 if let Some(func) = commands.get(&s) {
-                    let world = world.read().map_err(|_| anyhow!("couldn't lock world"))?;
-                    let players = players
-                        .read()
-                        .map_err(|_| anyhow!("couldn't lock players"))?;
-                    func(params, &world, &players, g)
+                    let mut world = self.world.write().map_err(world_op)?;
+                    let players = self.players.read().map_err(players_op)?;
+                    let g = self.g_lock.read().map_err(gamedata_op)?.clone();
+                    func(params, &mut world, &players, &g)
                 } else {
+                    let mut names: Vec<&str> = commands.keys().map(|k| k.as_str()).collect();
+                    names.extend(admin::COMMANDS.iter());
                     Err(anyhow!(format!(
                         "invalid command, choose one of the following {:?}",
-                        commands.keys()
+                        names
                     )))
                 }
             }
             _ => Err(anyhow!("command must start with a string")),
-        };
-        match res {
+        }
+    }
+}
+
+fn handle_server_commands(console: &Console) -> Result<()> {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match console.run(&line?) {
+            Ok(text) => println!("{}", text),
             Err(e) => println!("{:?}", e),
-            _ => {}
         }
     }
     Ok(())
 }
 
 enum ConnA {
-    Init(Sender<(PlayerOut, Option<usize>)>),
+    // a new connection, with the token of the player it wants to take over if it has one
+    Init((Sender<(PlayerOut, Option<usize>)>, Option<String>, Protocol)),
     Run((VecDeque<Literal>, usize)),
-    Quit(usize),
+    // a connection closed, the sender tells it apart from one that took its player over
+    Quit((usize, Sender<(PlayerOut, Option<usize>)>)),
+    // a command from the console, and where to send back its result
+    Admin((VecDeque<Literal>, Sender<Result<String>>)),
 }
 
 fn get_first_availible_id(players: &Vec<Option<Player>>) -> Option<usize> {
@@ -427,6 +494,15 @@ fn players_op<T>(_: T) -> Error {
 fn battle_map_op<T>(_: T) -> Error {
     anyhow!("{} battle map", LOCK_TEXT)
 }
+fn gamedata_op<T>(_: T) -> Error {
+    anyhow!("{} game data", LOCK_TEXT)
+}
+fn ids_op<T>(_: T) -> Error {
+    anyhow!("{} player ids", LOCK_TEXT)
+}
+fn logins_op<T>(_: T) -> Error {
+    anyhow!("{} logins", LOCK_TEXT)
+}
 
 fn world_tick(
     world_arc: Arc<RwLock<World>>,
@@ -438,6 +514,70 @@ fn world_tick(
     let mut players = players_arc.write().map_err(players_op)?;
     let mut battle_map = battle_map_arc.write().map_err(battle_map_op)?;
 
+    // move mobs that aren't in battle around
+    let player_locs: Vec<Vector3> = players
+        .iter()
+        .filter_map(|player| player.as_ref())
+        .map(|player| *player.loc())
+        .collect();
+    world.move_mobs(&player_locs, &battle_map, &g_arc)?;
+
+    // refill areas that have been cleared out
+    world.respawn_mobs(&player_locs, &g_arc)?;
+
+    // pass the turns of players that are taking too long
+    let timeout = Duration::from_secs(g_arc.turn_timeout.secs);
+    let active_battles: Vec<BattleHandle> = battle_map.battles().cloned().collect();
+    for battle_handle in &active_battles {
+        let holder = match battle_map.overdue(battle_handle, timeout)? {
+            Some(holder) if holder.enity_type == EntityType::Player => holder,
+            _ => continue,
+        };
+        let ids = battle_map.participants(holder)?;
+        let mut entities = get_entities(&ids, &mut players, &mut world)?;
+        let name = combatant(&mut entities, holder)?.name();
+
+        let timeouts = battle_map
+            .get_battle_data(holder)?
+            .combat_data(holder)?
+            .timeouts
+            + 1;
+        let forfeit_after = g_arc.turn_timeout.forfeit_after;
+        if forfeit_after > 0 && timeouts >= forfeit_after {
+            let player = combatant(&mut entities, holder)?;
+            player.send_text("you took too long too many times and forfeit the battle!\n".into());
+            player.send_image("none".into());
+            broadcast(
+                &mut entities,
+                holder,
+                format!("{} forfeit the battle!\n", name),
+            );
+            for id in battle_map.leave_battle(holder)? {
+                let entity = combatant(&mut entities, id)?;
+                entity.send_text("the battle is over!\n".into());
+                entity.send_image("none".into());
+            }
+            drop(entities);
+
+            // step back off the mob's square like 'run' does, or the battle starts right up again
+            let player = get_mut(&mut players, holder.id)?;
+            let posn = player.return_posn;
+            player.loc_mut().set(posn);
+        } else {
+            combatant(&mut entities, holder)?
+                .send_text("you took too long, so your turn was passed!\n".into());
+            broadcast(
+                &mut entities,
+                holder,
+                format!("{} took too long and passed\n", name),
+            );
+            battle_map.do_turn(holder, &mut entities, &g_arc)?;
+            battle_map
+                .get_battle_data_mut(holder)?
+                .combat_data_mut(holder)?
+                .timeouts = timeouts;
+        }
+    }
 
     Ok(())
 }
@@ -447,20 +587,23 @@ fn world_logic(
     players_arc: Arc<RwLock<Vec<Option<Player>>>>,
     battle_map_arc: Arc<RwLock<BattleMap>>,
     g_arc: Arc<GameData>,
-) -> Result<()> {
+) -> Result<bool> {
     let mut world = world_arc.write().map_err(world_op)?;
     let mut players = players_arc.write().map_err(players_op)?;
     let mut battle_map = battle_map_arc.write().map_err(battle_map_op)?;
+    // whether anything happened that might need another pass
+    let mut changed = false;
 
-    // start battles with mobs
+    // start battles with mobs, or join the fight if the mob is already in one
     for i in 0..players.len() {
         if let Some(player) = &mut players[i] {
-            if world.has_mob(*player.loc())? && battle_map.get_opponent(player.id()).is_err() {
+            if world.has_mob(*player.loc())? && !battle_map.in_battle(player.id()) {
+                changed = true;
                 let mob_template = world.get_mobtemplate_at(*player.loc(), &g_arc)?;
                 let defender_trades = mob_template.trades.len() > 0;
 
                 let mob = world.get_mob_at_mut(*player.loc(), &g_arc)?;
-                if battle_map.get_opponent(mob.id()).is_err() {
+                if !battle_map.in_battle(mob.id()) {
                     battle_map.init_battle(
                         Box::new(player),
                         Box::new(mob),
@@ -469,112 +612,287 @@ fn world_logic(
                     )?;
                     let mob_name = mob.name();
                     player.send_text(format!("{}: {}\n", mob_name, mob.entrance()?));
-                    player.send_image(mob.display_img.clone());
+                } else {
+                    let ally = battle_map
+                        .enemies(mob.id())?
+                        .into_iter()
+                        .find(|id| id.enity_type == EntityType::Player);
+                    battle_map.join_battle(Box::new(player), mob.id(), ally)?;
+                    player.send_text(format!("you join the fight against {}\n", mob.name()));
                 }
+                player.send_image(mob.display_img.clone());
             }
         }
     }
 
+    trading::cancel_broken_trades(&mut players, &battle_map);
+
     let active_battles: Vec<BattleHandle> = battle_map.battles().cloned().collect();
 
-    // handle pvp battles
-    // just need to handle dying here,
+    // handle everyone dying, and do the turns of mobs.
     // players handle their own turns
     for battle_handle in &active_battles {
-        let battle = battle_map.data_from_handle_mut(battle_handle).unwrap();
-        let (a, b) = battle.ids();
-        let (a, b) = match (a.enity_type, b.enity_type) {
-            (EntityType::Player, EntityType::Player) => get_two_mut(a.id, b.id, &mut players)?,
-            _ => continue,
-        };
-        let players = [a, b];
-        for i in 0..players.len() {
-            let curr = i;
-            let other = (curr + 1) % 2;
-            if players[curr].stats().health() <= 0.0 {
-                let other_name = players[other].name();
-                players[curr].send_text(format!("you were killed by {}\n", other_name));
-                players[curr].respawn(&world, &g_arc)?;
-                let curr_name = players[curr].name();
-                players[other].send_text(format!("you killed {}\n", curr_name));
-                battle_map.end_battle(players[other].id())?;
+        let ids = battle_map.data_from_handle(battle_handle)?.ids().clone();
+        let mut entities = get_entities(&ids, &mut players, &mut world)?;
+
+        let mut dead = Vec::new();
+        for id in &ids {
+            if combatant(&mut entities, *id)?.stats().health() <= 0.0 {
+                dead.push(*id);
             }
         }
-    }
 
-    // handle mob/player battles, including doing the mob's turn
-    for battle_handle in &active_battles {
-        let battle = battle_map.data_from_handle_mut(battle_handle)?;
-        let (a, b) = battle.ids();
-        let (entity, player) = match (a.enity_type, b.enity_type) {
-            (EntityType::Mob, EntityType::Player) => {
-                let mob = world.get_mob_mut(a.id)?;
-                let opponent = get_mut(&mut players, b.id)?;
-                (mob, opponent)
+        if dead.len() > 0 {
+            changed = true;
+            let mut dead_mobs = Vec::new();
+            let mut dead_players = Vec::new();
+            for id in &dead {
+                let killer = battle_map.killer(*id)?;
+                let enemies = battle_map.enemies(*id)?;
+                let entity = entities
+                    .remove(id)
+                    .ok_or(anyhow!("dead entity should be in the battle"))?;
+                let name = entity.name();
+                let killer_name = match killer {
+                    Some(killer) => combatant(&mut entities, killer)?.name(),
+                    None => "nobody".into(),
+                };
+
+                match id.enity_type {
+                    EntityType::Mob => {
+                        let loss = entity.loss()?;
+                        broadcast(&mut entities, *id, format!("{}: {}\n", name, loss));
+                        // the killer gets the drops, everyone fighting it gets the xp
+                        for enemy in &enemies {
+                            if enemy.enity_type != EntityType::Player {
+                                continue;
+                            }
+                            let player = combatant(&mut entities, *enemy)?;
+                            if Some(*enemy) == killer {
+                                player.send_text(format!("you killed {}\n", name));
+                                player.send_text("you got:\n".into());
+                                player.send_text(format!("{}\n", entity.drops().to_string()));
+                                player.inventory_mut().add_inventory(entity.drops());
+                            } else {
+                                player.send_text(format!("{} killed {}\n", killer_name, name));
+                            }
+                            player.send_text(format!("+ {}xp\n", entity.xp()));
+                            player.set_xp(player.xp() + entity.xp());
+                            player.send_text(format!("your xp is now {}\n", player.xp()));
+                        }
+                        dead_mobs.push(entity.loc().clone());
+                    }
+                    EntityType::Player => {
+                        if let Some(killer) = killer {
+                            if killer.enity_type == EntityType::Mob {
+                                let victory = combatant(&mut entities, killer)?.victory()?;
+                                entity.send_text(format!("{}: {}\n", killer_name, victory));
+                            }
+                        }
+                        entity.send_text(format!("you were killed by {}\n", killer_name));
+                        entity.send_image("none".into());
+                        for other in &ids {
+                            if other == id || dead.contains(other) {
+                                continue;
+                            }
+                            let other = combatant(&mut entities, *other)?;
+                            if Some(other.id()) == killer {
+                                other.send_text(format!("you killed {}\n", name));
+                            } else {
+                                other
+                                    .send_text(format!("{} was killed by {}\n", name, killer_name));
+                            }
+                        }
+                        dead_players.push(id.id);
+                    }
+                }
+                entities.insert(*id, entity);
             }
-            (EntityType::Player, EntityType::Mob) => {
-                let mob = world.get_mob_mut(b.id)?;
-                let opponent = get_mut(&mut players, a.id)?;
-                (mob, opponent)
+
+            let survivors: Vec<ID> = ids
+                .iter()
+                .filter(|id| !dead.contains(id))
+                .cloned()
+                .collect();
+            let mut sides = HashSet::new();
+            for id in &survivors {
+                sides.insert(battle_map.side(*id)?);
             }
-            _ => {
-                continue;
+            // whatever was still going to hit the winners lands before the battle ends
+            if sides.len() < 2 {
+                for id in &survivors {
+                    battle_map.handle_status_effects(*id, &mut entities, &g_arc)?;
+                }
             }
-        };
-        let mob_health = entity.stats().health();
-        let player_health = player.stats().health();
 
-        if mob_health <= 0.0 || player_health <= 0.0 {
-            let name = entity.name();
-            let loss = entity.loss()?;
-            let victory = entity.victory()?;
-
-            if player_health <= 0.0 {
-                player.send_text(format!("{}: {}\n", name, victory));
+            for id in &dead {
+                if !battle_map.in_battle(*id) {
+                    continue;
+                }
+                for id in battle_map.leave_battle(*id)? {
+                    if dead.contains(&id) {
+                        continue;
+                    }
+                    let entity = combatant(&mut entities, id)?;
+                    entity.send_text("the battle is over!\n".into());
+                    entity.send_image("none".into());
+                }
             }
+            drop(entities);
 
-            if mob_health <= 0.0 {
-                player.send_text(format!("{}: {}\n", name, loss));
-                player.send_text(format!("you killed {}\n", name));
-                player.send_text("you got:\n".into());
-                player.send_text(format!("{}\n", entity.drops().to_string()));
-                player.inventory_mut().add_inventory(entity.drops());
-                player.send_text(format!("+ {}xp\n", entity.xp()));
-                player.set_xp(player.xp() + entity.xp());
-                player.send_text(format!("your xp is now {}\n", player.xp()));
-                let loc = entity.loc().clone();
+            for loc in dead_mobs {
                 world.delete_mob_by_loc(loc)?;
-                battle_map.handle_status_effects(Box::new(player), &g_arc)?;
             }
-
-            if player_health <= 0.0 {
-                player.send_text(format!("you were killed by {}\n", name));
-                player.respawn(&world, &g_arc)?;
+            for id in dead_players {
+                get_mut(&mut players, id)?.respawn(&world, &g_arc)?;
             }
-
-            battle_map.end_battle(player.id())?;
-            player.send_image("none".into());
             continue;
         }
 
-        if !battle_map.turn(entity.id())? {
-            continue;
-        }
+        let holder = match battle_map.data_from_handle(battle_handle)?.turn_holder() {
+            Some(holder) if holder.enity_type == EntityType::Mob => holder,
+            _ => continue,
+        };
 
-        player.send_text(format!("{}: {}\n", entity.name(), entity.attack()?));
-        entity.do_random_move(Some(Box::new(player)), &mut battle_map, &g_arc);
-        battle_map.do_turn(Box::new(entity), Box::new(player), &g_arc)?;
+        changed = true;
+        // stunned or charging mobs just pass their turn
+        if !battle_map.stunned(holder)? {
+            let enemies = battle_map.enemies(holder)?;
+            let mob = entities
+                .remove(&holder)
+                .ok_or(anyhow!("mob should be in the battle"))?;
+            let attack = format!("{}: {}\n", mob.name(), mob.attack()?);
+            broadcast(&mut entities, holder, attack);
+            if enemies.len() > 0 {
+                let target = enemies[mob.rng().gen_range(0, enemies.len())];
+                let target = combatant(&mut entities, target)?;
+                mob.do_random_move(Some(Box::new(target)), &mut battle_map, &g_arc);
+            }
+            entities.insert(holder, mob);
+        }
+        battle_map.do_turn(holder, &mut entities, &g_arc)?;
     }
 
     // handle players dying outside of battle
     for player in players.iter_mut() {
         if let Some(player) = player {
             if player.stats().health() <= 0.0 {
+                changed = true;
                 player.send_text(format!("you died.\n"));
                 player.respawn(&world, &g_arc)?;
             }
         }
     }
+    Ok(changed)
+}
+
+// the game data's init event, with the token the player can reconnect with
+fn init_event(g: &GameData, token: &str) -> Event {
+    let mut data = g.init_data.clone();
+    data["reconnect_token"] = serde_json::Value::String(token.to_string());
+    Event::Init {
+        version: protocol::VERSION,
+        data,
+    }
+}
+
+// moves the player with this token over to a new connection,
+// returns false if nobody has it
+fn resume_player(
+    sender: &Sender<(PlayerOut, Option<usize>)>,
+    token: &str,
+    protocol: Protocol,
+    world_arc: &Arc<RwLock<World>>,
+    players_arc: &Arc<RwLock<Vec<Option<Player>>>>,
+    battle_map_arc: &Arc<RwLock<BattleMap>>,
+    g_arc: &GameData,
+) -> Result<bool> {
+    let battle_map = battle_map_arc.read().map_err(battle_map_op)?;
+    let mut players = players_arc.write().map_err(players_op)?;
+    let mut world = world_arc.write().map_err(world_op)?;
+    let id = match players
+        .iter()
+        .flatten()
+        .find(|player| !player.kicked && auth::tokens_match(token, &player.resume_token))
+    {
+        Some(player) => player.id().id,
+        None => return Ok(false),
+    };
+
+    let player = get_mut(&mut players, id)?;
+    // the old connection might not have noticed it's gone yet
+    if player.disconnected.is_none() {
+        let mut p_out = PlayerOut::new();
+        p_out.append_err(anyhow!("you reconnected from somewhere else"));
+        p_out.append_close();
+        player.send(p_out);
+    }
+    player.sender = sender.clone();
+    player.disconnected = None;
+    player.protocol = protocol;
+    player.forget_displays();
+    // tokens only work once
+    player.resume_token = auth::random_token();
+    let mut p_out = PlayerOut::new();
+    p_out.add_event(init_event(g_arc, &player.resume_token));
+    sender.send((p_out, Some(id)))?;
+    player.send_text("reconnected\n".into());
+
+    let id = ID::player(id);
+    if battle_map.in_battle(id) {
+        let ids = battle_map.participants(id)?;
+        let mut entities = get_entities(&ids, &mut players, &mut world)?;
+        let name = combatant(&mut entities, id)?.name();
+        broadcast(&mut entities, id, format!("{} reconnected!\n", name));
+    }
+    Ok(true)
+}
+
+// takes the player out of the game, ending their part in any battle
+fn remove_player(
+    player_id: usize,
+    world: &mut World,
+    players: &mut Vec<Option<Player>>,
+    battle_map: &mut BattleMap,
+) -> Result<()> {
+    let id = ID::player(player_id);
+    if battle_map.in_battle(id) {
+        let ids = battle_map.participants(id)?;
+        let mut entities = get_entities(&ids, players, world)?;
+        let name = combatant(&mut entities, id)?.name();
+        broadcast(&mut entities, id, format!("{} left the game!\n", name));
+        for other in battle_map.leave_battle(id)? {
+            let entity = combatant(&mut entities, other)?;
+            entity.send_text("the battle is over!\n".into());
+            entity.send_image("none".into());
+        }
+    }
+    players[player_id] = None;
+    Ok(())
+}
+
+// removes players who didn't reconnect in time
+fn remove_disconnected(
+    world_arc: Arc<RwLock<World>>,
+    players_arc: Arc<RwLock<Vec<Option<Player>>>>,
+    battle_map_arc: Arc<RwLock<BattleMap>>,
+    grace: Duration,
+) -> Result<()> {
+    let mut battle_map = battle_map_arc.write().map_err(battle_map_op)?;
+    let mut players = players_arc.write().map_err(players_op)?;
+    let mut world = world_arc.write().map_err(world_op)?;
+    for i in 0..players.len() {
+        let expired = match &players[i] {
+            Some(player) => match player.disconnected {
+                Some(time) => player.kicked || time.elapsed() >= grace,
+                None => false,
+            },
+            None => false,
+        };
+        if expired {
+            remove_player(i, &mut world, &mut players, &mut battle_map)?;
+        }
+    }
     Ok(())
 }
 
@@ -583,25 +901,53 @@ fn handle_player_input(
     world_arc: Arc<RwLock<World>>,
     players_arc: Arc<RwLock<Vec<Option<Player>>>>,
     battle_map_arc: Arc<RwLock<BattleMap>>,
+    logins_arc: Arc<RwLock<LoginAttempts>>,
+    ids_arc: Arc<RwLock<PlayerIds>>,
     g_arc: Arc<GameData>,
+    grace: Duration,
     rng: &mut StdRng,
 ) -> Result<()> {
     match player_input {
-        ConnA::Init(s) => {
+        ConnA::Init((sender, token, protocol)) => {
+            let mut resume_failed = false;
+            if let Some(token) = token {
+                if resume_player(
+                    &sender,
+                    &token,
+                    protocol,
+                    &world_arc,
+                    &players_arc,
+                    &battle_map_arc,
+                    &g_arc,
+                )? {
+                    return Ok(());
+                }
+                resume_failed = true;
+            }
+
             let mut players = players_arc.write().map_err(players_op)?;
-            let sender = s.clone();
             let id = get_first_availible_id(&players);
             if let Some(id) = id {
+                let number = ids_arc.write().map_err(ids_op)?.guest()?;
+                let mut player = Player::new(id, number, sender.clone(), &g_arc, rng)?;
+                player.protocol = protocol;
                 let mut p_out = PlayerOut::new();
-                p_out.add_pkt(g_arc.init_packet.clone());
+                p_out.add_event(init_event(&g_arc, &player.resume_token));
                 sender.send((p_out, Some(id)))?;
-                let mut player = Player::new(id, sender, &g_arc, rng)?;
+                if resume_failed {
+                    player.send_text(
+                        "couldn't reconnect to your last game, it may have timed out\n".into(),
+                    );
+                }
                 let world = world_arc.read().map_err(world_op)?;
                 player.respawn(&world, &g_arc)?;
                 players[id] = Some(player);
             } else {
                 let mut p_out = PlayerOut::new();
-                p_out.append_err(anyhow!("There are already 256 players in the game!"));
+                p_out.append_err(anyhow!(format!(
+                    "the server is full, it only takes {} players",
+                    players.len()
+                )));
                 sender.send((p_out, None))?;
             }
         }
@@ -612,6 +958,8 @@ fn handle_player_input(
                 world: world_arc.clone(),
                 battle_map: battle_map_arc.clone(),
                 players: players_arc.clone(),
+                logins: logins_arc.clone(),
+                ids: ids_arc.clone(),
                 g: &g_arc,
             };
             let res = dispatch(action_data);
@@ -622,135 +970,252 @@ fn handle_player_input(
                 players[player_id]
                     .as_mut()
                     .ok_or(anyhow!("bad player id"))?
-                    .sender
-                    .send((p_out, None))?;
+                    .send(p_out);
             }
         }
-        ConnA::Quit(player_id) => {
+        ConnA::Quit((player_id, sender)) => {
             let mut battle_map = battle_map_arc.write().map_err(battle_map_op)?;
             let mut players = players_arc.write().map_err(players_op)?;
             let mut world = world_arc.write().map_err(world_op)?;
-            if let Ok(opponent) = battle_map.get_opponent(ID::player(player_id)) {
-                let entity = get_entity(opponent, &mut players, &mut world)?;
-                entity.send_text("your opponent disconnected!\n".into());
-                battle_map.end_battle(opponent)?;
+            let player = get_mut(&mut players, player_id)?;
+            // someone else has reconnected as this player since
+            if !player.sender.same_channel(&sender) {
+                return Ok(());
             }
-            players[player_id] = None;
+            if player.kicked || grace == Duration::from_secs(0) {
+                return remove_player(player_id, &mut world, &mut players, &mut battle_map);
+            }
+
+            // keep the player where they are, in case they come back
+            player.disconnected = Some(Instant::now());
+            let id = ID::player(player_id);
+            if battle_map.in_battle(id) {
+                let ids = battle_map.participants(id)?;
+                let mut entities = get_entities(&ids, &mut players, &mut world)?;
+                let name = combatant(&mut entities, id)?.name();
+                broadcast(
+                    &mut entities,
+                    id,
+                    format!("{} disconnected, waiting for them to come back\n", name),
+                );
+            }
+        }
+        ConnA::Admin((params, reply)) => {
+            let mut battle_map = battle_map_arc.write().map_err(battle_map_op)?;
+            let mut players = players_arc.write().map_err(players_op)?;
+            let mut world = world_arc.write().map_err(world_op)?;
+            let mut logins = logins_arc.write().map_err(logins_op)?;
+            let res = admin::dispatch(AdminData {
+                params,
+                world: &mut world,
+                players: &mut players,
+                battle_map: &mut battle_map,
+                g: &g_arc,
+                logins: &mut logins,
+                console: true,
+            });
+            reply.send(res)?;
         }
     }
     Ok(())
 }
 
 fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let run = match &args.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Command::Validate => {
+            if let Err(e) = validate(&args) {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        Command::Run(run) => run,
+    };
+    SAVE_DIR
+        .set(args.save_dir.clone())
+        .map_err(|_| anyhow!("save folder was already set"))?;
+    KEEP_SAVES
+        .set(run.keep_saves)
+        .map_err(|_| anyhow!("number of saves to keep was already set"))?;
 
     // init gamedata and world
-    let (g, world) = init(&args)?;
+    let (g, world) = init(&args, &run.world)?;
 
     let (send, recv) = unbounded();
 
     let mut players = vec![];
-    for _ in 0..(std::u8::MAX as usize + 1) {
+    for _ in 0..run.max_players {
         players.push(None);
     }
 
     let battle_map = BattleMap::new();
-    let g = Arc::new(g);
+    // the inner Arc is swapped out whenever the game data is reloaded
+    let g = Arc::new(RwLock::new(Arc::new(g)));
     let world = Arc::new(RwLock::new(world));
     let players = Arc::new(RwLock::new(players));
     let battle_map = Arc::new(RwLock::new(battle_map));
+    let logins = Arc::new(RwLock::new(LoginAttempts::new()));
+    let ids = Arc::new(RwLock::new(PlayerIds::load()?));
 
     let g_arc = Arc::clone(&g);
     let world_arc = Arc::clone(&world);
     let players_arc = Arc::clone(&players);
     let battle_map_arc = Arc::clone(&battle_map);
+    let logins_arc = Arc::clone(&logins);
+    let ids_arc = Arc::clone(&ids);
+    let grace = Duration::from_secs(run.reconnect_grace);
 
-    // handle player input and world logic
-    // world logic runs whenever player input isn't happening
+    // handle player input and world logic.
+    // the logic thread sleeps until there's input or the next tick is due,
+    // world logic runs once input has been handled and for as long as it keeps changing things
     spawn(move || {
         let mut rng = SeedableRng::seed_from_u64(thread_rng().gen());
-        let mut last_update = Instant::now();
+        let mut next_tick = Instant::now() + TICK;
+        let mut dirty = true;
+        let mut views = ViewTracker::new();
         loop {
-            let mut res = |input| {
-                match input {
-                    Ok(player_input) => {
-                        // we have input from a player, handle it
-                        handle_player_input(
-                            player_input,
-                            world_arc.clone(),
-                            players_arc.clone(),
-                            battle_map_arc.clone(),
-                            g_arc.clone(),
-                            &mut rng,
-                        )
-                    }
-                    Err(_) => {
-                        // no player input to handle, just do world logic
-                        world_logic(
-                            world_arc.clone(),
-                            players_arc.clone(),
-                            battle_map_arc.clone(),
-                            g_arc.clone(),
-                        )?;
-                        if last_update.elapsed().as_millis() > TICK {
-                            world_tick(
-                                world_arc.clone(),
-                                players_arc.clone(),
-                                battle_map_arc.clone(),
-                                g_arc.clone(),
-                            )?;
-                            last_update = Instant::now();                            
-                        }
-                        Ok(())
+            // don't wait if world logic still has something to do
+            let deadline = if dirty { Instant::now() } else { next_tick };
+            let input = match recv.recv_deadline(deadline) {
+                Ok(player_input) => Some(player_input),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let res = || -> Result<()> {
+                // everything in this pass uses the same game data, even if it's reloaded meanwhile
+                let g_arc = g_arc.read().map_err(gamedata_op)?.clone();
+                if let Some(player_input) = input {
+                    dirty = true;
+                    handle_player_input(
+                        player_input,
+                        world_arc.clone(),
+                        players_arc.clone(),
+                        battle_map_arc.clone(),
+                        logins_arc.clone(),
+                        ids_arc.clone(),
+                        g_arc.clone(),
+                        grace,
+                        &mut rng,
+                    )?;
+                }
+
+                let now = Instant::now();
+                if now >= next_tick {
+                    dirty = true;
+                    world_tick(
+                        world_arc.clone(),
+                        players_arc.clone(),
+                        battle_map_arc.clone(),
+                        g_arc.clone(),
+                    )?;
+                    remove_disconnected(
+                        world_arc.clone(),
+                        players_arc.clone(),
+                        battle_map_arc.clone(),
+                        grace,
+                    )?;
+                    let took = now.elapsed();
+                    let late = now - next_tick;
+                    if took > TICK || late > TICK {
+                        println!("tick overran: started {:?} late and took {:?}", late, took);
                     }
+                    // ticks that were missed are skipped rather than run back to back
+                    next_tick = (next_tick + TICK).max(Instant::now());
+                }
+
+                // let queued up input go first
+                if dirty && recv.is_empty() {
+                    dirty = false;
+                    dirty = world_logic(
+                        world_arc.clone(),
+                        players_arc.clone(),
+                        battle_map_arc.clone(),
+                        g_arc.clone(),
+                    )?;
                 }
+                views.update(world_arc.clone(), players_arc.clone(), &g_arc)?;
+                Ok(())
             };
             // if we encounter any errors, print them
-            if let Err(err) = res(recv.try_recv()) {
+            if let Err(err) = res() {
                 println!("{}", err);
             }
         }
     });
 
+    let console = Console {
+        world: Arc::clone(&world),
+        players: Arc::clone(&players),
+        g_lock: Arc::clone(&g),
+        gamemode: args.gamemode.clone(),
+        logic: send.clone(),
+    };
+    if let Some(admin_port) = run.admin_port {
+        let token = remote::read_or_create_token(&admin_token_file())?;
+        let console = console.clone();
+        remote::listen(admin_port, token, move |line| console.run(line))?;
+        println!(
+            "accepting admin commands on 127.0.0.1:{}, the token is in {}",
+            admin_port,
+            admin_token_file()
+        );
+    }
+
     // spin up new thread for each connection we get
-    let port: u64 = args[1].parse()?;
-    let server = Server::bind(format!("0.0.0.0:{}", port))?;
+    let server = Server::bind(format!("0.0.0.0:{}", run.port))?;
     spawn(move || {
         for request in server.filter_map(Result::ok) {
             let send = send.clone();
             spawn(move || {
+                let token = resume_token(&request.uri());
+                let protocol = Protocol::negotiate(request.protocols());
+                let request = match protocol {
+                    Protocol::Json => request.use_protocol(protocol::JSON_PROTOCOL),
+                    Protocol::Legacy => request,
+                };
                 let client = request.accept().unwrap();
                 let ip = client.peer_addr().unwrap();
-                println!("Connection from {}", ip);
-                handle_connection(client, send);
+                println!("Connection from {} ({:?})", ip, protocol);
+                handle_connection(client, send, token, protocol);
             });
         }
     });
 
-    // autosave for players
+    // autosave for players, written while holding the lock
+    // so 'account' can't create or delete a save in the middle of it
     let players_clone = players.clone();
     spawn(move || {
         let players = players_clone;
+        let mut last_rotate = Instant::now();
         loop {
-            let dur = Duration::from_secs(10);
-            thread::sleep(dur);
+            thread::sleep(PLAYER_SAVE);
+            let keep = if last_rotate.elapsed() >= PLAYER_ROTATE {
+                last_rotate = Instant::now();
+                keep_saves()
+            } else {
+                0
+            };
             if let Ok(players) = players.read().map_err(players_op) {
-                for player in players.iter() {
-                    if let Some(player) = player {
-                        if let Some(username) = &player.username {
-                            let save_file = format!("{}/{}", PLAYER_SAVE_FOLDER, username);
-                            let try_write = || -> Result<()> {
-                                let mut file = OpenOptions::new()
-                                    .write(true)
-                                    .truncate(true)
-                                    .create(true)
-                                    .open(save_file)?;
-                                file.write_all(&player.save()?.as_bytes())?;
-                                Ok(())
-                            };
-                            if let Err(e) = try_write() {
-                                println!("Error while saving: {}", e);
-                            }
+                for player in players.iter().flatten() {
+                    if let Some(username) = &player.username {
+                        let save_file = format!("{}/{}", player_save_folder(), username);
+                        let res = player
+                            .save()
+                            .and_then(|save| save::write_file(&save_file, save.as_bytes(), keep));
+                        if let Err(e) = res {
+                            println!("Error while saving {}: {}", username, e);
                         }
                     }
                 }
@@ -760,88 +1225,139 @@ fn main() -> Result<()> {
         }
     });
 
+    // autosave for the world
+    if run.autosave > 0 {
+        let world = world.clone();
+        let g = g.clone();
+        let name = run.world.save_name();
+        let dur = Duration::from_secs(run.autosave);
+        spawn(move || loop {
+            thread::sleep(dur);
+            let try_save = || -> Result<()> {
+                let g = g.read().map_err(gamedata_op)?.clone();
+                let mut bytes = Vec::new();
+                {
+                    let mut world = world.write().map_err(world_op)?;
+                    save::write_world(&mut bytes, &mut world, &g)?;
+                }
+                save::write_file(
+                    &format!("{}/{}", world_save_folder(), name),
+                    &bytes,
+                    keep_saves(),
+                )
+            };
+            match try_save() {
+                Ok(()) => println!("autosaved the world as {}", name),
+                Err(e) => println!("Error while autosaving the world: {}", e),
+            }
+        });
+    }
+
     // handle server commands on the console
     // print out any errors
     loop {
-        if let Err(res) = handle_server_commands(Arc::clone(&world), Arc::clone(&players), &g) {
+        if let Err(res) = handle_server_commands(&console) {
             println!("{:?}\n", res);
         }
     }
 }
 
-fn handle_connection(stream: Client<TcpStream>, channel: Sender<ConnA>) {
+// clients reconnecting to their last game connect to "/?resume=<token>"
+fn resume_token(uri: &str) -> Option<String> {
+    let query = uri.splitn(2, '?').nth(1)?;
+    query
+        .split('&')
+        .find(|param| param.starts_with("resume="))
+        .map(|param| param["resume=".len()..].to_string())
+}
+
+fn handle_connection(
+    mut stream: Client<TcpStream>,
+    channel: Sender<ConnA>,
+    token: Option<String>,
+    protocol: Protocol,
+) {
     let (send, recv) = unbounded();
-    while let Err(e) = channel.send(ConnA::Init(send.clone())) {
+    while let Err(e) = channel.send(ConnA::Init((send.clone(), token.clone(), protocol))) {
         println!("{}", e);
     }
     let mut res = recv.recv().unwrap();
-    let id = res.1.unwrap();
-    let init_pkt = res.0.get_pkt().unwrap();
+    let id = match res.1 {
+        Some(id) => id,
+        // the server is full, say so before hanging up
+        None => {
+            while let Some(event) = res.0.get_event() {
+                if let Ok(message) = protocol.encode(event) {
+                    let _ = stream.send_message(&message);
+                }
+            }
+            let _ = stream.send_message(&OwnedMessage::Close(None));
+            let _ = stream.shutdown();
+            return;
+        }
+    };
+    let init = res.0.get_event().unwrap();
 
     let (mut reader, mut writer) = stream.split().unwrap();
     writer
-        .send_message(&OwnedMessage::Binary(init_pkt.bytes()))
+        .send_message(&protocol.encode(init).unwrap())
         .unwrap();
 
     let (quit_tx, quit_rx) = unbounded();
+    // waits for whichever comes first, something to send or the reader quitting
     spawn(move || loop {
-        if let Ok(_) = quit_rx.try_recv() {
-            break;
-        }
-        if let Ok((mut res, _)) = recv.try_recv() {
-            while let Some(pkt) = res.get_pkt() {
-                let message = OwnedMessage::Binary(pkt.bytes());
-                if writer.send_message(&message).is_err() {
-                    break;
+        let mut res = select! {
+            recv(quit_rx) -> _ => break,
+            recv(recv) -> res => match res {
+                Ok((res, _)) => res,
+                Err(_) => break,
+            },
+        };
+        let close = res.closes();
+        while let Some(event) = res.get_event() {
+            let message = match protocol.encode(event) {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("couldn't encode event: {}", e);
+                    continue;
                 }
+            };
+            if writer.send_message(&message).is_err() {
+                break;
             }
         }
-    });
-
-    loop {
-        let line;
-        if let Ok(l) = reader.recv_message() {
-            line = l;
-        } else {
+        if close {
+            // shutting down the socket also stops the reader, which sends the quit
+            let _ = writer.send_message(&OwnedMessage::Close(None));
+            let _ = writer.shutdown_all();
             break;
         }
+    });
 
-        let text;
-        if let OwnedMessage::Text(l) = line {
-            text = l;
-        } else {
-            break;
-        }
+    // tells this connection what was wrong with what it sent
+    let reply_err = |e: Error| {
+        let mut p_out = PlayerOut::new();
+        p_out.append_err(e);
+        let _ = send.send((p_out, None));
+    };
 
-        let params;
-        if let Ok(p) = from_str::<Vec<Literal>>(&text) {
-            params = p;
-        } else {
-            continue; // TODO: should replace this with an error message
-        }
+    loop {
+        let text = match reader.recv_message() {
+            Ok(OwnedMessage::Text(text)) => text,
+            Ok(OwnedMessage::Close(_)) | Err(_) => break,
+            Ok(OwnedMessage::Binary(_)) => {
+                reply_err(anyhow!("commands have to be sent as text"));
+                continue;
+            }
+            Ok(OwnedMessage::Ping(_)) | Ok(OwnedMessage::Pong(_)) => continue,
+        };
 
-        channel
-            .send(ConnA::Run((VecDeque::from_iter(params), id)))
-            .unwrap();
+        match protocol.parse(&text) {
+            Ok(params) => channel.send(ConnA::Run((params, id))).unwrap(),
+            Err(e) => reply_err(e),
+        }
     }
 
     quit_tx.send(()).unwrap();
-    channel.send(ConnA::Quit(id)).unwrap();
-}
-
-pub fn get_entity<'a>(
-    entity: ID,
-    players: &'a mut Vec<Option<Player>>,
-    world: &'a mut World,
-) -> Result<Box<&'a mut dyn Entity>> {
-    match entity.enity_type {
-        EntityType::Mob => {
-            let entity = world.get_mob_mut(entity.id)?;
-            Ok(Box::new(entity as &mut dyn Entity))
-        }
-        EntityType::Player => {
-            let entity = get_mut(players, entity.id)?;
-            Ok(Box::new(entity as &mut dyn Entity))
-        }
-    }
+    channel.send(ConnA::Quit((id, send))).unwrap();
 }
@@ -0,0 +1,446 @@
+use crate::{rgb::RGB, vector3::Vector3, world::MobU16};
+use anyhow::{anyhow, Result};
+use std::sync::OnceLock;
+
+// maps are split into square chunks of CHUNK_SIZE x CHUNK_SIZE cells on a single layer,
+// chunks on the edges of the map are cut short
+pub const CHUNK_SIZE: isize = 32;
+
+// a value that can be stored in a packed (run-length encoded) chunk
+pub trait Cell: Clone + Eq {
+    const BYTES: usize;
+    fn write(&self, out: &mut Vec<u8>);
+    fn read(bytes: &[u8]) -> Self;
+}
+
+impl Cell for u8 {
+    const BYTES: usize = 1;
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl Cell for bool {
+    const BYTES: usize = 1;
+    fn write(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+    fn read(bytes: &[u8]) -> Self {
+        bytes[0] != 0
+    }
+}
+
+impl Cell for MobU16 {
+    const BYTES: usize = 2;
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0.to_le_bytes());
+    }
+    fn read(bytes: &[u8]) -> Self {
+        MobU16(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+impl Cell for RGB {
+    const BYTES: usize = 3;
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.r, self.g, self.b]);
+    }
+    fn read(bytes: &[u8]) -> Self {
+        RGB::new(bytes[0], bytes[1], bytes[2])
+    }
+}
+
+// most chunks (air on the upper layers, cells without mobs) hold a single value,
+// so those don't get a vec until something in them changes
+enum Chunk<T> {
+    Uniform(T),
+    Dense(Vec<T>),
+}
+
+impl<T> Chunk<T>
+where
+    T: Clone + Eq,
+{
+    fn get(&self, offset: usize) -> &T {
+        match self {
+            Chunk::Uniform(val) => val,
+            Chunk::Dense(vals) => &vals[offset],
+        }
+    }
+
+    fn set(&mut self, offset: usize, val: T, len: usize) {
+        match self {
+            Chunk::Uniform(curr) => {
+                if *curr != val {
+                    let mut vals = vec![curr.clone(); len];
+                    vals[offset] = val;
+                    *self = Chunk::Dense(vals);
+                }
+            }
+            Chunk::Dense(vals) => vals[offset] = val,
+        }
+    }
+}
+
+// every packed chunk is a list of runs: run length (u32) followed by the value
+fn pack<T: Cell>(chunk: &Chunk<T>, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match chunk {
+        Chunk::Uniform(val) => {
+            bytes.extend_from_slice(&(len as u32).to_le_bytes());
+            val.write(&mut bytes);
+        }
+        Chunk::Dense(vals) => {
+            let mut i = 0;
+            while i < vals.len() {
+                let mut run = 1;
+                while i + run < vals.len() && vals[i + run] == vals[i] {
+                    run += 1;
+                }
+                bytes.extend_from_slice(&(run as u32).to_le_bytes());
+                vals[i].write(&mut bytes);
+                i += run;
+            }
+        }
+    }
+    bytes
+}
+
+fn runs<T: Cell>(bytes: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    bytes.chunks(4 + T::BYTES).map(|run| {
+        let len = u32::from_le_bytes([run[0], run[1], run[2], run[3]]) as usize;
+        (len, &run[4..])
+    })
+}
+
+// checked when a map is loaded, so that unpacking later on can't fail
+fn check_packed<T: Cell>(bytes: &[u8], len: usize) -> Result<()> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(4 + T::BYTES) {
+        return Err(anyhow!("packed chunk has a bad length of {} bytes", bytes.len()));
+    }
+    let mut total = 0;
+    for (run, _) in runs::<T>(bytes) {
+        if run == 0 {
+            return Err(anyhow!("packed chunk has an empty run"));
+        }
+        total += run;
+    }
+    if total != len {
+        return Err(anyhow!(
+            "packed chunk has {} cells, expected {}",
+            total,
+            len
+        ));
+    }
+    Ok(())
+}
+
+fn unpack<T: Cell>(bytes: &[u8], len: usize) -> Chunk<T> {
+    if bytes.len() == 4 + T::BYTES {
+        return Chunk::Uniform(T::read(&bytes[4..]));
+    }
+    let mut vals = Vec::with_capacity(len);
+    for (run, val) in runs::<T>(bytes) {
+        let val = T::read(val);
+        for _ in 0..run {
+            vals.push(val.clone());
+        }
+    }
+    Chunk::Dense(vals)
+}
+
+type Unpacker<T> = fn(&[u8], usize) -> Chunk<T>;
+
+struct Slot<T> {
+    // the chunk as it was read from disk, kept until the chunk is modified
+    // so that untouched chunks can be saved again without repacking them
+    packed: Option<Vec<u8>>,
+    chunk: OnceLock<Chunk<T>>,
+}
+
+pub struct Map<T> {
+    pub dim: Vector3,
+    chunks_x: usize,
+    chunks_y: usize,
+    slots: Vec<Slot<T>>,
+    unpack: Option<Unpacker<T>>,
+}
+
+impl<T> Map<T>
+where
+    T: Clone + Eq,
+{
+    fn empty(dim: Vector3) -> Self {
+        let chunks_x = ((dim.x() + CHUNK_SIZE - 1) / CHUNK_SIZE) as usize;
+        let chunks_y = ((dim.y() + CHUNK_SIZE - 1) / CHUNK_SIZE) as usize;
+        Map {
+            dim,
+            chunks_x,
+            chunks_y,
+            slots: Vec::new(),
+            unpack: None,
+        }
+    }
+
+    pub fn new(dim: Vector3, default: T) -> Self {
+        let mut map = Self::empty(dim);
+        for _ in 0..map.num_chunks() {
+            map.slots.push(Slot {
+                packed: None,
+                chunk: OnceLock::from(Chunk::Uniform(default.clone())),
+            });
+        }
+        map
+    }
+
+    pub fn from_vec(dim: Vector3, map: Vec<T>) -> Result<Self> {
+        if dim.dim() as usize != map.len() {
+            return Err(anyhow!("bad dimensions"));
+        }
+        let mut chunks: Vec<Vec<T>> = (0..Self::empty(dim).num_chunks())
+            .map(|_| Vec::new())
+            .collect();
+        let mut res = Self::empty(dim);
+        for (i, val) in map.into_iter().enumerate() {
+            let (chunk, _) = res.locate(res.index_to_posn(i));
+            chunks[chunk].push(val);
+        }
+        res.slots = chunks
+            .into_iter()
+            .map(|vals| Slot {
+                packed: None,
+                chunk: OnceLock::from(Chunk::Dense(vals)),
+            })
+            .collect();
+        Ok(res)
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks_x * self.chunks_y * self.dim.z() as usize
+    }
+
+    // the number of cells in a chunk, smaller than CHUNK_SIZE^2 on the edges of the map
+    fn chunk_len(&self, chunk: usize) -> usize {
+        let (w, h) = self.chunk_dim(chunk);
+        w * h
+    }
+
+    fn chunk_dim(&self, chunk: usize) -> (usize, usize) {
+        let cx = (chunk % self.chunks_x) as isize;
+        let cy = ((chunk / self.chunks_x) % self.chunks_y) as isize;
+        let w = CHUNK_SIZE.min(self.dim.x() - cx * CHUNK_SIZE);
+        let h = CHUNK_SIZE.min(self.dim.y() - cy * CHUNK_SIZE);
+        (w as usize, h as usize)
+    }
+
+    // chunk index and offset within that chunk of a location that's known to be in the map
+    fn locate(&self, loc: Vector3) -> (usize, usize) {
+        let cx = (loc.x() / CHUNK_SIZE) as usize;
+        let cy = (loc.y() / CHUNK_SIZE) as usize;
+        let chunk = loc.z() as usize * self.chunks_x * self.chunks_y + cy * self.chunks_x + cx;
+        let (w, _) = self.chunk_dim(chunk);
+        let offset = (loc.y() % CHUNK_SIZE) as usize * w + (loc.x() % CHUNK_SIZE) as usize;
+        (chunk, offset)
+    }
+
+    fn chunk(&self, chunk: usize) -> &Chunk<T> {
+        let slot = &self.slots[chunk];
+        slot.chunk.get_or_init(|| {
+            let unpack = self.unpack.expect("packed chunks always have an unpacker");
+            let packed = slot.packed.as_ref().expect("unloaded chunks are always packed");
+            unpack(packed, self.chunk_len(chunk))
+        })
+    }
+
+    fn chunk_mut(&mut self, chunk: usize) -> &mut Chunk<T> {
+        self.chunk(chunk);
+        let slot = &mut self.slots[chunk];
+        slot.packed = None;
+        slot.chunk.get_mut().expect("chunk was just unpacked")
+    }
+
+    pub fn index(&self, loc: Vector3) -> Result<usize> {
+        if loc.x() >= self.dim.x()
+            || loc.x() < 0
+            || loc.y() >= self.dim.y()
+            || loc.y() < 0
+            || loc.z() >= self.dim.z()
+            || loc.z() < 0
+        {
+            Err(anyhow!("point {:?} not in map of dim {:?}", loc, self.dim))
+        } else {
+            Ok(
+                loc.z() as usize * self.dim.x() as usize * self.dim.y() as usize
+                    + loc.y() as usize * self.dim.x() as usize
+                    + loc.x() as usize,
+            )
+        }
+    }
+
+    pub fn index_to_posn(&self, i: usize) -> Vector3 {
+        Vector3::new(
+            (i % self.dim.x() as usize) as isize,
+            ((i / (self.dim.x() as usize)) % self.dim.y() as usize) as isize,
+            (i / (self.dim.x() as usize * self.dim.y() as usize)) as isize,
+        )
+    }
+
+    pub fn direct_set(&mut self, index: usize, val: T) {
+        let (chunk, offset) = self.locate(self.index_to_posn(index));
+        let len = self.chunk_len(chunk);
+        self.chunk_mut(chunk).set(offset, val, len);
+    }
+
+    pub fn direct_get(&self, index: usize) -> T {
+        let (chunk, offset) = self.locate(self.index_to_posn(index));
+        self.chunk(chunk).get(offset).clone()
+    }
+
+    pub fn set(&mut self, loc: Vector3, val: T) -> Result<()> {
+        Ok(self.direct_set(self.index(loc)?, val))
+    }
+
+    pub fn get(&self, loc: Vector3) -> Result<T> {
+        Ok(self.direct_get(self.index(loc)?))
+    }
+}
+
+impl<T> Map<T>
+where
+    T: Cell,
+{
+    // chunks are only unpacked the first time something in them is accessed
+    pub fn from_packed(dim: Vector3, packed: Vec<Vec<u8>>) -> Result<Self> {
+        let mut map = Self::empty(dim);
+        if packed.len() != map.num_chunks() {
+            return Err(anyhow!(
+                "expected {} chunks, found {}",
+                map.num_chunks(),
+                packed.len()
+            ));
+        }
+        for (i, bytes) in packed.iter().enumerate() {
+            check_packed::<T>(bytes, map.chunk_len(i))
+                .map_err(|e| anyhow!(format!("chunk {}: {}", i, e)))?;
+        }
+        map.slots = packed
+            .into_iter()
+            .map(|bytes| Slot {
+                packed: Some(bytes),
+                chunk: OnceLock::new(),
+            })
+            .collect();
+        map.unpack = Some(unpack::<T>);
+        Ok(map)
+    }
+
+    pub fn pack(&self) -> Vec<Vec<u8>> {
+        (0..self.num_chunks())
+            .map(|i| match &self.slots[i].packed {
+                Some(bytes) => bytes.clone(),
+                None => pack(self.chunk(i), self.chunk_len(i)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 40x35 leaves chunks on the right and bottom edges cut short
+    fn dim() -> Vector3 {
+        Vector3::new(40, 35, 2)
+    }
+
+    fn cells<T: Clone + Eq>(map: &Map<T>) -> Vec<T> {
+        (0..(map.dim.dim() as usize))
+            .map(|i| map.direct_get(i))
+            .collect()
+    }
+
+    fn repacked<T: Cell>(map: &Map<T>) -> Result<Map<T>> {
+        Map::from_packed(map.dim, map.pack())
+    }
+
+    #[test]
+    fn uniform_chunks_pack_to_one_run() -> Result<()> {
+        let map = Map::new(dim(), 7u8);
+        let packed = map.pack();
+        assert_eq!(packed.len(), 2 * 2 * 2);
+        for (i, bytes) in packed.iter().enumerate() {
+            assert_eq!(bytes.len(), 4 + u8::BYTES);
+            let run = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+            assert_eq!(run, map.chunk_len(i));
+        }
+        assert!(cells(&repacked(&map)?) == cells(&map));
+        Ok(())
+    }
+
+    #[test]
+    fn dense_chunks_round_trip() -> Result<()> {
+        let dim = dim();
+        let mut blocks = Map::new(dim, 0u8);
+        let mut mobs = Map::new(dim, MobU16::empty());
+        let mut colors = Map::new(dim, RGB::black());
+        // changes in every chunk, including the last cell of the edge chunks
+        for i in (0..(dim.dim() as usize)).step_by(5) {
+            blocks.direct_set(i, (i % 3) as u8);
+            mobs.direct_set(i, MobU16(i as u16));
+            colors.direct_set(i, RGB::new(i as u8, 1, 2));
+        }
+        let last = Vector3::new(dim.x() - 1, dim.y() - 1, dim.z() - 1);
+        blocks.set(last, 9)?;
+        mobs.set(last, MobU16(9))?;
+        colors.set(last, RGB::white())?;
+
+        assert!(cells(&repacked(&blocks)?) == cells(&blocks));
+        assert!(cells(&repacked(&mobs)?) == cells(&mobs));
+        assert!(cells(&repacked(&colors)?) == cells(&colors));
+        Ok(())
+    }
+
+    #[test]
+    fn changes_after_loading_are_packed() -> Result<()> {
+        let mut map = Map::from_packed(dim(), Map::new(dim(), 1u8).pack())?;
+        let loc = Vector3::new(39, 34, 0);
+        map.set(loc, 2)?;
+        let loaded = repacked(&map)?;
+        assert_eq!(loaded.get(loc)?, 2);
+        assert_eq!(loaded.get(Vector3::new(38, 34, 0))?, 1);
+        assert!(cells(&loaded) == cells(&map));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_bad_chunks() {
+        let map = Map::new(dim(), 1u8);
+        let mut packed = map.pack();
+        packed.pop();
+        assert!(Map::<u8>::from_packed(dim(), packed).is_err());
+
+        // the last chunk is 8x3, so a run has to cover exactly 24 cells
+        let run = |len: u32, val: u8| {
+            let mut bytes = len.to_le_bytes().to_vec();
+            bytes.push(val);
+            bytes
+        };
+        for bad in [
+            run(23, 1),
+            run(25, 1),
+            [run(24, 1), run(0, 2)].concat(),
+            vec![],
+            vec![24, 0, 0],
+        ] {
+            let mut packed = map.pack();
+            *packed.last_mut().unwrap() = bad;
+            assert!(Map::<u8>::from_packed(dim(), packed).is_err());
+        }
+        let mut packed = map.pack();
+        *packed.last_mut().unwrap() = [run(20, 1), run(4, 2)].concat();
+        assert!(Map::<u8>::from_packed(dim(), packed).is_ok());
+    }
+}
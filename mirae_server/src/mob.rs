@@ -4,7 +4,7 @@ use crate::{
     display::Image,
     entity::{get_items_rand, Entity, NUM_WEARS},
     gamedata::{
-        gamedata::{GameData, MobName},
+        gamedata::{GameData, MobName, Named},
         item::Ability,
         mobtemplate::{InventoryBuilder, MobTemplate, Quotes},
     },
@@ -16,10 +16,29 @@ use crate::{
     gamedata::gamedata::{DmgType, ItemName},
     stat::default_empty_fields,
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use rand::{prelude::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+// the parts of a mob that change after it spawns,
+// everything else comes from the mob's template
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MobSave {
+    id: usize,
+    name: String,
+    loc: Vector3,
+    inventory: Inventory,
+    drops: Inventory,
+    equip: Inventory,
+    wear: Inventory,
+    stats: Stat,
+    xp: i64,
+    rng_seed: u64,
+    attack_buffs: HashMap<DmgType, f64>,
+    defense_buffs: HashMap<DmgType, f64>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Mob {
     id: usize,
@@ -58,7 +77,10 @@ fn make_inventory(gen: &InventoryBuilder, rng: &mut StdRng, g: &GameData) -> Res
             };
         while inventory.size() < num_picks {
             let rand_item_name = &all_items[rng.gen_range(0, all_items.len())];
-            let rand_item = &g.items[rand_item_name];
+            let rand_item = g
+                .items
+                .get(rand_item_name)
+                .ok_or(anyhow!(format!("invalid item name! {:?}", rand_item_name)))?;
             add_if_tags_match(&mut inventory, rand_item, rand_item_name);
         }
     } else {
@@ -140,6 +162,61 @@ impl Mob {
 
         Ok(mob)
     }
+
+    // reseeds the mob's rng so that the saved seed continues the same random sequence
+    pub fn save(&mut self) -> MobSave {
+        let rng_seed = self.rng.gen();
+        self.rng = SeedableRng::seed_from_u64(rng_seed);
+        MobSave {
+            id: self.id,
+            name: self.name.0.clone(),
+            loc: self.loc,
+            inventory: self.inventory.clone(),
+            drops: self.drops.clone(),
+            equip: self.equip.clone(),
+            wear: self.wear.clone(),
+            stats: self.stats.clone(),
+            xp: self.xp,
+            rng_seed,
+            attack_buffs: self.attack_buffs.clone(),
+            defense_buffs: self.defense_buffs.clone(),
+        }
+    }
+
+    pub fn from_save(save: MobSave, g: &GameData) -> Result<Self> {
+        let name = MobName::checked_from(save.name, g)?;
+        let template = &g.mob_templates.name_to_item[&name];
+        Ok(Self {
+            id: save.id,
+            inventory: save.inventory,
+            drops: save.drops,
+            equip: save.equip,
+            wear: save.wear,
+            stats: save.stats,
+            loc: save.loc,
+            rng: SeedableRng::seed_from_u64(save.rng_seed),
+            xp: save.xp,
+            abilities: template.abilities.clone(),
+            name,
+            quotes: template.quotes.clone(),
+            display_img: template.display_img.clone(),
+            attack_buffs: save.attack_buffs,
+            defense_buffs: save.defense_buffs,
+        })
+    }
+
+    // picks up changes to the mob's template after the game data is reloaded
+    pub fn refresh(&mut self, g: &GameData) {
+        if let Some(template) = g.mob_templates.name_to_item.get(&self.name) {
+            self.abilities = template.abilities.clone();
+            self.quotes = template.quotes.clone();
+            self.display_img = template.display_img.clone();
+        }
+    }
+
+    pub fn template_name(&self) -> &MobName {
+        &self.name
+    }
 }
 
 impl Entity for Mob {
@@ -1,14 +1,18 @@
 use crate::{
+    auth,
     combat::ID,
-    display::Image,
+    display::{Bounds, Image},
     entity::Entity,
     gamedata::{
         gamedata::{DmgType, GameData, ItemName, Named, StatType},
         item::Ability,
     },
     inventory::Inventory,
+    map::Map,
     playerout::PlayerOut,
+    protocol::Protocol,
     stat::{default_empty_fields, Stat},
+    trading::TradeOffer,
     vector3::Vector3,
     world::World,
 };
@@ -16,7 +20,13 @@ use anyhow::{anyhow, Result};
 use crossbeam::channel::Sender;
 use rand::{prelude::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+// every new player starts out with one of each of these
+pub const STARTING_ITEMS: [&str; 2] = ["stick", "workbench"];
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlayerSave {
@@ -25,10 +35,43 @@ pub struct PlayerSave {
     wear: Inventory,
     stats: Stat,
     xp: i64,
+    // saves from before positions and buffs were stored don't have these
+    #[serde(default = "no_posn")]
+    loc: Option<Vector3>,
+    #[serde(default = "no_posn")]
+    return_posn: Option<Vector3>,
+    #[serde(default = "no_buffs")]
+    attack_buffs: Option<HashMap<DmgType, f64>>,
+    #[serde(default = "no_buffs")]
+    defense_buffs: Option<HashMap<DmgType, f64>>,
+    #[serde(default = "no_explored")]
+    explored: Option<ExploredSave>,
+}
+
+// the squares a player has seen, packed the same way world maps are
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExploredSave {
+    dim: Vector3,
+    chunks: Vec<Vec<u8>>,
+}
+
+fn no_explored() -> Option<ExploredSave> {
+    None
+}
+
+fn no_posn() -> Option<Vector3> {
+    None
+}
+
+fn no_buffs() -> Option<HashMap<DmgType, f64>> {
+    None
 }
 
 pub struct Player {
+    // where the player is kept in the players list
     id: usize,
+    // what other players know them by, accounts keep it between logins
+    pub number: u32,
     inventory: Inventory,
     drops: Inventory,
     equip: Inventory,
@@ -42,11 +85,31 @@ pub struct Player {
     pub return_posn: Vector3,
     pub sender: Sender<(PlayerOut, Option<usize>)>,
     pub username: Option<String>,
+    // this player's half of a trade with another player, if they're trading
+    pub trade: Option<TradeOffer>,
+    // names of players whose chat this player doesn't see
+    pub muted: HashSet<String>,
+    // lets a new connection take over this player if the old one drops
+    pub resume_token: String,
+    // when the connection dropped, the player is kept for a while in case it comes back
+    pub disconnected: Option<Instant>,
+    // kicked players are removed as soon as their connection closes
+    pub kicked: bool,
+    // the last display and static display sent, later ones only send what changed
+    last_display: Option<Image>,
+    last_static_display: Option<Image>,
+    // how their connection talks, legacy clients can't read display deltas
+    pub protocol: Protocol,
+    // what the player last saw with 'disp', None until they've looked
+    pub view: Option<Bounds>,
+    // every square the player has seen, for fog of war
+    explored: Map<bool>,
 }
 
 impl Player {
     pub fn new(
         id: usize,
+        number: u32,
         sender: Sender<(PlayerOut, Option<usize>)>,
         g: &GameData,
         rng: &mut StdRng,
@@ -56,13 +119,15 @@ impl Player {
         base_stats.insert(StatType::from("max_energy".to_string()), 10.0);
         base_stats.insert(StatType::from("speed".to_string()), 10.0);
         let mut inventory = Inventory::new();
-        inventory.set(ItemName::checked_from("stick".into(), g)?, 1);
-        inventory.set(ItemName::checked_from("workbench".into(), g)?, 1);
+        for item in &STARTING_ITEMS {
+            inventory.set(ItemName::checked_from(item.to_string(), g)?, 1);
+        }
 
         let buffs = default_empty_fields(&HashMap::new(), 1.0, &g.dmg);
 
         Ok(Player {
             id,
+            number,
             sender,
             inventory,
             drops: Inventory::new(),
@@ -74,6 +139,16 @@ impl Player {
             xp: 1000,
             return_posn: Vector3::zero(),
             username: None,
+            trade: None,
+            muted: HashSet::new(),
+            resume_token: auth::random_token(),
+            disconnected: None,
+            kicked: false,
+            last_display: None,
+            last_static_display: None,
+            protocol: Protocol::Legacy,
+            view: None,
+            explored: Map::new(Vector3::zero(), false),
             attack_buffs: buffs.clone(),
             defense_buffs: buffs,
         })
@@ -86,20 +161,100 @@ impl Player {
             wear: self.wear.clone(),
             stats: self.stats.clone(),
             xp: self.xp,
+            loc: Some(self.loc),
+            return_posn: Some(self.return_posn),
+            attack_buffs: Some(self.attack_buffs.clone()),
+            defense_buffs: Some(self.defense_buffs.clone()),
+            explored: Some(ExploredSave {
+                dim: self.explored.dim,
+                chunks: self.explored.pack(),
+            }),
         };
         Ok(serde_jacl::ser::to_string(&save)?)
     }
 
-    pub fn load(&mut self, s: String) -> Result<()> {
+    pub fn load(&mut self, s: String, world: &World, g: &GameData) -> Result<()> {
         let save: PlayerSave = serde_jacl::de::from_str(&s)?;
         self.inventory = save.inventory;
         self.equip = save.equip;
         self.wear = save.wear;
         self.stats = save.stats;
         self.xp = save.xp;
+        if let Some(buffs) = save.attack_buffs {
+            self.attack_buffs = default_empty_fields(&buffs, 1.0, &g.dmg);
+        }
+        if let Some(buffs) = save.defense_buffs {
+            self.defense_buffs = default_empty_fields(&buffs, 1.0, &g.dmg);
+        }
+        // the world may have changed since the player last logged in,
+        // so only go back to saved positions that can still be stood in
+        if let Some(loc) = save.loc {
+            if Self::can_stand_at(loc, world, g) {
+                self.loc_mut().set(loc);
+            }
+        }
+        if let Some(return_posn) = save.return_posn {
+            if Self::can_stand_at(return_posn, world, g) {
+                self.return_posn = return_posn;
+            }
+        }
+        // a map explored in a world of a different size means nothing here
+        if let Some(explored) = save.explored {
+            if explored.dim == world.blocks().dim {
+                self.explored = Map::from_packed(explored.dim, explored.chunks)?;
+            }
+        }
         Ok(())
     }
 
+    // anything sent while the player is disconnected is dropped
+    pub fn send(&self, p_out: PlayerOut) {
+        let _ = self.sender.send((p_out, None));
+    }
+
+    // tells the player why and drops their connection,
+    // they're removed from the game once it's closed
+    pub fn kick(&mut self, reason: String) {
+        let mut p_out = PlayerOut::new();
+        p_out.append_err(anyhow!(reason));
+        p_out.append_close();
+        self.kicked = true;
+        self.send(p_out);
+    }
+
+    pub fn explored(&self) -> &Map<bool> {
+        &self.explored
+    }
+
+    pub fn explore(&mut self, dim: Vector3, seen: &HashSet<Vector3>) -> Result<()> {
+        if self.explored.dim != dim {
+            self.explored = Map::new(dim, false);
+        }
+        for posn in seen {
+            self.explored.set(*posn, true)?;
+        }
+        Ok(())
+    }
+
+    // the next displays are sent in full, for when the client doesn't have the last ones
+    pub fn forget_displays(&mut self) {
+        self.last_display = None;
+        self.last_static_display = None;
+    }
+
+    pub fn send_chat(&mut self, s: String) {
+        let mut p_out = PlayerOut::new();
+        p_out.append_chat(s);
+        self.send(p_out);
+    }
+
+    pub fn can_stand_at(posn: Vector3, world: &World, g: &GameData) -> bool {
+        match world.get_block_at(g, posn) {
+            Ok(block) => !block.solid,
+            Err(_) => false,
+        }
+    }
+
     pub fn respawn(&mut self, world: &World, g: &GameData) -> Result<()> {
         self.send_text(format!("respawning...\n"));
         self.stats_mut().reset_health(&g);
@@ -162,7 +317,7 @@ impl Entity for Player {
         if let Some(name) = &self.username {
             name.clone()
         } else {
-            format!("player {}", self.id)
+            format!("player {}", self.number)
         }
     }
 
@@ -199,25 +354,35 @@ impl Entity for Player {
     }
 
     fn send_display(&mut self, i: Image, static_display : bool) {
-        let mut p_out = PlayerOut::new();
-        if static_display {
-            p_out.append_static_display(i);
+        // legacy clients can't read deltas, they get the whole display whenever it changes
+        let deltas = self.protocol == Protocol::Json;
+        let last = if static_display {
+            &mut self.last_static_display
         } else {
-            p_out.append_display(i);
+            &mut self.last_display
+        };
+        let mut p_out = PlayerOut::new();
+        match last.as_ref().and_then(|prev| i.delta_from(prev)) {
+            Some(delta) if delta.is_empty() => return,
+            Some(delta) if deltas && static_display => p_out.append_static_display_delta(delta),
+            Some(delta) if deltas => p_out.append_display_delta(delta),
+            _ if static_display => p_out.append_static_display(i.clone()),
+            _ => p_out.append_display(i.clone()),
         }
-        self.sender.send((p_out, None)).unwrap();
+        *last = Some(i);
+        self.send(p_out);
     }
 
     fn send_text(&mut self, s: String) {
         let mut p_out = PlayerOut::new();
         p_out.append_text(s);
-        self.sender.send((p_out, None)).unwrap();
+        self.send(p_out);
     }
 
     fn send_image(&mut self, s: String) {
         let mut p_out = PlayerOut::new();
         p_out.append_img(s);
-        self.sender.send((p_out, None)).unwrap();
+        self.send(p_out);
     }
 
     fn id(&self) -> ID {
@@ -0,0 +1,81 @@
+// the numbers players are known by, kept apart from their slot in the players list.
+// accounts keep the same number every time they log in, guests get a new one each time they connect
+use crate::{player_ids_file, save};
+use anyhow::{anyhow, Result};
+use std::{collections::HashMap, fs, io};
+
+pub struct PlayerIds {
+    // stored one "<username> <number>" per line
+    accounts: HashMap<String, u32>,
+    next: u32,
+}
+
+impl PlayerIds {
+    pub fn load() -> Result<Self> {
+        let text = match fs::read_to_string(player_ids_file()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut accounts = HashMap::new();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next().map(|n| n.parse::<u32>())) {
+                (Some(name), Some(Ok(number))) => {
+                    accounts.insert(name.to_string(), number);
+                }
+                _ => {
+                    return Err(anyhow!(format!(
+                        "bad line '{}' in {}",
+                        line,
+                        player_ids_file()
+                    )))
+                }
+            }
+        }
+        // new numbers start past every account's, so two accounts never share one
+        let next = accounts.values().map(|n| n + 1).max().unwrap_or(0);
+        Ok(Self { accounts, next })
+    }
+
+    fn take_next(&mut self) -> Result<u32> {
+        let number = self.next;
+        self.next = self
+            .next
+            .checked_add(1)
+            .ok_or(anyhow!("ran out of player numbers"))?;
+        Ok(number)
+    }
+
+    // guest numbers aren't saved, nobody can connect as the same guest again
+    pub fn guest(&mut self) -> Result<u32> {
+        self.take_next()
+    }
+
+    pub fn account(&mut self, name: &str) -> Result<u32> {
+        if let Some(number) = self.accounts.get(name) {
+            return Ok(*number);
+        }
+        let number = self.take_next()?;
+        self.accounts.insert(name.to_string(), number);
+        self.write()?;
+        Ok(number)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if self.accounts.remove(name).is_some() {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    fn write(&self) -> Result<()> {
+        let mut accounts: Vec<(&String, &u32)> = self.accounts.iter().collect();
+        accounts.sort_by_key(|(_, number)| **number);
+        let text: String = accounts
+            .iter()
+            .map(|(name, number)| format!("{} {}\n", name, number))
+            .collect();
+        save::write_file(&player_ids_file(), text.as_bytes(), 0)
+    }
+}
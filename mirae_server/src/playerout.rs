@@ -1,7 +1,11 @@
-use crate::display::Image;
+use crate::{
+    display::{Image, ImageDelta},
+    protocol::Event,
+};
 
 use std::collections::VecDeque;
 
+// packet types of the legacy wire format
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum PacketType {
     Text = 0,
@@ -10,6 +14,9 @@ pub enum PacketType {
     Err = 3,
     Img = 4,
     StaticDisplay = 5,
+    Chat = 6,
+    DisplayDelta = 7,
+    StaticDisplayDelta = 8,
 }
 
 impl std::fmt::Display for PacketType {
@@ -33,71 +40,78 @@ impl Packet {
 }
 
 pub struct PlayerOut {
-    packets: VecDeque<Packet>,
+    events: VecDeque<Event>,
+    // the connection is closed once these events are sent
+    close: bool,
 }
 
 impl PlayerOut {
     pub fn new() -> Self {
         PlayerOut {
-            packets: VecDeque::new(),
+            events: VecDeque::new(),
+            close: false,
         }
     }
 
+    pub fn append_close(&mut self) {
+        self.close = true;
+    }
+
+    pub fn closes(&self) -> bool {
+        self.close
+    }
+
     pub fn append_text<S: Into<String>>(&mut self, text: S) {
-        if let Some(mut most_recent_pkt) = self.packets.pop_back() {
-            if most_recent_pkt.p_type == PacketType::Text {
-                most_recent_pkt
-                    .content
-                    .append(&mut text.into().into_bytes());
-                self.packets.push_back(most_recent_pkt);
-                return;
-            }
+        if let Some(Event::Text { text: most_recent }) = self.events.back_mut() {
+            most_recent.push_str(&text.into());
+            return;
         }
-        self.add_pkt(Packet {
-            p_type: PacketType::Text,
-            content: text.into().into_bytes(),
-        });
+        self.add_event(Event::Text { text: text.into() });
+    }
+
+    // chat isn't merged into text so the client can tell them apart
+    pub fn append_chat(&mut self, text: String) {
+        self.add_event(Event::Chat { text });
     }
 
     pub fn append_err(&mut self, err: anyhow::Error) {
-        self.add_pkt(Packet {
-            p_type: PacketType::Err,
-            content: err.to_string().into_bytes(),
+        self.add_event(Event::Error {
+            message: err.to_string(),
         });
     }
 
-    pub fn append_display(&mut self, img: Image) {
-        self.add_pkt(Packet {
-            p_type: PacketType::Display,
-            content: img.into_bytes(),
-        });
+    pub fn append_display(&mut self, image: Image) {
+        self.add_event(Event::Display { image });
     }
 
-    pub fn append_static_display(&mut self, img: Image) {
-        self.add_pkt(Packet {
-            p_type: PacketType::StaticDisplay,
-            content: img.into_bytes(),
-        });
+    pub fn append_static_display(&mut self, image: Image) {
+        self.add_event(Event::StaticDisplay { image });
     }
 
-    pub fn append_img(&mut self, img: String) {
-        self.add_pkt(Packet {
-            p_type: PacketType::Img,
-            content: img.into_bytes(),
-        });
+    pub fn append_display_delta(&mut self, delta: ImageDelta) {
+        self.add_event(Event::DisplayDelta { delta });
+    }
+
+    pub fn append_static_display_delta(&mut self, delta: ImageDelta) {
+        self.add_event(Event::StaticDisplayDelta { delta });
+    }
+
+    pub fn append_img(&mut self, name: String) {
+        self.add_event(Event::Image { name });
     }
 
-    pub fn get_pkt(&mut self) -> Option<Packet> {
-        self.packets.pop_front()
+    pub fn get_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
     }
 
     pub fn append_player_out(&mut self, mut p_out: PlayerOut) {
-        while let Some(p) = p_out.get_pkt() {
-            self.add_pkt(p);
+        self.close |= p_out.close;
+        while let Some(e) = p_out.get_event() {
+            self.add_event(e);
         }
     }
 
-    pub fn add_pkt(&mut self, p: Packet) {
-        self.packets.push_back(p);
+    pub fn add_event(&mut self, e: Event) {
+        self.events.push_back(e);
     }
 }
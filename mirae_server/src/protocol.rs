@@ -0,0 +1,151 @@
+// the messages sent between the server and its clients.
+// clients that ask for the "mirae.json.v1" websocket subprotocol talk JSON both ways,
+// they send commands like {"type": "action", "args": ["move", 1, 0]}
+// and get back events like {"type": "text", "text": "respawning...\n"}.
+// everyone else gets the legacy format: jacl lists in, "Type:len:" packets out
+use crate::{
+    display::{Image, ImageDelta},
+    playerout::{Packet, PacketType},
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_jacl::{
+    de::from_str,
+    structs::{Literal, Number},
+};
+use std::collections::VecDeque;
+use websocket::OwnedMessage;
+
+// bumped whenever a command or event changes in a way old clients can't handle
+pub const VERSION: u32 = 1;
+pub const JSON_PROTOCOL: &str = "mirae.json.v1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Legacy,
+    Json,
+}
+
+// what clients send
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    // a game command, the same words and numbers that would be typed in
+    Action { args: Vec<Arg> },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Arg {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    String(String),
+}
+
+impl Arg {
+    fn into_literal(self) -> Literal {
+        match self {
+            Arg::Int(i) => Literal::Number(Number::Int(i)),
+            Arg::Float(f) => Literal::Number(Number::Flt(f)),
+            Arg::Bool(b) => Literal::Bool(b),
+            Arg::String(s) => Literal::String(s),
+        }
+    }
+}
+
+// what the server sends
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    // sent first, with the images the client should load and its reconnect token
+    Init {
+        version: u32,
+        data: serde_json::Value,
+    },
+    Text {
+        text: String,
+    },
+    // chat is kept apart from text so the client can show it differently
+    Chat {
+        text: String,
+    },
+    Error {
+        message: String,
+    },
+    // the map around the player
+    Display {
+        image: Image,
+    },
+    // a map that stays up in the side panel, like during battles
+    StaticDisplay {
+        image: Image,
+    },
+    // what changed since the last display or static display
+    DisplayDelta {
+        delta: ImageDelta,
+    },
+    StaticDisplayDelta {
+        delta: ImageDelta,
+    },
+    // the name of a picture to show in the side panel, "none" clears it
+    Image {
+        name: String,
+    },
+}
+
+impl Event {
+    fn into_packet(self) -> Packet {
+        let (p_type, content) = match self {
+            Event::Init { data, .. } => (PacketType::Init, data.to_string().into_bytes()),
+            Event::Text { text } => (PacketType::Text, text.into_bytes()),
+            Event::Chat { text } => (PacketType::Chat, text.into_bytes()),
+            Event::Error { message } => (PacketType::Err, message.into_bytes()),
+            Event::Display { image } => (PacketType::Display, image.into_bytes()),
+            Event::StaticDisplay { image } => (PacketType::StaticDisplay, image.into_bytes()),
+            Event::DisplayDelta { delta } => (PacketType::DisplayDelta, delta.into_bytes()),
+            Event::StaticDisplayDelta { delta } => {
+                (PacketType::StaticDisplayDelta, delta.into_bytes())
+            }
+            Event::Image { name } => (PacketType::Img, name.into_bytes()),
+        };
+        Packet { p_type, content }
+    }
+}
+
+impl Protocol {
+    // from the subprotocols the client offered in its handshake
+    pub fn negotiate(offered: &[String]) -> Self {
+        if offered.iter().any(|p| p == JSON_PROTOCOL) {
+            Protocol::Json
+        } else {
+            Protocol::Legacy
+        }
+    }
+
+    // reads a command from the client as the params of a game action
+    pub fn parse(self, text: &str) -> Result<VecDeque<Literal>> {
+        match self {
+            Protocol::Legacy => match from_str::<Vec<Literal>>(text) {
+                Ok(params) => Ok(VecDeque::from(params)),
+                Err(e) => Err(anyhow!(format!(
+                    "couldn't read '{}', commands look like [\"move\" 1 0]: {}",
+                    text, e
+                ))),
+            },
+            Protocol::Json => match serde_json::from_str::<Command>(text) {
+                Ok(Command::Action { args }) => {
+                    Ok(args.into_iter().map(Arg::into_literal).collect())
+                }
+                Err(e) => Err(anyhow!(format!("couldn't read command: {}", e))),
+            },
+        }
+    }
+
+    pub fn encode(self, event: Event) -> Result<OwnedMessage> {
+        match self {
+            Protocol::Legacy => Ok(OwnedMessage::Binary(event.into_packet().bytes())),
+            Protocol::Json => Ok(OwnedMessage::Text(serde_json::to_string(&event)?)),
+        }
+    }
+}
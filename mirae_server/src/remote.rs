@@ -0,0 +1,91 @@
+// lets scripts run console commands on a live server. it only listens on loopback,
+// and every connection has to send the admin token as its first line.
+// after that each line is a command, answered by whatever it printed
+// and then a line that's either "ok" or "error: <what went wrong>"
+use crate::auth::{random_token, tokens_match};
+use anyhow::{anyhow, Result};
+use std::{
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    thread::spawn,
+};
+
+// the token is kept between restarts so scripts don't need to be changed
+pub fn read_or_create_token(path: &str) -> Result<String> {
+    if Path::new(path).exists() {
+        let token = fs::read_to_string(path)?.trim().to_string();
+        if token.is_empty() {
+            return Err(anyhow!(format!("the admin token in {} is empty", path)));
+        }
+        return Ok(token);
+    }
+
+    let token = random_token();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        // only the user running the server should be able to read it
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+pub fn listen<F>(port: u16, token: String, run: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<String> + Clone + Send + 'static,
+{
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let token = token.clone();
+            let run = run.clone();
+            spawn(move || {
+                if let Err(e) = handle_admin(stream, &token, &run) {
+                    println!("admin connection error: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn handle_admin<F>(stream: TcpStream, token: &str, run: &F) -> Result<()>
+where
+    F: Fn(&str) -> Result<String>,
+{
+    let mut writer = stream.try_clone()?;
+    let mut lines = BufReader::new(stream).lines();
+    let given = match lines.next() {
+        Some(line) => line?,
+        None => return Ok(()),
+    };
+    if !tokens_match(given.trim(), token) {
+        writeln!(writer, "error: wrong token")?;
+        return Ok(());
+    }
+    writeln!(writer, "ok")?;
+
+    for line in lines {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match run(line) {
+            Ok(text) => {
+                if !text.is_empty() {
+                    writeln!(writer, "{}", text)?;
+                }
+                writeln!(writer, "ok")?;
+            }
+            // errors are kept to one line so they always end the reply
+            Err(e) => writeln!(writer, "error: {}", e.to_string().replace('\n', " "))?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,626 @@
+use crate::{
+    gamedata::gamedata::GameData,
+    map::Map,
+    rgb::RGB,
+    vector3::Vector3,
+    world::{MobU16, World, WorldState},
+};
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+// every versioned world save starts with these bytes,
+// files that don't are treated as the original unversioned format (v0)
+const MAGIC: &[u8; 8] = b"MIRAEWLD";
+pub const VERSION: u32 = 3;
+
+// sections in a versioned save, each one is stored as
+// tag (4 bytes), length (u64), crc32 of the data (u32), data.
+// since v3 BLKS, MOBS and CLRS hold a list of packed chunks,
+// each one stored as length (u32), data
+const SEED: [u8; 4] = *b"SEED";
+const BLOCK_NAMES: [u8; 4] = *b"BNAM";
+const MOB_NAMES: [u8; 4] = *b"MNAM";
+const DIMENSIONS: [u8; 4] = *b"DIMS";
+const BLOCKS: [u8; 4] = *b"BLKS";
+const MOBS: [u8; 4] = *b"MOBS";
+const COLORS: [u8; 4] = *b"CLRS";
+// added in v2, the live state of the world (spawned mobs, rng) as jacl text
+const LIVE: [u8; 4] = *b"LIVE";
+
+pub struct Load {
+    pub seed: u64,
+    pub block_names: Vec<String>,
+    pub mob_names: Vec<String>,
+    pub blocks: Map<u8>,
+    pub mobs: Map<MobU16>,
+    pub colors: Map<RGB>,
+    pub state: Option<WorldState>,
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            if crc & 1 == 1 {
+                crc = (crc >> 1) ^ 0xEDB8_8320;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+fn tag_name(tag: &[u8; 4]) -> String {
+    String::from_utf8_lossy(tag).into_owned()
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    posn: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, posn: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.posn
+    }
+
+    fn take(&mut self, len: usize, what: &str) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(anyhow!(
+                "save file is truncated, needed {} bytes for {} but only {} are left",
+                len,
+                what,
+                self.remaining()
+            ));
+        }
+        let slice = &self.bytes[self.posn..(self.posn + len)];
+        self.posn += len;
+        Ok(slice)
+    }
+
+    fn u16(&mut self, what: &str) -> Result<u16> {
+        let b = self.take(2, what)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self, what: &str) -> Result<u32> {
+        let b = self.take(4, what)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self, what: &str) -> Result<u64> {
+        let b = self.take(8, what)?;
+        let mut buf = [0; 8];
+        buf.copy_from_slice(b);
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    // read a single null-terminated string
+    fn string(&mut self, what: &str) -> Result<String> {
+        let mut string = Vec::new();
+        loop {
+            let val = self.take(1, what)?[0];
+            if val == 0 {
+                break;
+            }
+            string.push(val);
+        }
+        Ok(String::from_utf8(string)?)
+    }
+
+    fn names(&mut self, what: &str) -> Result<Vec<String>> {
+        let num = self.u32(what)?;
+        let mut names = Vec::new();
+        for _ in 0..num {
+            names.push(self.string(what)?);
+        }
+        Ok(names)
+    }
+
+    fn finish(&self, what: &str) -> Result<()> {
+        if self.remaining() != 0 {
+            Err(anyhow!(
+                "{} has {} unexpected trailing bytes",
+                what,
+                self.remaining()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn names_to_bytes(names: Vec<String>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    // write number of names
+    bytes.extend_from_slice(&(names.len() as u32).to_le_bytes());
+    // write strings, null terminated
+    for name in names {
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn chunks_to_bytes(chunks: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+    for chunk in chunks {
+        bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&chunk);
+    }
+    bytes
+}
+
+fn chunks_from_bytes(mut reader: ByteReader, name: &str) -> Result<Vec<Vec<u8>>> {
+    let what = format!("chunks in section {}", name);
+    let num = reader.u32(&what)?;
+    let mut chunks = Vec::new();
+    for _ in 0..num {
+        let len = reader.u32(&what)? as usize;
+        chunks.push(reader.take(len, &what)?.to_vec());
+    }
+    reader.finish(&format!("section {}", name))?;
+    Ok(chunks)
+}
+
+fn blocks_from_bytes(bytes: &[u8], dim: Vector3) -> Result<Map<u8>> {
+    let size = dim.dim() as usize;
+    if bytes.len() != size {
+        return Err(anyhow!("expected {} blocks, found {}", size, bytes.len()));
+    }
+    Map::from_vec(dim, bytes.to_vec())
+}
+
+fn mobs_from_bytes(bytes: &[u8], dim: Vector3) -> Result<Map<MobU16>> {
+    let size = dim.dim() as usize;
+    if bytes.len() != size * 2 {
+        return Err(anyhow!("expected {} mobs, found {} bytes", size, bytes.len()));
+    }
+    Map::from_vec(
+        dim,
+        bytes
+            .chunks_exact(2)
+            .map(|a| MobU16(u16::from_le_bytes([a[0], a[1]])))
+            .collect(),
+    )
+}
+
+fn colors_from_bytes(bytes: &[u8], dim: Vector3) -> Result<Map<RGB>> {
+    let size = dim.dim() as usize;
+    if bytes.len() != size * 3 {
+        return Err(anyhow!(
+            "expected {} colors, found {} bytes",
+            size,
+            bytes.len()
+        ));
+    }
+    Map::from_vec(
+        dim,
+        bytes
+            .chunks_exact(3)
+            .map(|a| RGB::new(a[0], a[1], a[2]))
+            .collect(),
+    )
+}
+
+pub fn write_world<W: Write>(out: &mut W, world: &mut World, g: &GameData) -> Result<()> {
+    let block_names = g.block_names();
+    let mob_names = g.mob_names();
+
+    let dim = world.blocks().dim;
+    let mut dims = Vec::new();
+    dims.extend_from_slice(&(dim.x() as u32).to_le_bytes());
+    dims.extend_from_slice(&(dim.y() as u32).to_le_bytes());
+    dims.extend_from_slice(&(dim.z() as u32).to_le_bytes());
+
+    let blocks = chunks_to_bytes(world.blocks().pack());
+    let mobs = chunks_to_bytes(world.mobs().pack());
+    let colors = chunks_to_bytes(world.colors().pack());
+    let live = serde_jacl::ser::to_string(&world.save_state())?.into_bytes();
+
+    let sections = vec![
+        (SEED, world.seed.to_le_bytes().to_vec()),
+        (BLOCK_NAMES, names_to_bytes(block_names)),
+        (MOB_NAMES, names_to_bytes(mob_names)),
+        (DIMENSIONS, dims),
+        (BLOCKS, blocks),
+        (MOBS, mobs),
+        (COLORS, colors),
+        (LIVE, live),
+    ];
+
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(sections.len() as u32).to_le_bytes())?;
+    for (tag, data) in sections {
+        out.write_all(&tag)?;
+        out.write_all(&(data.len() as u64).to_le_bytes())?;
+        out.write_all(&crc32(&data).to_le_bytes())?;
+        out.write_all(&data)?;
+    }
+    Ok(())
+}
+
+pub fn read_world(bytes: &[u8]) -> Result<Load> {
+    if bytes.len() >= MAGIC.len() && &bytes[..MAGIC.len()] == MAGIC {
+        read_versioned(&bytes[MAGIC.len()..])
+    } else {
+        read_v0(bytes)
+    }
+}
+
+// the original format: seed, block names, mob names, u16 dimensions,
+// then blocks, mobs and colors with no framing at all
+fn read_v0(bytes: &[u8]) -> Result<Load> {
+    let mut reader = ByteReader::new(bytes);
+    let seed = reader.u64("seed")?;
+    let block_names = reader.names("block names")?;
+    let mob_names = reader.names("mob names")?;
+    let dim = Vector3::new(
+        reader.u16("dimensions")? as isize,
+        reader.u16("dimensions")? as isize,
+        reader.u16("dimensions")? as isize,
+    );
+    let size = dim.dim() as usize;
+    let blocks = blocks_from_bytes(reader.take(size, "blocks")?, dim)?;
+    let mobs = mobs_from_bytes(reader.take(size * 2, "mobs")?, dim)?;
+    let colors = colors_from_bytes(reader.take(size * 3, "colors")?, dim)?;
+    reader.finish("v0 save file")?;
+
+    Ok(Load {
+        seed,
+        block_names,
+        mob_names,
+        blocks,
+        mobs,
+        colors,
+        state: None,
+    })
+}
+
+fn read_versioned(bytes: &[u8]) -> Result<Load> {
+    let mut reader = ByteReader::new(bytes);
+    let version = reader.u32("version")?;
+    if version == 0 || version > VERSION {
+        return Err(anyhow!(
+            "save file has version {}, this server can only load versions up to {}",
+            version,
+            VERSION
+        ));
+    }
+
+    let num_sections = reader.u32("section count")?;
+    let mut sections = HashMap::new();
+    for _ in 0..num_sections {
+        let mut tag = [0; 4];
+        tag.copy_from_slice(reader.take(4, "section tag")?);
+        let name = tag_name(&tag);
+        let len = reader.u64(&format!("length of section {}", name))? as usize;
+        let crc = reader.u32(&format!("checksum of section {}", name))?;
+        let data = reader.take(len, &format!("section {}", name))?;
+        if crc32(data) != crc {
+            return Err(anyhow!("section {} is corrupted, checksum mismatch", name));
+        }
+        if sections.insert(tag, data).is_some() {
+            return Err(anyhow!("section {} appears more than once", name));
+        }
+    }
+    reader.finish("save file")?;
+
+    match version {
+        1 => read_v1(&sections),
+        2 => read_v2(&sections),
+        3 => read_v3(&sections),
+        _ => unreachable!("version was checked above"),
+    }
+}
+
+fn section<'a>(sections: &HashMap<[u8; 4], &'a [u8]>, tag: [u8; 4]) -> Result<ByteReader<'a>> {
+    sections
+        .get(&tag)
+        .map(|data| ByteReader::new(*data))
+        .ok_or_else(|| anyhow!("save file is missing section {}", tag_name(&tag)))
+}
+
+struct Header {
+    seed: u64,
+    block_names: Vec<String>,
+    mob_names: Vec<String>,
+    dim: Vector3,
+}
+
+fn read_header(sections: &HashMap<[u8; 4], &[u8]>) -> Result<Header> {
+    let mut seed = section(sections, SEED)?;
+    let mut block_names = section(sections, BLOCK_NAMES)?;
+    let mut mob_names = section(sections, MOB_NAMES)?;
+    let mut dims = section(sections, DIMENSIONS)?;
+
+    let (x, y, z) = (
+        dims.u32("dimensions")?,
+        dims.u32("dimensions")?,
+        dims.u32("dimensions")?,
+    );
+    // big enough to overflow when working out how many bytes the maps take up
+    let cells = (x as usize)
+        .checked_mul(y as usize)
+        .and_then(|cells| cells.checked_mul(z as usize))
+        .and_then(|cells| cells.checked_mul(3));
+    if cells.is_none() {
+        return Err(anyhow!(format!(
+            "save file has dimensions {}x{}x{}, which are too big",
+            x, y, z
+        )));
+    }
+
+    let header = Header {
+        seed: seed.u64("seed")?,
+        block_names: block_names.names("block names")?,
+        mob_names: mob_names.names("mob names")?,
+        dim: Vector3::new(x as isize, y as isize, z as isize),
+    };
+    seed.finish("section SEED")?;
+    block_names.finish("section BNAM")?;
+    mob_names.finish("section MNAM")?;
+    dims.finish("section DIMS")?;
+    Ok(header)
+}
+
+fn read_live(sections: &HashMap<[u8; 4], &[u8]>) -> Result<WorldState> {
+    let live = std::str::from_utf8(section(sections, LIVE)?.bytes)?;
+    Ok(serde_jacl::de::from_str(live)?)
+}
+
+fn read_v1(sections: &HashMap<[u8; 4], &[u8]>) -> Result<Load> {
+    let header = read_header(sections)?;
+    let dim = header.dim;
+    Ok(Load {
+        seed: header.seed,
+        block_names: header.block_names,
+        mob_names: header.mob_names,
+        blocks: blocks_from_bytes(section(sections, BLOCKS)?.bytes, dim)?,
+        mobs: mobs_from_bytes(section(sections, MOBS)?.bytes, dim)?,
+        colors: colors_from_bytes(section(sections, COLORS)?.bytes, dim)?,
+        state: None,
+    })
+}
+
+// v2 is v1 plus the live world state
+fn read_v2(sections: &HashMap<[u8; 4], &[u8]>) -> Result<Load> {
+    let mut load = read_v1(sections)?;
+    load.state = Some(read_live(sections)?);
+    Ok(load)
+}
+
+// v3 stores the maps as packed chunks, which are only unpacked when they're first used
+fn read_v3(sections: &HashMap<[u8; 4], &[u8]>) -> Result<Load> {
+    let header = read_header(sections)?;
+    let dim = header.dim;
+    Ok(Load {
+        seed: header.seed,
+        block_names: header.block_names,
+        mob_names: header.mob_names,
+        blocks: Map::from_packed(dim, chunks_from_bytes(section(sections, BLOCKS)?, "BLKS")?)
+            .map_err(|e| anyhow!(format!("section BLKS: {}", e)))?,
+        mobs: Map::from_packed(dim, chunks_from_bytes(section(sections, MOBS)?, "MOBS")?)
+            .map_err(|e| anyhow!(format!("section MOBS: {}", e)))?,
+        colors: Map::from_packed(dim, chunks_from_bytes(section(sections, COLORS)?, "CLRS")?)
+            .map_err(|e| anyhow!(format!("section CLRS: {}", e)))?,
+        state: Some(read_live(sections)?),
+    })
+}
+
+// writes to a temporary file next to path and renames it into place once it's complete,
+// so a crash partway through leaves the last save intact.
+// the `keep` saves before this one are kept as path.1 (the newest) up to path.<keep>
+pub fn write_file(path: &str, bytes: &[u8], keep: usize) -> Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    rotate(path, keep)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn rotate(path: &str, keep: usize) -> Result<()> {
+    if keep == 0 || !Path::new(path).exists() {
+        return Ok(());
+    }
+    for i in (1..keep).rev() {
+        let older = format!("{}.{}", path, i);
+        if Path::new(&older).exists() {
+            fs::rename(&older, format!("{}.{}", path, i + 1))?;
+        }
+    }
+    // copied rather than moved, so there's always a save at path
+    fs::copy(path, format!("{}.1", path))?;
+    Ok(())
+}
+
+// removes a save along with the older copies kept by write_file
+pub fn remove_file(path: &str) -> Result<()> {
+    fs::remove_file(path)?;
+    let mut i = 1;
+    while Path::new(&format!("{}.{}", path, i)).exists() {
+        fs::remove_file(format!("{}.{}", path, i))?;
+        i += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_gamemode;
+
+    fn gamedata() -> Result<GameData> {
+        read_gamemode("pvp/gamemode.jacl")?.into_gamedata()
+    }
+
+    // a world small enough to check cell by cell, with chunks cut short on the edges
+    fn load(g: &GameData) -> Result<Load> {
+        let dim = Vector3::new(40, 35, 2);
+        let mut blocks = Map::new(dim, 0u8);
+        let mut mobs = Map::new(dim, MobU16::empty());
+        let mut colors = Map::new(dim, RGB::new(30, 30, 30));
+        for i in 0..(dim.dim() as usize) {
+            blocks.direct_set(i, (i % 7) as u8);
+            colors.direct_set(i, RGB::new(i as u8, 30, 200));
+        }
+        mobs.set(Vector3::new(39, 34, 1), MobU16(1))?;
+        Ok(Load {
+            seed: 1234,
+            block_names: g.block_names(),
+            mob_names: g.mob_names(),
+            blocks,
+            mobs,
+            colors,
+            state: None,
+        })
+    }
+
+    fn cells<T: Clone + Eq>(map: &Map<T>) -> Vec<T> {
+        (0..(map.dim.dim() as usize))
+            .map(|i| map.direct_get(i))
+            .collect()
+    }
+
+    fn assert_same(a: &Load, b: &Load) {
+        assert_eq!(a.seed, b.seed);
+        assert_eq!(a.block_names, b.block_names);
+        assert_eq!(a.mob_names, b.mob_names);
+        assert_eq!(a.blocks.dim, b.blocks.dim);
+        assert!(cells(&a.blocks) == cells(&b.blocks));
+        assert!(cells(&a.mobs) == cells(&b.mobs));
+        assert!(cells(&a.colors) == cells(&b.colors));
+    }
+
+    fn saved(g: &GameData) -> Result<Vec<u8>> {
+        let mut world = World::from_load(load(g)?, g)?;
+        let mut bytes = Vec::new();
+        write_world(&mut bytes, &mut world, g)?;
+        Ok(bytes)
+    }
+
+    // where each section's header starts and where its data ends
+    fn section_bounds(bytes: &[u8]) -> Vec<(usize, usize)> {
+        let mut reader = ByteReader::new(&bytes[MAGIC.len()..]);
+        reader.u32("version").unwrap();
+        let num = reader.u32("section count").unwrap();
+        let mut bounds = Vec::new();
+        for _ in 0..num {
+            let start = MAGIC.len() + reader.posn;
+            reader.take(4, "tag").unwrap();
+            let len = reader.u64("length").unwrap() as usize;
+            reader.u32("checksum").unwrap();
+            reader.take(len, "data").unwrap();
+            bounds.push((start, MAGIC.len() + reader.posn));
+        }
+        bounds
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let g = gamedata()?;
+        let loaded = read_world(&saved(&g)?)?;
+        assert_same(&loaded, &load(&g)?);
+        assert!(loaded.state.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn reads_v0() -> Result<()> {
+        let g = gamedata()?;
+        let expected = load(&g)?;
+        let dim = expected.blocks.dim;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&expected.seed.to_le_bytes());
+        bytes.extend(names_to_bytes(expected.block_names.clone()));
+        bytes.extend(names_to_bytes(expected.mob_names.clone()));
+        for d in &[dim.x(), dim.y(), dim.z()] {
+            bytes.extend_from_slice(&(*d as u16).to_le_bytes());
+        }
+        bytes.extend(cells(&expected.blocks));
+        for mob in cells(&expected.mobs) {
+            bytes.extend_from_slice(&mob.0.to_le_bytes());
+        }
+        for color in cells(&expected.colors) {
+            bytes.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+
+        let load = read_world(&bytes)?;
+        assert_same(&load, &expected);
+        assert!(load.state.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_truncated_files() -> Result<()> {
+        let g = gamedata()?;
+        let bytes = saved(&g)?;
+        let mut cuts = vec![0, 4, MAGIC.len(), MAGIC.len() + 4];
+        for (start, end) in section_bounds(&bytes) {
+            // at the boundary, partway through the header and partway through the data
+            cuts.extend(&[start, start + 6, start + 16, end - 1]);
+        }
+        for cut in cuts {
+            assert!(
+                read_world(&bytes[..cut]).is_err(),
+                "loaded a save cut off after {} bytes",
+                cut
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_corrupted_sections() -> Result<()> {
+        let g = gamedata()?;
+        let bytes = saved(&g)?;
+        for (start, end) in section_bounds(&bytes) {
+            let mut corrupted = bytes.clone();
+            corrupted[end - 1] ^= 0xff;
+            let e = read_world(&corrupted)
+                .err()
+                .expect("loaded a corrupted save");
+            let name = tag_name(&[
+                bytes[start],
+                bytes[start + 1],
+                bytes[start + 2],
+                bytes[start + 3],
+            ]);
+            assert!(e.to_string().contains(&name), "{}", e);
+            assert!(e.to_string().contains("checksum"), "{}", e);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_overflowing_dims() -> Result<()> {
+        let g = gamedata()?;
+        let mut bytes = saved(&g)?;
+        for (start, end) in section_bounds(&bytes) {
+            if bytes[start..(start + 4)] != DIMENSIONS {
+                continue;
+            }
+            let dims = [u32::MAX.to_le_bytes(); 3].concat();
+            bytes[(end - 12)..end].copy_from_slice(&dims);
+            bytes[(start + 12)..(start + 16)].copy_from_slice(&crc32(&dims).to_le_bytes());
+        }
+        let e = read_world(&bytes).err().expect("loaded dims that overflow");
+        assert!(e.to_string().contains("too big"), "{}", e);
+        Ok(())
+    }
+}
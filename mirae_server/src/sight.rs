@@ -0,0 +1,127 @@
+// what players can see of the world.
+// players only see squares in line of sight, solid blocks that don't let light through hide
+// what's behind them. terrain they've seen before stays on their map, but the mobs and players
+// on it don't, and squares they've never seen are shown as unknown
+use crate::{
+    actions::VIEW_DIST,
+    display::{Bounds, Image},
+    entity::Entity,
+    gamedata::gamedata::GameData,
+    map::Map,
+    player::Player,
+    vector3::Vector3,
+    world::World,
+};
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+
+pub enum Sight<'a> {
+    // admin tools see everything
+    All,
+    Player {
+        visible: &'a HashSet<Vector3>,
+        explored: &'a Map<bool>,
+    },
+}
+
+impl Sight<'_> {
+    // whether mobs and players on this square can be seen
+    pub fn sees(&self, posn: Vector3) -> bool {
+        match self {
+            Sight::All => true,
+            Sight::Player { visible, .. } => visible.contains(&posn),
+        }
+    }
+
+    // whether the terrain on this square can be shown
+    pub fn knows(&self, posn: Vector3) -> bool {
+        match self {
+            Sight::All => true,
+            Sight::Player { visible, explored } => {
+                visible.contains(&posn) || explored.get(posn).unwrap_or(false)
+            }
+        }
+    }
+}
+
+// the squares between two points on the same layer, not counting either end
+fn between(from: Vector3, to: Vector3) -> Vec<Vector3> {
+    let dx = (to.x() - from.x()).abs();
+    let dy = -(to.y() - from.y()).abs();
+    let sx = if from.x() < to.x() { 1 } else { -1 };
+    let sy = if from.y() < to.y() { 1 } else { -1 };
+    let mut err = dx + dy;
+    let (mut x, mut y) = (from.x(), from.y());
+    let mut squares = Vec::new();
+    loop {
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+        if x == to.x() && y == to.y() {
+            break;
+        }
+        squares.push(Vector3::new(x, y, from.z()));
+    }
+    squares
+}
+
+// the squares in bounds that can be seen from a point
+pub fn visible_from(
+    world: &World,
+    g: &GameData,
+    from: Vector3,
+    bounds: &Bounds,
+) -> Result<HashSet<Vector3>> {
+    let mut visible = HashSet::new();
+    for posn in bounds.posns() {
+        if posn == from {
+            visible.insert(posn);
+            continue;
+        }
+        let mut clear = true;
+        for square in between(from, posn) {
+            if world.get_block_at(g, square)?.blocks_sight() {
+                clear = false;
+                break;
+            }
+        }
+        if clear {
+            visible.insert(posn);
+        }
+    }
+    Ok(visible)
+}
+
+// what a player sees of the world inside bounds,
+// anything they can see right now is added to what they've explored
+pub fn look(
+    world: &World,
+    players: &mut Vec<Option<Player>>,
+    g: &GameData,
+    id: usize,
+    bounds: &Bounds,
+    resolution: usize,
+) -> Result<Image> {
+    let player = players
+        .get_mut(id)
+        .and_then(|player| player.as_mut())
+        .ok_or(anyhow!(format!("no player with id {}", id)))?;
+    let dim = world.blocks().dim;
+    let loc = *player.loc();
+    let view = Bounds::get_bounds_centered(loc, VIEW_DIST, dim);
+    let visible = visible_from(world, g, loc, &view)?;
+    player.explore(dim, &visible)?;
+
+    let player = players[id].as_ref().expect("player was just found");
+    let sight = Sight::Player {
+        visible: &visible,
+        explored: player.explored(),
+    };
+    Image::new(world, players, g, bounds, resolution, &sight)
+}
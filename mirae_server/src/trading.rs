@@ -0,0 +1,237 @@
+// trades between players, each player holds their half of the trade.
+// both sides have to accept the same offers before any items change hands
+use crate::{
+    actions::{get, get_mut, get_two_mut},
+    combat::{BattleMap, ID},
+    entity::Entity,
+    gamedata::gamedata::ItemName,
+    inventory::Inventory,
+    player::Player,
+    vector3::Vector3,
+};
+use anyhow::{anyhow, Result};
+
+pub const TRADE_DIST: f64 = 5.0;
+
+pub struct TradeOffer {
+    pub partner: usize,
+    // what this player is giving up
+    pub items: Inventory,
+    pub accepted: bool,
+    // where the player was when the trade started, moving away cancels it
+    loc: Vector3,
+}
+
+impl TradeOffer {
+    fn new(partner: usize, loc: Vector3) -> Self {
+        Self {
+            partner,
+            items: Inventory::new(),
+            accepted: false,
+            loc,
+        }
+    }
+}
+
+fn offer(player: &Player) -> Result<&TradeOffer> {
+    player
+        .trade
+        .as_ref()
+        .ok_or(anyhow!("you aren't trading with anyone"))
+}
+
+fn offer_mut(player: &mut Player) -> Result<&mut TradeOffer> {
+    player
+        .trade
+        .as_mut()
+        .ok_or(anyhow!("you aren't trading with anyone"))
+}
+
+pub fn start(
+    players: &mut Vec<Option<Player>>,
+    battle_map: &BattleMap,
+    player_id: usize,
+    partner_id: usize,
+) -> Result<()> {
+    if player_id == partner_id {
+        return Err(anyhow!("you can't trade with yourself"));
+    }
+    let (player, partner) = get_two_mut(player_id, partner_id, players)?;
+    if player.trade.is_some() {
+        return Err(anyhow!("you're already trading, use 'trade cancel' first"));
+    }
+    if partner.trade.is_some() {
+        return Err(anyhow!(format!("{} is already trading", partner.name())));
+    }
+    if battle_map.in_battle(player.id()) || battle_map.in_battle(partner.id()) {
+        return Err(anyhow!("you can't trade during a battle"));
+    }
+    if (*partner.loc() - *player.loc()).sqr_mag() >= TRADE_DIST * TRADE_DIST {
+        return Err(anyhow!(format!(
+            "{} is too far away to trade",
+            partner.name()
+        )));
+    }
+
+    player.trade = Some(TradeOffer::new(partner_id, *player.loc()));
+    partner.trade = Some(TradeOffer::new(player_id, *partner.loc()));
+    player.send_text(format!(
+        "started trading with {}, add items with 'trade offer <item> <count>'\n",
+        partner.name()
+    ));
+    partner.send_text(format!(
+        "{} started trading with you, add items with 'trade offer <item> <count>' \
+         or stop with 'trade cancel'\n",
+        player.name()
+    ));
+    Ok(())
+}
+
+// adds (or with a negative count, takes back) items from the player's side of the trade,
+// which means both players have to accept again
+pub fn change_offer(
+    players: &mut Vec<Option<Player>>,
+    player_id: usize,
+    item: ItemName,
+    count: i64,
+) -> Result<()> {
+    let partner_id = offer(get(players, player_id)?)?.partner;
+    let (player, partner) = get_two_mut(player_id, partner_id, players)?;
+    let offered = offer(player)?.items.get(&item) as i64;
+    if offered + count < 0 {
+        return Err(anyhow!(format!(
+            "you've only offered {} of '{}'",
+            offered, item.0
+        )));
+    }
+    if offered + count > player.inventory().get(&item) as i64 {
+        return Err(anyhow!(format!(
+            "you don't have {} of '{}'",
+            offered + count,
+            item.0
+        )));
+    }
+
+    let mine = offer_mut(player)?;
+    mine.items.change(item, count)?;
+    mine.accepted = false;
+    offer_mut(partner)?.accepted = false;
+
+    let text = format!(
+        "{} is offering: {}\nyou are offering: {}\n",
+        player.name(),
+        offer(player)?.items.to_string(),
+        offer(partner)?.items.to_string()
+    );
+    partner.send_text(text);
+    player.send_text("updated your offer\n".into());
+    Ok(())
+}
+
+pub fn status(players: &mut Vec<Option<Player>>, player_id: usize) -> Result<()> {
+    let partner_id = offer(get(players, player_id)?)?.partner;
+    let (player, partner) = get_two_mut(player_id, partner_id, players)?;
+    let mine = offer(player)?;
+    let theirs = offer(partner)?;
+    let text = format!(
+        "trading with {}\nyou are offering: {}{}\nthey are offering: {}{}\n",
+        partner.name(),
+        mine.items.to_string(),
+        if mine.accepted { " (accepted)" } else { "" },
+        theirs.items.to_string(),
+        if theirs.accepted { " (accepted)" } else { "" },
+    );
+    player.send_text(text);
+    Ok(())
+}
+
+// once both sides have accepted, the items are swapped in one go
+pub fn accept(players: &mut Vec<Option<Player>>, player_id: usize) -> Result<()> {
+    let partner_id = offer(get(players, player_id)?)?.partner;
+    let (player, partner) = get_two_mut(player_id, partner_id, players)?;
+    offer_mut(player)?.accepted = true;
+    if !offer(partner)?.accepted {
+        player.send_text(format!("waiting for {} to accept\n", partner.name()));
+        partner.send_text(format!(
+            "{} accepted the trade, 'trade accept' to finish it\n",
+            player.name()
+        ));
+        return Ok(());
+    }
+
+    let mine = player.trade.take().expect("checked above");
+    let theirs = partner.trade.take().expect("checked above");
+    // items can be used up while trading, so check again right before the swap
+    if !player.inventory().contains_inventory(&mine.items)
+        || !partner.inventory().contains_inventory(&theirs.items)
+    {
+        let text = "the trade was cancelled, someone no longer has what they offered\n";
+        player.send_text(text.into());
+        partner.send_text(text.into());
+        return Ok(());
+    }
+    player.inventory_mut().remove_inventory(&mine.items)?;
+    partner.inventory_mut().remove_inventory(&theirs.items)?;
+    player.inventory_mut().add_inventory(&theirs.items);
+    partner.inventory_mut().add_inventory(&mine.items);
+
+    player.send_text(format!("you got: {}\n", theirs.items.to_string()));
+    partner.send_text(format!("you got: {}\n", mine.items.to_string()));
+    Ok(())
+}
+
+pub fn cancel(players: &mut Vec<Option<Player>>, player_id: usize) -> Result<()> {
+    let player = get_mut(players, player_id)?;
+    let partner_id = player
+        .trade
+        .take()
+        .ok_or(anyhow!("you aren't trading with anyone"))?
+        .partner;
+    player.send_text("cancelled the trade\n".into());
+    let name = player.name();
+    if let Ok(partner) = get_mut(players, partner_id) {
+        if partner.trade.as_ref().map(|t| t.partner) == Some(player_id) {
+            partner.trade = None;
+            partner.send_text(format!("{} cancelled the trade\n", name));
+        }
+    }
+    Ok(())
+}
+
+// cancels trades where someone moved, started fighting or left the game
+pub fn cancel_broken_trades(players: &mut Vec<Option<Player>>, battle_map: &BattleMap) {
+    for i in 0..players.len() {
+        let player = match &players[i] {
+            Some(player) => player,
+            None => continue,
+        };
+        let offer = match &player.trade {
+            Some(offer) => offer,
+            None => continue,
+        };
+        let partner = players[offer.partner].as_ref();
+        let partner_trade = partner.and_then(|partner| partner.trade.as_ref());
+        // disconnected players are kept around in case they come back, but they can't trade
+        let partner_gone = partner.is_none_or(|partner| partner.disconnected.is_some());
+        let reason = if partner_trade.map(|t| t.partner) != Some(i) || partner_gone {
+            "your trading partner left"
+        } else if *player.loc() != offer.loc {
+            "you moved"
+        } else if battle_map.in_battle(ID::player(i)) {
+            "you're in a battle"
+        } else {
+            continue;
+        };
+
+        let player = players[i].as_mut().expect("checked above");
+        let partner_id = player.trade.take().expect("checked above").partner;
+        player.send_text(format!("the trade was cancelled, {}\n", reason));
+        let name = player.name();
+        if let Some(partner) = players[partner_id].as_mut() {
+            if partner.trade.as_ref().map(|t| t.partner) == Some(i) {
+                partner.trade = None;
+                partner.send_text(format!("the trade with {} was cancelled\n", name));
+            }
+        }
+    }
+}
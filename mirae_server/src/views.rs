@@ -0,0 +1,86 @@
+// keeps the map players are looking at up to date without them asking.
+// once a player has used 'disp', any change to a square in their view
+// (a block, a mob or a player moving) sends them a fresh display
+use crate::{
+    actions::VIEW_DIST, display::Bounds, entity::Entity, gamedata::gamedata::GameData,
+    player::Player, players_op, sight, vector3::Vector3, world::World, world_op,
+};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+pub struct ViewTracker {
+    // where each player was (and what number they had) when views were last updated
+    players: HashMap<usize, (u32, Vector3)>,
+}
+
+impl ViewTracker {
+    pub fn new() -> Self {
+        Self {
+            players: HashMap::new(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        world_arc: Arc<RwLock<World>>,
+        players_arc: Arc<RwLock<Vec<Option<Player>>>>,
+        g: &GameData,
+    ) -> Result<()> {
+        let mut world = world_arc.write().map_err(world_op)?;
+        let mut players = players_arc.write().map_err(players_op)?;
+        let mut changes = world.take_changes();
+
+        // players that moved, joined or left change both the square they left and the one they're on
+        let mut seen = HashMap::new();
+        for (i, player) in players.iter().enumerate() {
+            if let Some(player) = player {
+                let now = (player.number, *player.loc());
+                match self.players.get(&i) {
+                    Some(before) if *before == now => {}
+                    Some((_, before)) => {
+                        changes.push(*before);
+                        changes.push(now.1);
+                    }
+                    None => changes.push(now.1),
+                }
+                seen.insert(i, now);
+            }
+        }
+        for (i, (_, before)) in self.players.iter() {
+            if !seen.contains_key(i) {
+                changes.push(*before);
+            }
+        }
+        self.players = seen;
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let dim = world.blocks().dim;
+        for i in 0..players.len() {
+            let (old, loc) = match &players[i] {
+                Some(player) if player.disconnected.is_none() => match player.view {
+                    Some(view) => (view, *player.loc()),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            let new = Bounds::get_bounds_centered(loc, VIEW_DIST, dim);
+            if !changes
+                .iter()
+                .any(|posn| old.in_bounds(*posn) || new.in_bounds(*posn))
+            {
+                continue;
+            }
+            let img = sight::look(&world, &mut players, g, i, &new, 1)?;
+            if let Some(player) = &mut players[i] {
+                player.view = Some(new);
+                player.send_display(img, false);
+            }
+        }
+        Ok(())
+    }
+}
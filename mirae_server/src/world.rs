@@ -1,26 +1,27 @@
 use crate::{
+    combat::BattleMap,
     entity::Entity,
     gamedata::{
         block::{Block, PointLight},
         gamedata::{GameData, StructureName},
-        mobtemplate::MobTemplate,
+        mobtemplate::{MobTemplate, Movement},
         terrain::Biome,
     },
-    mob::Mob,
+    map::Map,
+    mob::{Mob, MobSave},
     noise,
     rgb::RGB,
+    save::Load,
     vector3::Vector3,
-    Load,
 };
 use anyhow::{anyhow, Result};
 use bimap::BiMap;
-use rand::{prelude::StdRng, Rng, SeedableRng};
-use std::collections::{HashMap, HashSet, VecDeque};
-
-pub struct Map<T> {
-    pub dim: Vector3,
-    map: Vec<T>,
-}
+use rand::{prelude::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Instant,
+};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct MobU16(pub u16);
@@ -45,68 +46,6 @@ impl From<usize> for MobU16 {
     }
 }
 
-impl<T> Map<T>
-where
-    T: Clone + Eq,
-{
-    pub fn new(dim: Vector3, default: T) -> Self {
-        Map {
-            dim,
-            map: vec![default; dim.dim() as usize],
-        }
-    }
-
-    pub fn from_vec(dim: Vector3, map: Vec<T>) -> Result<Self> {
-        if dim.dim() as usize == map.len() {
-            Ok(Self { dim, map })
-        } else {
-            Err(anyhow!("bad dimensions"))
-        }
-    }
-
-    pub fn index(&self, loc: Vector3) -> Result<usize> {
-        if loc.x() >= self.dim.x()
-            || loc.x() < 0
-            || loc.y() >= self.dim.y()
-            || loc.y() < 0
-            || loc.z() >= self.dim.z()
-            || loc.z() < 0
-        {
-            Err(anyhow!("point {:?} not in map of dim {:?}", loc, self.dim))
-        } else {
-            Ok(
-                loc.z() as usize * self.dim.x() as usize * self.dim.y() as usize
-                    + loc.y() as usize * self.dim.x() as usize
-                    + loc.x() as usize,
-            )
-        }
-    }
-
-    pub fn index_to_posn(&self, i: usize) -> Vector3 {
-        Vector3::new(
-            (i % self.dim.x() as usize) as isize,
-            ((i / (self.dim.x() as usize)) % self.dim.y() as usize) as isize,
-            (i / (self.dim.x() as usize * self.dim.y() as usize)) as isize,
-        )
-    }
-
-    pub fn direct_set(&mut self, index: usize, val: T) {
-        self.map[index] = val;
-    }
-
-    pub fn direct_get(&self, index: usize) -> T {
-        self.map[index].clone()
-    }
-
-    pub fn set(&mut self, loc: Vector3, val: T) -> Result<()> {
-        Ok(self.direct_set(self.index(loc)?, val))
-    }
-
-    pub fn get(&self, loc: Vector3) -> Result<T> {
-        Ok(self.direct_get(self.index(loc)?))
-    }
-}
-
 struct SpawnedMobs {
     locs: BiMap<Vector3, usize>,
     mobs: HashMap<usize, Mob>,
@@ -138,6 +77,10 @@ impl SpawnedMobs {
         self.mobs.get_mut(&id)
     }
 
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&usize, &mut Mob)> {
+        self.mobs.iter_mut()
+    }
+
     pub fn get_posn(&self, mob: &Mob) -> Option<Vector3> {
         Some(self.locs.get_by_right(&mob.id().id)?.clone())
     }
@@ -158,6 +101,9 @@ impl SpawnedMobs {
     }
 }
 
+// how many random mobs we try on a block before giving up on it for this respawn pass
+const MAX_RESPAWN_ATTEMPTS: usize = 100;
+
 fn get_rand(seed: u64) -> StdRng {
     SeedableRng::seed_from_u64(seed)
 }
@@ -286,12 +232,138 @@ fn get_block<'a>(block_map: &Map<u8>, g: &'a GameData, i: usize) -> Result<&'a B
         .ok_or(anyhow!("block doesn't exist!"))
 }
 
+// the squares from (x0, y0) up to but not including (x1, y1) on every layer.
+// light only goes straight down between layers, so lighting is worked out for whole columns
+#[derive(Clone, Copy)]
+struct Columns {
+    x0: isize,
+    y0: isize,
+    x1: isize,
+    y1: isize,
+}
+
+impl Columns {
+    fn all(dim: Vector3) -> Self {
+        Self {
+            x0: 0,
+            y0: 0,
+            x1: dim.x(),
+            y1: dim.y(),
+        }
+    }
+
+    // grown by some number of squares on every side, without going off the map
+    fn grow(&self, by: isize, dim: Vector3) -> Self {
+        Self {
+            x0: (self.x0 - by).max(0),
+            y0: (self.y0 - by).max(0),
+            x1: (self.x1 + by).min(dim.x()),
+            y1: (self.y1 + by).min(dim.y()),
+        }
+    }
+
+    fn contains(&self, loc: Vector3) -> bool {
+        loc.x() >= self.x0 && loc.x() < self.x1 && loc.y() >= self.y0 && loc.y() < self.y1
+    }
+
+    fn posns(&self, dim: Vector3) -> Vec<Vector3> {
+        let mut posns = Vec::new();
+        for z in 0..dim.z() {
+            for y in self.y0..self.y1 {
+                for x in self.x0..self.x1 {
+                    posns.push(Vector3::new(x, y, z));
+                }
+            }
+        }
+        posns
+    }
+}
+
+// the furthest any block's point light reaches
+fn max_light_range(g: &GameData) -> isize {
+    g.blocks
+        .name_to_item
+        .values()
+        .filter_map(|block| block.light.point_light.as_ref())
+        .map(|lighting| lighting.max_range as isize)
+        .max()
+        .unwrap_or(0)
+}
+
+fn sunlight(block_map: &Map<u8>, g: &GameData, loc: Vector3) -> Result<RGB> {
+    if get_block_by_loc(block_map, g, loc)?.unlit {
+        return Ok(RGB::white());
+    }
+    let mut light = RGB::new(200, 200, 200);
+    for z in 0..loc.z() {
+        let loc = Vector3::new(loc.x(), loc.y(), z);
+        let block = get_block_by_loc(block_map, g, loc)?;
+        light = light.mul(block.transparency);
+    }
+    Ok(light)
+}
+
+// works out the light of every square in area from scratch.
+// light from emitters outside of area that reaches into it is counted too
+fn light_columns(
+    light_map: &mut Map<RGB>,
+    block_map: &Map<u8>,
+    g: &GameData,
+    area: &Columns,
+) -> Result<()> {
+    let dim = block_map.dim;
+    for loc in area.posns(dim) {
+        light_map.set(loc, sunlight(block_map, g, loc)?)?;
+    }
+
+    // light emitters
+    let sources = area.grow(max_light_range(g), dim);
+    for loc in sources.posns(dim) {
+        let block = get_block_by_loc(block_map, g, loc)?;
+        // expand each point light individually
+        if let Some(lighting) = &block.light.point_light {
+            expand_point_light(light_map, block_map, lighting, g, loc, area)?;
+        }
+        // expand down lights (assuming there's a block below this one)
+        if let Some(lighting) = &block.light.down_light {
+            let loc = loc + Vector3::new(0, 0, 1);
+            if !area.contains(loc) {
+                continue;
+            }
+            if let Ok(below) = get_block_by_loc(block_map, g, loc) {
+                if !below.unlit && !below.solid {
+                    let color = light_map.get(loc)?;
+                    light_map.set(loc, color.add(lighting.color.scale(lighting.intensity)))?;
+                }
+            }
+        }
+    }
+
+    let min_light = 30;
+    for loc in area.posns(dim) {
+        let mut light = light_map.get(loc)?;
+        if light.r < min_light {
+            light.r = min_light;
+        }
+        if light.g < min_light {
+            light.g = min_light;
+        }
+        if light.b < min_light {
+            light.b = min_light;
+        }
+        light_map.set(loc, light)?;
+    }
+    Ok(())
+}
+
+// only squares in area are lit, the rest of the flood fill just finds the way there
 fn expand_point_light(
     light_map: &mut Map<RGB>,
     block_map: &Map<u8>,
     lighting: &PointLight,
     g: &GameData,
     loc: Vector3,
+    area: &Columns,
 ) -> Result<()> {
     let mut visited = HashSet::new();
     let mut to_eval = VecDeque::new();
@@ -302,7 +374,7 @@ fn expand_point_light(
         let block = get_block_by_loc(block_map, g, curr)?;
         if block.unlit && !first {
             continue;
-        } else if !block.unlit {
+        } else if !block.unlit && area.contains(curr) {
             let intensity = (lighting.intensity - (depth as f64) * lighting.falloff).max(0.0);
             let color = light_map.get(curr)?;
             light_map.set(curr, color.add(lighting.color.scale(intensity)))?;
@@ -334,6 +406,42 @@ fn expand_point_light(
     Ok(())
 }
 
+// picks a random mob and checks it against the spawn rules of the block,
+// returns None if the mob isn't allowed to spawn there
+fn pick_mob(block: &Block, g: &GameData, rng: &mut StdRng) -> Result<Option<MobU16>> {
+    let max = g.mob_templates.max_id.as_u16().unwrap_or(0);
+    let mob_id = MobU16(rng.gen_range(0, max));
+    let mob_name = g.get_mob_name_by_id(mob_id)?;
+    let mob = &g.mob_templates.name_to_item[&mob_name];
+    if mob.dont_spawn {
+        return Ok(None);
+    }
+
+    if !block.mob_spawn.allows(&mob.tags) {
+        return Ok(None);
+    }
+
+    if block.mob_spawn.favor.len() > 0 {
+        let mut favored = false;
+        for tag0 in &block.mob_spawn.favor {
+            for tag1 in &mob.tags {
+                if tag0 == tag1 {
+                    favored = true;
+                    break;
+                }
+            }
+            if favored {
+                break;
+            }
+        }
+
+        if !favored && rng.gen_range(0.0, 1.0) < block.mob_spawn.favor_prob {
+            return Ok(None);
+        }
+    }
+    Ok(Some(mob_id))
+}
+
 fn gen_noise(rng: &mut StdRng, g: &GameData, biome: bool) -> Vec<f64> {
     noise::generate_perlin_noise(
         g.terrain.dim.x() as usize,
@@ -347,28 +455,101 @@ fn gen_noise(rng: &mut StdRng, g: &GameData, biome: bool) -> Vec<f64> {
     )
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MobHome {
+    loc: Vector3,
+    home: Vector3,
+}
+
+// everything about a running world that isn't stored in the maps
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorldState {
+    id: usize,
+    rng_seed: u64,
+    mobs: Vec<MobSave>,
+    mob_homes: Vec<MobHome>,
+}
+
 pub struct World {
     spawned_mobs: SpawnedMobs,
+    // maps the current location of every mob that has moved to where it started
+    mob_homes: HashMap<Vector3, Vector3>,
     mob_map: Map<MobU16>,
     block_map: Map<u8>,
     light_map: Map<RGB>,
+    last_respawn: Instant,
     pub seed: u64,
     id: usize,
     pub rng: StdRng,
+    // squares whose block or mob changed since views were last updated
+    changed: Vec<Vector3>,
 }
 
 impl World {
-    pub fn from_load(load: Load) -> Result<World> {
+    pub fn from_load(load: Load, g: &GameData) -> Result<World> {
         let rng = get_rand(load.seed);
-        Ok(World {
+        let mut world = World {
             spawned_mobs: SpawnedMobs::new(),
-            mob_map: Map::from_vec(load.dim, load.mobs.into_iter().map(|a| MobU16(a)).collect())?,
-            block_map: Map::from_vec(load.dim, load.blocks)?,
-            light_map: Map::from_vec(load.dim, load.colors)?,
+            mob_homes: HashMap::new(),
+            mob_map: load.mobs,
+            block_map: load.blocks,
+            light_map: load.colors,
+            last_respawn: Instant::now(),
             seed: load.seed,
             rng,
             id: 0,
-        })
+            changed: Vec::new(),
+        };
+        if let Some(state) = load.state {
+            world.restore_state(state, g)?;
+        }
+        Ok(world)
+    }
+
+    // reseeds the world's rng (and the rng of every spawned mob)
+    // so that the saved seeds continue the same random sequences after a restart
+    pub fn save_state(&mut self) -> WorldState {
+        let rng_seed = self.rng.gen();
+        self.rng = get_rand(rng_seed);
+        WorldState {
+            id: self.id,
+            rng_seed,
+            mobs: self
+                .spawned_mobs
+                .mobs
+                .values_mut()
+                .map(|mob| mob.save())
+                .collect(),
+            mob_homes: self
+                .mob_homes
+                .iter()
+                .map(|(loc, home)| MobHome {
+                    loc: *loc,
+                    home: *home,
+                })
+                .collect(),
+        }
+    }
+
+    fn restore_state(&mut self, state: WorldState, g: &GameData) -> Result<()> {
+        self.id = state.id;
+        self.rng = get_rand(state.rng_seed);
+        for mob in state.mobs {
+            let mob = Mob::from_save(mob, g)?;
+            let loc = *mob.loc();
+            if self.mob_map.get(loc)? != g.get_mob_id_by_name(mob.template_name())? {
+                return Err(anyhow!(format!(
+                    "saved mob {:?} doesn't match the mob map at {:?}",
+                    mob.template_name(),
+                    loc
+                )));
+            }
+            self.spawned_mobs.insert(loc, mob);
+        }
+        for mob_home in state.mob_homes {
+            self.mob_homes.insert(mob_home.loc, mob_home.home);
+        }
+        Ok(())
     }
 
     pub fn from_seed(seed: u64, g: &GameData) -> Result<World> {
@@ -429,67 +610,11 @@ impl World {
             if rng.gen::<f64>() < block.mob_spawn.spawn_chance
                 && mob_map.direct_get(i) == MobU16::empty()
             {
-                let mut f = || -> Result<bool> {
-                    let max = g.mob_templates.max_id.as_u16().unwrap_or(0);
-                    let mob_id = MobU16(rng.gen_range(0, max));
-                    let mob_name = g.get_mob_name_by_id(mob_id)?;
-                    let mob = &g.mob_templates.name_to_item[&mob_name];
-                    if mob.dont_spawn {
-                        return Ok(false);
-                    }
-
-                    for tag0 in &block.mob_spawn.exclude {
-                        for tag1 in &mob.tags {
-                            if tag0 == tag1 {
-                                return Ok(false);
-                            }
-                        }
-                    }
-
-                    if block.mob_spawn.require.len() > 0 {
-                        let mut has_require = false;
-                        for tag0 in &block.mob_spawn.require {
-                            for tag1 in &mob.tags {
-                                if tag0 == tag1 {
-                                    has_require = true;
-                                    break;
-                                }
-                            }
-                            if has_require {
-                                break;
-                            }
-                        }
-    
-                        if !has_require {
-                            return Ok(false);
-                        }
-                    }
-
-                    if block.mob_spawn.favor.len() > 0 {
-                        let mut favored = false;
-                        for tag0 in &block.mob_spawn.favor {
-                            for tag1 in &mob.tags {
-                                if tag0 == tag1 {
-                                    favored = true;
-                                    break;
-                                }
-                            }
-                            if favored {
-                                break;
-                            }
-                        }
-                        
-                        if !favored && rng.gen_range(0.0, 1.0) < block.mob_spawn.favor_prob {
-                            return Ok(false);
-                        }
-                    }
-                    mob_map.direct_set(i, mob_id);
-                    Ok(true)
-                };
-                let mut pass = false;
-                while !pass {
-                    pass = f()?;
+                let mut mob_id = None;
+                while mob_id.is_none() {
+                    mob_id = pick_mob(block, g, &mut rng)?;
                 }
+                mob_map.direct_set(i, mob_id.expect("we just picked a mob"));
             }
         }
 
@@ -497,72 +622,19 @@ impl World {
 
         // calculate lighting
         let mut light_map = Map::new(g.terrain.dim, RGB::new(0, 0, 0));
-
-        // sunlight
-        for i in 0..(light_map.dim.dim() as usize) {
-            let block = get_block(&block_map, g, i)?;
-            if block.unlit {
-                light_map.direct_set(i, RGB::white());
-                continue;
-            }
-            let mut light = RGB::new(200, 200, 200);
-            let loc = block_map.index_to_posn(i);
-            for z in 0..loc.z() {
-                let loc = Vector3::new(loc.x(), loc.y(), z);
-                let block = get_block_by_loc(&block_map, g, loc)?;
-                light = light.mul(block.transparency);
-            }
-            light_map.direct_set(i, light);
-        }
-
-        // light emitters
-        for i in 0..(light_map.dim.dim() as usize) {
-            let block = get_block(&block_map, g, i)?;
-            // expand each point light individually
-            if let Some(lighting) = &block.light.point_light {
-                expand_point_light(
-                    &mut light_map,
-                    &block_map,
-                    lighting,
-                    g,
-                    block_map.index_to_posn(i),
-                )?;
-            }
-            // expand down lights (assuming there's a block below this one)
-            if let Some(lighting) = &block.light.down_light {
-                let loc = block_map.index_to_posn(i) + Vector3::new(0, 0, 1);
-                if let Ok(below) = get_block_by_loc(&block_map, g, loc) {
-                    if !below.unlit && !below.solid {
-                        let color = light_map.get(loc)?;
-                        light_map.set(loc, color.add(lighting.color.scale(lighting.intensity)))?;
-                    }
-                }
-            }
-        }
-
-        let min_light = 30;
-        for i in 0..(light_map.dim.dim() as usize) {
-            let mut light = light_map.direct_get(i);
-            if light.r < min_light {
-                light.r = min_light;
-            }
-            if light.g < min_light {
-                light.g = min_light;
-            }
-            if light.b < min_light {
-                light.b = min_light;
-            }
-            light_map.direct_set(i, light);
-        }
+        light_columns(&mut light_map, &block_map, g, &Columns::all(g.terrain.dim))?;
 
         Ok(World {
             spawned_mobs: SpawnedMobs::new(),
+            mob_homes: HashMap::new(),
             mob_map,
             block_map,
             light_map,
+            last_respawn: Instant::now(),
             seed,
             rng,
             id: 0,
+            changed: Vec::new(),
         })
     }
 
@@ -629,9 +701,38 @@ impl World {
         }
     }
 
+    // several mobs at once, for battles with more than one mob in them
+    pub fn get_mobs_mut(&mut self, ids: &Vec<usize>) -> Result<Vec<&mut Mob>> {
+        let mobs: Vec<&mut Mob> = self
+            .spawned_mobs
+            .iter_mut()
+            .filter(|(id, _)| ids.contains(id))
+            .map(|(_, mob)| mob)
+            .collect();
+        if mobs.len() != ids.len() {
+            return Err(anyhow!(format!("no mobs with ids {:?}", ids)));
+        }
+        Ok(mobs)
+    }
+
+    // the mob is spawned in properly the first time someone runs into it
+    pub fn place_mob(&mut self, loc: Vector3, mob: MobU16, g: &GameData) -> Result<()> {
+        if self.has_mob(loc)? {
+            return Err(anyhow!(format!("there's already a mob at {:?}", loc)));
+        }
+        if self.get_block_at(g, loc)?.solid {
+            return Err(anyhow!(format!("{:?} is inside a solid block", loc)));
+        }
+        self.mob_map.set(loc, mob)?;
+        self.changed.push(loc);
+        Ok(())
+    }
+
     pub fn delete_mob_by_loc(&mut self, loc: Vector3) -> Result<()> {
         self.mob_map.set(loc, MobU16::empty())?;
+        self.changed.push(loc);
         self.spawned_mobs.remove_loc(loc);
+        self.mob_homes.remove(&loc);
         Ok(())
     }
 
@@ -647,12 +748,195 @@ impl World {
         if let Some(_) = self.mob_map.get(end)?.as_u16() {
             return Err(anyhow!(format!("there's already a mob at {:?}", end)));
         }
-        if let Some(mob) = self.spawned_mobs.remove_loc(start) {
+        if let Some(mut mob) = self.spawned_mobs.remove_loc(start) {
+            mob.loc_mut().set(end);
             self.spawned_mobs.insert(end, mob);
         }
+        let home = self.mob_homes.remove(&start).unwrap_or(start);
+        self.mob_homes.insert(end, home);
         let val = self.mob_map.get(start)?;
         self.mob_map.set(start, MobU16::empty())?;
         self.mob_map.set(end, val)?;
+        self.changed.push(start);
+        self.changed.push(end);
+        Ok(())
+    }
+
+    fn can_move_to(&self, loc: Vector3, g: &GameData) -> Result<bool> {
+        if self.block_map.get(loc).is_err() {
+            return Ok(false);
+        }
+        Ok(!self.get_block_at(g, loc)?.solid && !self.has_mob(loc)?)
+    }
+
+    fn chase_step(
+        &self,
+        loc: Vector3,
+        movement: &Movement,
+        player_locs: &Vec<Vector3>,
+    ) -> Option<Vector3> {
+        if movement.chase_dist == 0 {
+            return None;
+        }
+        let max_dist_sqr = (movement.chase_dist * movement.chase_dist) as f64;
+        let mut closest: Option<Vector3> = None;
+        for player_loc in player_locs {
+            let diff = *player_loc - loc;
+            if diff.z() != 0 || diff.sqr_mag() > max_dist_sqr {
+                continue;
+            }
+            if closest.map_or(true, |c| diff.sqr_mag() < c.sqr_mag()) {
+                closest = Some(diff);
+            }
+        }
+        let diff = closest?;
+        Some(loc + Vector3::new(diff.x().signum(), diff.y().signum(), 0))
+    }
+
+    fn wander_step(
+        &mut self,
+        loc: Vector3,
+        movement: &Movement,
+        player_locs: &Vec<Vector3>,
+        g: &GameData,
+    ) -> Result<Option<Vector3>> {
+        let neighbors = [
+            Vector3::new(-1, 0, 0),
+            Vector3::new(1, 0, 0),
+            Vector3::new(0, -1, 0),
+            Vector3::new(0, 1, 0),
+            Vector3::new(-1, -1, 0),
+            Vector3::new(1, 1, 0),
+            Vector3::new(-1, 1, 0),
+            Vector3::new(1, -1, 0),
+        ];
+        let end = loc + neighbors[self.rng.gen_range(0, neighbors.len())];
+        let home = *self.mob_homes.get(&loc).unwrap_or(&loc);
+        let radius_sqr = (movement.wander_radius * movement.wander_radius) as f64;
+        // a mob that was pulled away from home by chasing is always allowed to walk back
+        let in_range = (end - home).sqr_mag() <= radius_sqr
+            || (end - home).sqr_mag() < (loc - home).sqr_mag();
+        if in_range && !player_locs.contains(&end) && self.can_move_to(end, g)? {
+            Ok(Some(end))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // moves every mob that isn't fighting according to the movement profile of its template
+    pub fn move_mobs(
+        &mut self,
+        player_locs: &Vec<Vector3>,
+        battle_map: &BattleMap,
+        g: &GameData,
+    ) -> Result<()> {
+        // find all the mobs first, so that mobs moving forward in the map don't move twice
+        let mut locs = Vec::new();
+        for i in 0..(self.mob_map.dim.dim() as usize) {
+            if self.mob_map.direct_get(i).as_u16().is_some() {
+                locs.push(self.mob_map.index_to_posn(i));
+            }
+        }
+
+        for loc in locs {
+            let movement = &self.get_mobtemplate_at(loc, g)?.movement;
+            if movement.is_idle() || player_locs.contains(&loc) {
+                continue;
+            }
+            if let Some(mob) = self.spawned_mobs.get_at(loc) {
+                if battle_map.in_battle(mob.id()) {
+                    continue;
+                }
+            }
+            if self.rng.gen::<f64>() >= movement.move_chance {
+                continue;
+            }
+
+            let end = match self.chase_step(loc, movement, player_locs) {
+                Some(end) => {
+                    if self.can_move_to(end, g)? {
+                        Some(end)
+                    } else {
+                        None
+                    }
+                }
+                None if movement.wander_radius > 0 => {
+                    self.wander_step(loc, movement, player_locs, g)?
+                }
+                None => None,
+            };
+            if let Some(end) = end {
+                self.move_mob(loc, end)?;
+            }
+        }
+        Ok(())
+    }
+
+    // refills blocks that have fewer mobs on them than their spawn chance would give,
+    // at most once every respawn delay
+    pub fn respawn_mobs(&mut self, player_locs: &Vec<Vector3>, g: &GameData) -> Result<()> {
+        let respawn = &g.terrain.mob_respawn;
+        if respawn.per_pass == 0 || self.last_respawn.elapsed().as_secs() < respawn.delay {
+            return Ok(());
+        }
+        self.last_respawn = Instant::now();
+
+        let min_dist_sqr = (respawn.min_player_dist * respawn.min_player_dist) as f64;
+        let mut num_blocks: HashMap<u8, u64> = HashMap::new();
+        let mut population: HashMap<u8, u64> = HashMap::new();
+        let mut candidates: HashMap<u8, Vec<usize>> = HashMap::new();
+        let mut total = 0;
+        for i in 0..(self.mob_map.dim.dim() as usize) {
+            let block_id = self.block_map.direct_get(i);
+            *num_blocks.entry(block_id).or_insert(0) += 1;
+            if self.mob_map.direct_get(i).as_u16().is_some() {
+                *population.entry(block_id).or_insert(0) += 1;
+                total += 1;
+                continue;
+            }
+            let loc = self.mob_map.index_to_posn(i);
+            if player_locs
+                .iter()
+                .all(|player_loc| (*player_loc - loc).sqr_mag() >= min_dist_sqr)
+            {
+                candidates.entry(block_id).or_insert_with(Vec::new).push(i);
+            }
+        }
+
+        let mut block_ids: Vec<u8> = candidates.keys().cloned().collect();
+        block_ids.sort();
+        block_ids.shuffle(&mut self.rng);
+
+        let mut spawned = 0;
+        for block_id in block_ids {
+            let block = self.get_block(g, candidates[&block_id][0])?;
+            let target =
+                (num_blocks[&block_id] as f64 * block.mob_spawn.spawn_chance).round() as u64;
+            let mut deficit = target.saturating_sub(*population.get(&block_id).unwrap_or(&0));
+            let cells = candidates
+                .get_mut(&block_id)
+                .expect("block id came from candidates");
+            while deficit > 0
+                && cells.len() > 0
+                && spawned < respawn.per_pass
+                && total + spawned < respawn.cap
+            {
+                let i = cells.swap_remove(self.rng.gen_range(0, cells.len()));
+                for _ in 0..MAX_RESPAWN_ATTEMPTS {
+                    if let Some(mob_id) = pick_mob(block, g, &mut self.rng)? {
+                        self.mob_map.direct_set(i, mob_id);
+                        self.changed.push(self.mob_map.index_to_posn(i));
+                        spawned += 1;
+                        deficit -= 1;
+                        break;
+                    }
+                }
+            }
+        }
+
+        if spawned > 0 {
+            println!("respawned {} mobs", spawned);
+        }
         Ok(())
     }
 
@@ -677,8 +961,48 @@ impl World {
         &self.block_map
     }
 
-    pub fn blocks_mut(&mut self) -> &mut Map<u8> {
-        &mut self.block_map
+    // relights everything the block could have changed the light of
+    pub fn set_block(&mut self, g: &GameData, loc: Vector3, block: u8) -> Result<()> {
+        self.block_map.set(loc, block)?;
+        self.changed.push(loc);
+
+        // lights within range of the block may now reach further or not as far,
+        // so anything within range of those lights can change
+        let dim = self.block_map.dim;
+        let area = Columns {
+            x0: loc.x(),
+            y0: loc.y(),
+            x1: loc.x() + 1,
+            y1: loc.y() + 1,
+        }
+        .grow(2 * max_light_range(g), dim);
+        let before = area
+            .posns(dim)
+            .into_iter()
+            .map(|posn| self.light_map.get(posn))
+            .collect::<Result<Vec<RGB>>>()?;
+        light_columns(&mut self.light_map, &self.block_map, g, &area)?;
+        for (posn, light) in area.posns(dim).into_iter().zip(before) {
+            if self.light_map.get(posn)? != light {
+                self.changed.push(posn);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spawned_mobs(&self) -> impl Iterator<Item = &Mob> {
+        self.spawned_mobs.mobs.values()
+    }
+
+    pub fn refresh_mobs(&mut self, g: &GameData) {
+        for mob in self.spawned_mobs.mobs.values_mut() {
+            mob.refresh(g);
+        }
+    }
+
+    // hands over what changed since the last call
+    pub fn take_changes(&mut self) -> Vec<Vector3> {
+        std::mem::take(&mut self.changed)
     }
 
     pub fn mobs(&self) -> &Map<MobU16> {
@@ -697,3 +1021,91 @@ impl World {
         self.get_block(g, self.blocks().index(loc)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_gamemode;
+
+    fn gamedata() -> Result<GameData> {
+        read_gamemode("pvp/gamemode.jacl")?.into_gamedata()
+    }
+
+    // a world where every square on a layer is the same block, lit from scratch
+    fn world(g: &GameData, size: isize, layers: &[&str]) -> Result<World> {
+        let dim = Vector3::new(size, size, layers.len() as isize);
+        let mut block_map = Map::new(dim, 0u8);
+        for loc in Columns::all(dim).posns(dim) {
+            block_map.set(loc, g.get_block_id_by_name(layers[loc.z() as usize])?)?;
+        }
+        let mut light_map = Map::new(dim, RGB::black());
+        light_columns(&mut light_map, &block_map, g, &Columns::all(dim))?;
+        Ok(World {
+            spawned_mobs: SpawnedMobs::new(),
+            mob_homes: HashMap::new(),
+            mob_map: Map::new(dim, MobU16::empty()),
+            block_map,
+            light_map,
+            last_respawn: Instant::now(),
+            seed: 0,
+            rng: get_rand(0),
+            id: 0,
+            changed: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn removing_a_light_relights_around_it() -> Result<()> {
+        let g = gamedata()?;
+        // a rock ceiling keeps the sun out, so the floor only gets min_light
+        let mut world = world(&g, 9, &["rock", "stone"])?;
+        let dark = RGB::new(30, 30, 30);
+        let center = Vector3::new(4, 4, 1);
+        let next_to = Vector3::new(5, 4, 1);
+        assert_eq!(world.colors().get(next_to)?, dark);
+
+        world.set_block(&g, center, g.get_block_id_by_name("lantern")?)?;
+        assert!(
+            world.colors().get(next_to)? > dark,
+            "the lantern lights its neighbours"
+        );
+
+        world.take_changes();
+        world.set_block(&g, center, g.get_block_id_by_name("stone")?)?;
+        let dim = world.blocks().dim;
+        for x in 0..dim.x() {
+            for y in 0..dim.y() {
+                let loc = Vector3::new(x, y, 1);
+                assert_eq!(world.colors().get(loc)?, dark, "{:?} is still lit", loc);
+            }
+        }
+        assert!(world.take_changes().contains(&next_to));
+        Ok(())
+    }
+
+    #[test]
+    fn removing_a_ceiling_lets_the_sun_in() -> Result<()> {
+        let g = gamedata()?;
+        let mut world = world(&g, 5, &["rock", "stone"])?;
+        let hole = Vector3::new(2, 2, 0);
+        let below = Vector3::new(2, 2, 1);
+        let dark = RGB::new(30, 30, 30);
+        assert_eq!(world.colors().get(below)?, dark);
+
+        world.set_block(&g, hole, g.get_block_id_by_name("empty")?)?;
+        let sun = sunlight(world.blocks(), &g, below)?;
+        assert!(world.colors().get(below)?.r >= sun.r);
+        assert!(world.colors().get(below)? > dark);
+        // the rest of the floor is still under the rock
+        assert_eq!(world.colors().get(Vector3::new(0, 0, 1))?, dark);
+
+        // and the light matches lighting the whole world from scratch
+        let dim = world.blocks().dim;
+        let mut fresh = Map::new(dim, RGB::black());
+        light_columns(&mut fresh, world.blocks(), &g, &Columns::all(dim))?;
+        for loc in Columns::all(dim).posns(dim) {
+            assert_eq!(world.colors().get(loc)?, fresh.get(loc)?);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
 #!/bin/bash
 (cd mirae_client && nohup python client.py 31415 9000 > client_log &)
-(cd mirae_server && cargo run --release 9000 $1 $2)
+(cd mirae_server && cargo run --release -- --port 9000 --admin-port 9001 --$1 $2)
 
@@ -2,5 +2,5 @@
 
 pkill python
 (cd mirae_client && nohup python client.py 8000 9000 127.0.0.1 > client_log &)
-(cd mirae_server && cargo run --release 9000 $1 $2)
+(cd mirae_server && cargo run --release -- --port 9000 --$1 $2)
 